@@ -0,0 +1,252 @@
+//! User-defined command aliases from the `[alias]` config table.
+//!
+//! ```toml
+//! [alias]
+//! ship-it = ["rebase", "!cargo fmt --check", "push --force"]
+//! wip = "save --all wip {*}"
+//! ```
+//!
+//! Each step is either a built-in `lk` command line or, when prefixed with
+//! `!`, a shell command. `lk` command lines are split into words like a shell
+//! would, so `save -m "wip msg"` passes one message. `{1}`, `{2}`, ... are
+//! replaced with the alias's arguments and `{*}` with all of them. Steps run
+//! in order and the alias stops at the first step that fails.
+
+use crate::config::{Config, Value};
+use crate::error::{Error, Result};
+use crate::git::shell_quote;
+
+/// Config table aliases are read from (`alias.<name>`).
+pub const ALIAS_PREFIX: &str = "alias.";
+
+/// One step of an alias.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Arguments for a built-in `lk` command, e.g. `["push", "--force"]`.
+    Lk(Vec<String>),
+    /// A command line for the shell.
+    Shell(String),
+}
+
+impl Step {
+    fn parse(step: &str) -> Result<Step> {
+        match step.trim().strip_prefix('!') {
+            Some(script) => Ok(Step::Shell(script.trim().to_string())),
+            None => split_words(step).map(Step::Lk),
+        }
+    }
+
+    /// Substitutes `{N}` and `{*}` with `args`. In shell steps the arguments
+    /// are quoted; in `lk` steps a bare `{*}` expands to one word per
+    /// argument.
+    pub fn interpolate(&self, args: &[String]) -> Result<Step> {
+        match self {
+            Step::Lk(words) => {
+                let mut expanded = Vec::new();
+                for word in words {
+                    if word == "{*}" {
+                        expanded.extend(args.iter().cloned());
+                    } else {
+                        expanded.push(substitute(word, args, |arg| arg.to_string())?);
+                    }
+                }
+                Ok(Step::Lk(expanded))
+            }
+            Step::Shell(script) => Ok(Step::Shell(substitute(script, args, shell_quote)?)),
+        }
+    }
+}
+
+/// A named sequence of steps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alias {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+/// Looks up the alias `name`. A single string is a one-step alias.
+pub fn find(config: &Config, name: &str) -> Result<Option<Alias>> {
+    let Some(setting) = config.get(&format!("{ALIAS_PREFIX}{name}")) else {
+        return Ok(None);
+    };
+    let steps = match &setting.value {
+        Value::Str(step) => vec![Step::parse(step)?],
+        Value::List(steps) => steps
+            .iter()
+            .map(|step| Step::parse(step))
+            .collect::<Result<_>>()?,
+    };
+    Ok(Some(Alias {
+        name: name.to_string(),
+        steps,
+    }))
+}
+
+/// Splits an `lk` step into words the way a POSIX shell would: quotes group
+/// words and a backslash escapes the next character, but nothing is
+/// expanded.
+fn split_words(step: &str) -> Result<Vec<String>> {
+    let unclosed =
+        |quote: char| Error::precondition(format!("Unclosed {quote} in alias step `{step}`."));
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = step.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(|| unclosed('\''))? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(|| unclosed('"'))? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or_else(|| unclosed('"'))? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Replaces `{N}` (1-based) and `{*}` placeholders in `text`, formatting each
+/// argument with `quote`.
+fn substitute(text: &str, args: &[String], quote: impl Fn(&str) -> String) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            out.push_str(&rest[start..]);
+            return Ok(out);
+        };
+        let placeholder = &after[..end];
+        if placeholder == "*" {
+            let quoted: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+            out.push_str(&quoted.join(" "));
+        } else if let Ok(index) = placeholder.parse::<usize>() {
+            let arg = index
+                .checked_sub(1)
+                .and_then(|i| args.get(i))
+                .ok_or_else(|| {
+                    Error::precondition(format!(
+                        "Missing argument {{{index}}} (got {} argument{}).",
+                        args.len(),
+                        if args.len() == 1 { "" } else { "s" }
+                    ))
+                })?;
+            out.push_str(&quote(arg));
+        } else {
+            out.push('{');
+            out.push_str(placeholder);
+            out.push('}');
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EXIT_PRECONDITION;
+    use test_case::test_case;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn bang_marks_shell_steps() {
+        assert_eq!(
+            Step::parse("!cargo fmt --check").unwrap(),
+            Step::Shell(String::from("cargo fmt --check"))
+        );
+        assert_eq!(
+            Step::parse("push --force").unwrap(),
+            Step::Lk(args(&["push", "--force"]))
+        );
+    }
+
+    #[test_case(r#"save -m "wip msg""#, &["save", "-m", "wip msg"] ; "double quotes")]
+    #[test_case("save -m 'it''s done'", &["save", "-m", "its done"] ; "adjacent single quotes")]
+    #[test_case(r#"save -m "say \"hi\"""#, &["save", "-m", r#"say "hi""#] ; "escaped quote")]
+    #[test_case(r"new two\ words", &["new", "two words"] ; "escaped space")]
+    #[test_case("  push   --force ", &["push", "--force"] ; "extra whitespace")]
+    fn lk_steps_split_like_a_shell(step: &str, expected: &[&str]) {
+        assert_eq!(Step::parse(step).unwrap(), Step::Lk(args(expected)));
+    }
+
+    #[test]
+    fn unclosed_quote_is_a_precondition_error() {
+        let err = Step::parse(r#"save -m "wip"#).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_PRECONDITION);
+    }
+
+    #[test]
+    fn find_reads_lists_and_single_steps() {
+        let mut config = Config::default();
+        config.apply_flag("alias.sync", Some("rebase"));
+        assert_eq!(
+            find(&config, "sync").unwrap().unwrap().steps,
+            vec![Step::Lk(args(&["rebase"]))]
+        );
+        assert!(find(&config, "ship-it").unwrap().is_none());
+    }
+
+    #[test]
+    fn lk_steps_expand_star_to_separate_words() {
+        let step = Step::parse("save --all {*}").unwrap();
+        assert_eq!(
+            step.interpolate(&args(&["fix", "the bug"])).unwrap(),
+            Step::Lk(args(&["save", "--all", "fix", "the bug"]))
+        );
+    }
+
+    #[test]
+    fn shell_steps_quote_arguments() {
+        let step = Step::parse("!echo {1} {*}").unwrap();
+        assert_eq!(
+            step.interpolate(&args(&["it's", "b"])).unwrap(),
+            Step::Shell(String::from(r"echo 'it'\''s' 'it'\''s' b"))
+        );
+    }
+
+    #[test]
+    fn missing_argument_is_a_precondition_error() {
+        let err = Step::parse("new {2}")
+            .unwrap()
+            .interpolate(&args(&["one"]))
+            .unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_PRECONDITION);
+    }
+
+    #[test]
+    fn unknown_placeholders_are_left_alone() {
+        let step = Step::parse("!git log --format={h}").unwrap();
+        assert_eq!(
+            step.interpolate(&[]).unwrap(),
+            Step::Shell(String::from("git log --format={h}"))
+        );
+    }
+}
@@ -0,0 +1,535 @@
+//! Branch names for `lk new` and `lk worktree add`.
+//!
+//! The words given on the command line are turned into a slug (`Fix: auth
+//! bug #123?` becomes `fix-auth-bug-123`) and rendered through the
+//! `branch.template` (see [`Template`]). The full name, prefix included, is
+//! checked with `git check-ref-format` and against the optional
+//! `branch.pattern` policy before anything is created.
+//!
+//! Branches matching a `branch.protected` glob are guarded against `lk
+//! commit`, `lk save` and `lk push` (see [`is_protected`]).
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use regex::Regex;
+
+use crate::config::{
+    Config, Value, BRANCH_MAX_LENGTH, BRANCH_PATTERN, BRANCH_PROTECTED, BRANCH_TEMPLATE,
+    BRANCH_TICKET_PATTERN,
+};
+use crate::error::{Error, Result};
+use crate::repo::Repo;
+
+/// Turns free text into something usable as (part of) a branch name:
+/// lowercased, with runs of anything but letters, digits, `.`, `_` and `/`
+/// replaced by a single `-`, and cut to `max_len` characters. `/` is kept
+/// so a slug can still have path segments.
+pub fn slugify(text: &str, max_len: usize) -> String {
+    let mapped: String = text
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            c if c.is_alphanumeric() => c,
+            '.' | '_' | '/' => c,
+            _ => '-',
+        })
+        .collect();
+
+    let segments: Vec<String> = mapped.split('/').filter_map(clean_segment).collect();
+    let slug = segments.join("/");
+    let truncated: String = slug.chars().take(max_len).collect();
+    truncated
+        .trim_end_matches(['-', '.', '/', '_'])
+        .trim_end_matches(".lock")
+        .to_string()
+}
+
+/// Collapses repeated `-` and `.` and trims them from the ends, dropping
+/// segments that end up empty.
+fn clean_segment(segment: &str) -> Option<String> {
+    let mut out = String::new();
+    for c in segment.chars() {
+        let repeated = matches!(c, '-' | '.') && out.ends_with(c);
+        if !repeated {
+            out.push(c);
+        }
+    }
+    let out = out
+        .trim_matches(['-', '.'])
+        .trim_end_matches(".lock")
+        .to_string();
+    (!out.is_empty()).then_some(out)
+}
+
+/// The `branch.max-length` setting.
+pub fn max_length(config: &Config) -> Result<usize> {
+    let value = config
+        .resolve_str(BRANCH_MAX_LENGTH, None)
+        .unwrap_or_default();
+    value.parse().map_err(|_| {
+        Error::parse(format!(
+            "Invalid {BRANCH_MAX_LENGTH} `{value}`: expected a number."
+        ))
+    })
+}
+
+/// Slugifies `text` with the configured maximum length. Fails when nothing
+/// usable is left, e.g. for `lk new '???'`.
+pub fn slug(config: &Config, text: &str) -> Result<String> {
+    let slug = slugify(text, max_length(config)?);
+    if slug.is_empty() {
+        return Err(Error::precondition(format!(
+            "`{text}` has no characters usable in a branch name."
+        )));
+    }
+    Ok(slug)
+}
+
+/// Checks `branch` with `git check-ref-format --branch` and, when
+/// `branch.pattern` is set, that the whole name matches it.
+pub fn validate(repo: &Repo, branch: &str) -> Result<()> {
+    repo.git()
+        .stdout(
+            "check branch name",
+            &["check-ref-format", "--branch", branch],
+        )
+        .map_err(|_| Error::precondition(format!("`{branch}` is not a valid branch name.")))?;
+
+    let Some(pattern) = repo.config().resolve_str(BRANCH_PATTERN, None) else {
+        return Ok(());
+    };
+    let policy = Regex::new(&pattern)
+        .map_err(|err| Error::parse(format!("Invalid {BRANCH_PATTERN} `{pattern}`: {err}")))?;
+    if !policy.is_match(branch) {
+        return Err(Error::precondition(format!(
+            "Branch `{branch}` does not match the {BRANCH_PATTERN} policy `{pattern}`."
+        )));
+    }
+    Ok(())
+}
+
+/// The `branch.protected` glob that matches `branch`, if any.
+pub fn is_protected(config: &Config, branch: &str) -> Option<String> {
+    let globs = match config.get(BRANCH_PROTECTED).map(|setting| &setting.value) {
+        Some(Value::Str(glob)) => vec![glob.clone()],
+        Some(Value::List(globs)) => globs.clone(),
+        None => Vec::new(),
+    };
+    globs.into_iter().find(|glob| glob_match(glob, branch))
+}
+
+/// Matches `text` against a glob where `*` is any run of characters
+/// (including `/`) and `?` is any one character.
+pub fn glob_match(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut g, mut t) = (0, 0);
+    // Where the last `*` was and how much text it has swallowed so far.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    g = star_g + 1;
+                    t = star_t + 1;
+                    star = Some((star_g, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+/// Format for a bare `{date}`.
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The `branch.template` setting, e.g. `users/{git_user}/{ticket}-{slug}`.
+///
+/// * `{slug}` is the slugified name, without the ticket if the template
+///   has a `{ticket}`.
+/// * `{ticket}` is the first word of the name that matches
+///   `branch.ticket-pattern` (e.g. `ABC-123`), as typed.
+/// * `{git_user}` (or `{user}`) is the local part of `user.email`, else the
+///   slugified `user.name`.
+/// * `{date}` is today's date; `{date:%Y%m}` takes a strftime format.
+#[derive(Debug, Clone)]
+pub struct Template {
+    template: String,
+    ticket: Regex,
+    ticket_pattern: String,
+    max_len: usize,
+}
+
+/// What a template is filled in with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fields {
+    pub slug: String,
+    pub ticket: Option<String>,
+    pub user: Option<String>,
+    pub now: DateTime<Local>,
+}
+
+/// A placeholder or literal text in a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part<'a> {
+    Text(&'a str),
+    Slug,
+    Ticket,
+    User,
+    Date(&'a str),
+}
+
+impl Template {
+    pub fn new(config: &Config) -> Result<Template> {
+        let template = config
+            .resolve_str(BRANCH_TEMPLATE, None)
+            .unwrap_or_else(|| String::from("{slug}"));
+        let ticket_pattern = config
+            .resolve_str(BRANCH_TICKET_PATTERN, None)
+            .unwrap_or_default();
+        let ticket = Regex::new(&format!("^(?:{ticket_pattern})$")).map_err(|err| {
+            Error::parse(format!(
+                "Invalid {BRANCH_TICKET_PATTERN} `{ticket_pattern}`: {err}"
+            ))
+        })?;
+        let template = Template {
+            template,
+            ticket,
+            ticket_pattern,
+            max_len: max_length(config)?,
+        };
+        template.parts()?;
+        Ok(template)
+    }
+
+    fn parts(&self) -> Result<Vec<Part<'_>>> {
+        let mut parts = Vec::new();
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(&rest[..start]));
+            }
+            let after = &rest[start + 1..];
+            let end = after.find('}').ok_or_else(|| {
+                Error::parse(format!(
+                    "Unclosed `{{` in {BRANCH_TEMPLATE} `{}`.",
+                    self.template
+                ))
+            })?;
+            let part = match &after[..end] {
+                "slug" => Part::Slug,
+                "ticket" => Part::Ticket,
+                "git_user" | "user" => Part::User,
+                "date" => Part::Date(DEFAULT_DATE_FORMAT),
+                other => match other.strip_prefix("date:") {
+                    Some(format) if !has_errors(format) => Part::Date(format),
+                    Some(format) => {
+                        return Err(Error::parse(format!(
+                            "Invalid date format `{format}` in {BRANCH_TEMPLATE}."
+                        )))
+                    }
+                    None => {
+                        return Err(Error::parse(format!(
+                            "Unknown placeholder `{{{other}}}` in {BRANCH_TEMPLATE} `{}`.",
+                            self.template
+                        )))
+                    }
+                },
+            };
+            parts.push(part);
+            rest = &after[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest));
+        }
+        Ok(parts)
+    }
+
+    fn uses(&self, wanted: fn(&Part) -> bool) -> bool {
+        self.parts().unwrap_or_default().iter().any(wanted)
+    }
+
+    /// Splits the name given on the command line into the ticket (only when
+    /// the template has a `{ticket}`) and the slug of the remaining words.
+    pub fn split_name(&self, name: &str) -> (Option<String>, String) {
+        let mut words: Vec<&str> = name.split_whitespace().collect();
+        let mut ticket = None;
+        if self.uses(|part| *part == Part::Ticket) {
+            if let Some(ix) = words.iter().position(|word| self.ticket.is_match(word)) {
+                ticket = Some(words.remove(ix).to_string());
+            }
+        }
+        (ticket, slugify(&words.join(" "), self.max_len))
+    }
+
+    /// Gathers everything the template needs for `name`, asking git for the
+    /// user only when the template uses it.
+    pub fn fields(&self, repo: &Repo, name: &str) -> Result<Fields> {
+        let (ticket, slug) = self.split_name(name);
+        let user = if self.uses(|part| *part == Part::User) {
+            Some(git_user(repo)?)
+        } else {
+            None
+        };
+        Ok(Fields {
+            slug,
+            ticket,
+            user,
+            now: Local::now(),
+        })
+    }
+
+    /// Fills in the template.
+    pub fn render(&self, fields: &Fields) -> Result<String> {
+        let mut out = String::new();
+        for part in self.parts()? {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Slug => out.push_str(&fields.slug),
+                Part::Ticket => out.push_str(fields.ticket.as_deref().ok_or_else(|| {
+                    Error::precondition(format!(
+                        "{BRANCH_TEMPLATE} `{}` needs a ticket matching `{}` in the name.",
+                        self.template, self.ticket_pattern
+                    ))
+                })?),
+                Part::User => out.push_str(fields.user.as_deref().unwrap_or_default()),
+                Part::Date(format) => out.push_str(&fields.now.format(format).to_string()),
+            }
+        }
+        if fields.slug.is_empty() && self.uses(|part| *part == Part::Slug) {
+            return Err(Error::precondition(
+                "The name has no characters usable in a branch name.",
+            ));
+        }
+        Ok(out)
+    }
+
+    /// The branch for `name`: the template filled in for it, after `prefix`.
+    pub fn branch(&self, repo: &Repo, prefix: Option<&str>, name: &str) -> Result<String> {
+        let rendered = self.render(&self.fields(repo, name)?)?;
+        Ok(format!("{}{rendered}", prefix.unwrap_or_default()))
+    }
+
+    /// A pattern matching the branches this template could have produced
+    /// for `name` on any day, with or without `prefix`. When the name
+    /// carries no ticket, any ticket matches.
+    pub fn matcher(&self, fields: &Fields, prefix: Option<&str>) -> Result<Regex> {
+        let mut pattern = String::from("^");
+        if let Some(prefix) = prefix.filter(|prefix| !prefix.is_empty()) {
+            pattern.push_str(&format!("(?:{})?", regex::escape(prefix)));
+        }
+        for part in self.parts()? {
+            match part {
+                Part::Text(text) => pattern.push_str(&regex::escape(text)),
+                Part::Slug => pattern.push_str(&regex::escape(&fields.slug)),
+                Part::Ticket => match &fields.ticket {
+                    Some(ticket) => pattern.push_str(&regex::escape(ticket)),
+                    None => pattern.push_str(&format!("(?:{})", self.ticket_pattern)),
+                },
+                Part::User => match &fields.user {
+                    Some(user) => pattern.push_str(&regex::escape(user)),
+                    None => pattern.push_str("[^/]+"),
+                },
+                Part::Date(_) => pattern.push_str("[^/]+?"),
+            }
+        }
+        pattern.push('$');
+        Regex::new(&pattern).map_err(|err| Error::parse(format!("{err}")))
+    }
+}
+
+/// True when `format` has specifiers chrono can't render.
+fn has_errors(format: &str) -> bool {
+    StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+}
+
+/// `{git_user}`: the local part of `user.email`, else the slugified
+/// `user.name`.
+fn git_user(repo: &Repo) -> Result<String> {
+    let read = |key: &str| {
+        repo.git()
+            .stdout("read git user", &["config", key])
+            .ok()
+            .and_then(|lines| lines.into_iter().next())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let user = read("user.email")
+        .map(|email| email.split('@').next().unwrap_or_default().to_string())
+        .or_else(|| read("user.name"))
+        .map(|user| slugify(&user, usize::MAX))
+        .filter(|user| !user.is_empty());
+    user.ok_or_else(|| {
+        Error::precondition(format!(
+            "{BRANCH_TEMPLATE} uses {{git_user}}, but neither user.email nor user.name is set."
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EXIT_PRECONDITION;
+    use crate::git::ScriptedRunner;
+    use crate::repo::{scripted_repo, scripted_repo_with};
+    use test_case::test_case;
+
+    #[test_case("Fix: auth bug #123?", "fix-auth-bug-123" ; "punctuation")]
+    #[test_case("  many   spaces  ", "many-spaces" ; "whitespace")]
+    #[test_case("feature/Login Page", "feature/login-page" ; "keeps segments")]
+    #[test_case("a//b/../c", "a/b/c" ; "empty and dot segments")]
+    #[test_case("v1..2 config.lock", "v1.2-config" ; "double dots")]
+    #[test_case("cache.lock", "cache" ; "lock suffix")]
+    #[test_case("Ünïcode ✨ name", "ünïcode-name" ; "unicode letters")]
+    fn slugify_cleans_text(text: &str, expected: &str) {
+        assert_eq!(slugify(text, 64), expected);
+    }
+
+    #[test]
+    fn slugify_truncates_without_trailing_dash() {
+        assert_eq!(slugify("fix the login page", 8), "fix-the");
+    }
+
+    #[test_case("main", "main", true ; "exact")]
+    #[test_case("release/*", "release/1.2", true ; "star")]
+    #[test_case("release/*", "release", false ; "star needs the slash")]
+    #[test_case("*/main", "users/kyle/main", true ; "star spans slashes")]
+    #[test_case("v?", "v1", true ; "question mark")]
+    #[test_case("main", "maintenance", false ; "no prefix match")]
+    fn globs(glob: &str, text: &str, expected: bool) {
+        assert_eq!(glob_match(glob, text), expected);
+    }
+
+    #[test]
+    fn protected_branches_match_the_configured_glob() {
+        let mut config = Config::default();
+        config.apply_flag(BRANCH_PROTECTED, Some("release/*"));
+        assert_eq!(
+            is_protected(&config, "release/2.0").as_deref(),
+            Some("release/*")
+        );
+        assert_eq!(is_protected(&config, "feature"), None);
+        assert_eq!(is_protected(&Config::default(), "main"), None);
+    }
+
+    #[test]
+    fn slug_of_only_punctuation_is_an_error() {
+        let err = slug(&Config::default(), "???").unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_PRECONDITION);
+    }
+
+    #[test]
+    fn invalid_max_length_is_a_parse_error() {
+        let mut config = Config::default();
+        config.apply_flag(BRANCH_MAX_LENGTH, Some("lots"));
+        assert!(matches!(max_length(&config), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn validate_rejects_names_git_rejects() {
+        let git = ScriptedRunner::new().fail(
+            "check-ref-format --branch bad..name",
+            1,
+            "fatal: 'bad..name' is not a valid branch name",
+        );
+        let err = validate(&scripted_repo(&git), "bad..name").unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_PRECONDITION);
+    }
+
+    #[test_case("users/kyle/fix-auth", true ; "matching")]
+    #[test_case("fix-auth", false ; "missing prefix")]
+    fn validate_checks_the_pattern(branch: &str, ok: bool) {
+        let mut config = Config::default();
+        config.apply_flag(BRANCH_PATTERN, Some("^users/[a-z]+/[a-z0-9-]+$"));
+        let git = ScriptedRunner::new();
+        assert_eq!(
+            validate(&scripted_repo_with(&git, config), branch).is_ok(),
+            ok
+        );
+    }
+
+    fn template(template: &str) -> Template {
+        let mut config = Config::default();
+        config.apply_flag(BRANCH_TEMPLATE, Some(template));
+        Template::new(&config).unwrap()
+    }
+
+    fn fields(slug: &str, ticket: Option<&str>) -> Fields {
+        use chrono::TimeZone;
+        Fields {
+            slug: slug.to_string(),
+            ticket: ticket.map(String::from),
+            user: Some(String::from("kyle")),
+            now: Local.with_ymd_and_hms(2026, 3, 9, 12, 0, 0).unwrap(),
+        }
+    }
+
+    #[test_case("{slug}", "fix-auth" ; "default")]
+    #[test_case("users/{git_user}/{ticket}-{slug}", "users/kyle/ABC-123-fix-auth" ; "user and ticket")]
+    #[test_case("{user}/{slug}", "kyle/fix-auth" ; "user alias")]
+    #[test_case("{date:%Y%m}/{slug}", "202603/fix-auth" ; "date format")]
+    #[test_case("{date}-{slug}", "2026-03-09-fix-auth" ; "default date")]
+    fn render_fills_placeholders(text: &str, expected: &str) {
+        let rendered = template(text).render(&fields("fix-auth", Some("ABC-123")));
+        assert_eq!(rendered.unwrap(), expected);
+    }
+
+    #[test_case("{branch}" ; "unknown placeholder")]
+    #[test_case("{slug" ; "unclosed")]
+    #[test_case("{date:%Q}" ; "bad date format")]
+    fn bad_templates_are_parse_errors(text: &str) {
+        let mut config = Config::default();
+        config.apply_flag(BRANCH_TEMPLATE, Some(text));
+        assert!(matches!(Template::new(&config), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn ticket_is_split_from_the_name_only_when_templated() {
+        let with_ticket = template("{ticket}-{slug}");
+        assert_eq!(
+            with_ticket.split_name("fix ABC-123 auth"),
+            (Some(String::from("ABC-123")), String::from("fix-auth"))
+        );
+        assert_eq!(
+            template("{slug}").split_name("ABC-123 fix auth"),
+            (None, String::from("abc-123-fix-auth"))
+        );
+    }
+
+    #[test]
+    fn missing_ticket_is_a_precondition_error() {
+        let err = template("{ticket}-{slug}")
+            .render(&fields("fix-auth", None))
+            .unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_PRECONDITION);
+    }
+
+    #[test]
+    fn git_user_is_the_email_local_part() {
+        let git = ScriptedRunner::new().with("config user.email", "Kyle.Rader@example.com");
+        let repo = scripted_repo(&git);
+        let fields = template("{git_user}/{slug}").fields(&repo, "fix").unwrap();
+        assert_eq!(fields.user.as_deref(), Some("kyle.rader"));
+    }
+
+    #[test]
+    fn matcher_finds_branches_from_any_day_and_ticket() {
+        let template = template("{date:%Y%m}/{ticket}-{slug}");
+        let mut name = fields("fix-auth", None);
+        name.user = None;
+        let matcher = template.matcher(&name, Some("users/kyle/")).unwrap();
+        assert!(matcher.is_match("202601/ABC-9-fix-auth"));
+        assert!(matcher.is_match("users/kyle/202601/ABC-9-fix-auth"));
+        assert!(!matcher.is_match("202601/ABC-9-fix-auth-2"));
+        assert!(!matcher.is_match("fix-auth"));
+    }
+}
@@ -0,0 +1,89 @@
+//! Candidates for dynamic shell completion (see `lk completions`).
+
+use crate::error::Result;
+use crate::repo::Repo;
+use crate::worktree;
+
+/// Names of the repository's worktrees, for `lk w s` and `lk w r`.
+pub fn worktree_names(repo: &Repo) -> Result<Vec<String>> {
+    Ok(worktree::list(repo)?
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect())
+}
+
+/// Branches on the fetch remote without the remote prefix, for `lk rebase`.
+pub fn remote_branches(repo: &Repo) -> Result<Vec<String>> {
+    let prefix = format!("refs/remotes/{}/", repo.remotes().fetch_remote());
+    let branches = repo.git().stdout(
+        "list remote branches",
+        &["for-each-ref", "--format=%(refname)", prefix.as_str()],
+    )?;
+    Ok(branches
+        .iter()
+        .filter_map(|refname| refname.strip_prefix(prefix.as_str()))
+        .filter(|branch| *branch != "HEAD")
+        .map(String::from)
+        .collect())
+}
+
+/// Local branches, remote branches and tags, for `lk w a --base`.
+pub fn refs(repo: &Repo) -> Result<Vec<String>> {
+    let refs = repo.git().stdout(
+        "list refs",
+        &[
+            "for-each-ref",
+            "--format=%(refname:short)",
+            "refs/heads/",
+            "refs/remotes/",
+            "refs/tags/",
+        ],
+    )?;
+    Ok(refs
+        .into_iter()
+        .filter(|name| !name.ends_with("/HEAD"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::ScriptedRunner;
+    use crate::repo::scripted_repo;
+
+    #[test]
+    fn worktree_names_come_from_directories() {
+        let git = ScriptedRunner::new().with(
+            "worktree list --porcelain",
+            "worktree /repos/app\nbranch refs/heads/main\n\nworktree /repos/app_fix-auth\nbranch refs/heads/fix-auth\n",
+        );
+        assert_eq!(
+            worktree_names(&scripted_repo(&git)).unwrap(),
+            vec!["app", "fix-auth"]
+        );
+    }
+
+    #[test]
+    fn remote_branches_strip_remote_and_skip_head() {
+        let git = ScriptedRunner::new().with(
+            "for-each-ref --format=%(refname) refs/remotes/origin/",
+            "refs/remotes/origin/HEAD\nrefs/remotes/origin/main\nrefs/remotes/origin/users/me/fix",
+        );
+        assert_eq!(
+            remote_branches(&scripted_repo(&git)).unwrap(),
+            vec!["main", "users/me/fix"]
+        );
+    }
+
+    #[test]
+    fn refs_skip_symbolic_remote_head() {
+        let git = ScriptedRunner::new().with(
+            "for-each-ref --format=%(refname:short) refs/heads/ refs/remotes/ refs/tags/",
+            "main\norigin/HEAD\norigin/main\nv1.0.0",
+        );
+        assert_eq!(
+            refs(&scripted_repo(&git)).unwrap(),
+            vec!["main", "origin/main", "v1.0.0"]
+        );
+    }
+}
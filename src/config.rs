@@ -0,0 +1,515 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Serialize, Serializer};
+
+use crate::error::{Error, Result};
+use crate::git::GitRunner;
+use crate::remote::DEFAULT_REMOTE;
+use crate::vars::{
+    LOKI_CONFIG, LOKI_NEW_BASE, LOKI_NEW_PREFIX, LOKI_OFFLINE, LOKI_REBASE_TARGET, LOKI_REMOTE,
+    LOKI_TRUNK, LOKI_WORKTREE_BASE,
+};
+
+/// Name of the per-repo config file, looked up at the repository root.
+pub const REPO_CONFIG_FILE: &str = ".loki.toml";
+
+/// Prefix for loki settings stored in git config (e.g. `loki.branch.prefix`).
+const GIT_CONFIG_PREFIX: &str = "loki.";
+
+pub const TRUNK: &str = "trunk";
+pub const REMOTE: &str = "remote";
+pub const OFFLINE: &str = "offline";
+pub const BRANCH_PREFIX: &str = "branch.prefix";
+pub const BRANCH_PATTERN: &str = "branch.pattern";
+pub const BRANCH_MAX_LENGTH: &str = "branch.max-length";
+pub const BRANCH_TEMPLATE: &str = "branch.template";
+pub const BRANCH_TICKET_PATTERN: &str = "branch.ticket-pattern";
+pub const BRANCH_PROTECTED: &str = "branch.protected";
+pub const NEW_BASE: &str = "new.base";
+pub const WORKTREE_BASE: &str = "worktree.base";
+pub const REBASE_TARGET: &str = "rebase.target";
+
+/// A known configuration key, used for env var lookup and `lk config show`.
+pub struct Key {
+    pub name: &'static str,
+    pub env: Option<&'static str>,
+}
+
+pub const KEYS: &[Key] = &[
+    Key {
+        name: TRUNK,
+        env: Some(LOKI_TRUNK),
+    },
+    Key {
+        name: REMOTE,
+        env: Some(LOKI_REMOTE),
+    },
+    Key {
+        name: OFFLINE,
+        env: Some(LOKI_OFFLINE),
+    },
+    Key {
+        name: BRANCH_PREFIX,
+        env: Some(LOKI_NEW_PREFIX),
+    },
+    Key {
+        name: BRANCH_PATTERN,
+        env: None,
+    },
+    Key {
+        name: BRANCH_MAX_LENGTH,
+        env: None,
+    },
+    Key {
+        name: BRANCH_TEMPLATE,
+        env: None,
+    },
+    Key {
+        name: BRANCH_TICKET_PATTERN,
+        env: None,
+    },
+    Key {
+        name: BRANCH_PROTECTED,
+        env: None,
+    },
+    Key {
+        name: NEW_BASE,
+        env: Some(LOKI_NEW_BASE),
+    },
+    Key {
+        name: WORKTREE_BASE,
+        env: Some(LOKI_WORKTREE_BASE),
+    },
+    Key {
+        name: REBASE_TARGET,
+        env: Some(LOKI_REBASE_TARGET),
+    },
+];
+
+/// Where a configuration value came from, in increasing order of precedence.
+/// Serializes as its display string, e.g. `"env LOKI_TRUNK"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    Global(PathBuf),
+    Repo(PathBuf),
+    GitConfig,
+    Env(&'static str),
+    Flag,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::Global(path) => write!(f, "global {}", path.display()),
+            Source::Repo(path) => write!(f, "repo {}", path.display()),
+            Source::GitConfig => write!(f, "git config"),
+            Source::Env(var) => write!(f, "env {var}"),
+            Source::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Str(String),
+    List(Vec<String>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{s}"),
+            Value::List(items) => write!(f, "[{}]", items.join(", ")),
+        }
+    }
+}
+
+/// A resolved configuration value together with the layer that supplied it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Setting {
+    pub value: Value,
+    pub source: Source,
+}
+
+impl Setting {
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::Str(s) => Some(s.as_str()),
+            Value::List(_) => None,
+        }
+    }
+}
+
+/// Effective loki configuration, merged from (lowest to highest precedence):
+/// built-in defaults, the global config file, the per-repo `.loki.toml`,
+/// `git config loki.*`, environment variables and finally CLI flags.
+#[derive(Debug, Default)]
+pub struct Config {
+    values: BTreeMap<String, Setting>,
+    pub global_path: Option<PathBuf>,
+    pub repo_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// Loads every configuration layer for the repository `git` runs in.
+    pub fn load(git: &dyn GitRunner) -> Result<Self> {
+        let (config, mut errors) = Config::load_lenient(git);
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+
+    /// Like [`Config::load`], but skips config files that don't parse and
+    /// returns their errors instead, for commands that should keep working
+    /// with a broken config (`lk config show` among them).
+    pub fn load_lenient(git: &dyn GitRunner) -> (Self, Vec<Error>) {
+        let mut config = Config {
+            global_path: global_config_path(),
+            repo_path: repo_config_path(git),
+            ..Default::default()
+        };
+
+        let mut errors = Vec::new();
+        if let Some(path) = config.global_path.clone() {
+            errors.extend(config.merge_file(&path, Source::Global(path.clone())).err());
+        }
+        if let Some(path) = config.repo_path.clone() {
+            errors.extend(config.merge_file(&path, Source::Repo(path.clone())).err());
+        }
+
+        let git_lines = git
+            .stdout("read git config", &["config", "--get-regexp", r"^loki\."])
+            .unwrap_or_default();
+        config.merge_git_config(git_lines);
+
+        config.merge_env(|var| std::env::var(var).ok());
+
+        (config, errors)
+    }
+
+    fn merge_file(&mut self, path: &Path, source: Source) -> Result<()> {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return Ok(());
+        };
+        self.merge_toml(&contents, source)
+            .map_err(|err| Error::parse(format!("Invalid config file {}: {err}", path.display())))
+    }
+
+    fn merge_toml(&mut self, contents: &str, source: Source) -> Result<()> {
+        let table: toml::Table = contents
+            .parse()
+            .map_err(|err| Error::parse(format!("{err}")))?;
+        let mut flat = BTreeMap::new();
+        flatten_table("", &table, &mut flat);
+        for (key, value) in flat {
+            self.set(key, value, source.clone());
+        }
+        Ok(())
+    }
+
+    fn merge_git_config<I>(&mut self, lines: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut flat: BTreeMap<String, Value> = BTreeMap::new();
+        for line in lines {
+            let (key, value) = match line.split_once(' ') {
+                Some((key, value)) => (key, value.to_string()),
+                // A bare `[loki] key` entry is git's boolean shorthand for true.
+                None => (line.as_str(), String::from("true")),
+            };
+            let Some(key) = key.strip_prefix(GIT_CONFIG_PREFIX) else {
+                continue;
+            };
+            // Repeated keys are git's way of expressing a list.
+            let merged = match flat.remove(key) {
+                None => Value::Str(value),
+                Some(Value::Str(first)) => Value::List(vec![first, value]),
+                Some(Value::List(mut items)) => {
+                    items.push(value);
+                    Value::List(items)
+                }
+            };
+            flat.insert(key.to_string(), merged);
+        }
+        for (key, value) in flat {
+            self.set(key, value, Source::GitConfig);
+        }
+    }
+
+    pub(crate) fn merge_env(&mut self, lookup: impl Fn(&str) -> Option<String>) {
+        for key in KEYS {
+            let Some(var) = key.env else { continue };
+            if let Some(value) = lookup(var).filter(|v| !v.is_empty()) {
+                self.set(key.name.to_string(), Value::Str(value), Source::Env(var));
+            }
+        }
+    }
+
+    fn set(&mut self, key: String, value: Value, source: Source) {
+        self.values.insert(key, Setting { value, source });
+    }
+
+    /// Records a global CLI flag so that derived defaults (e.g. `worktree.base`)
+    /// see it too.
+    pub fn apply_flag(&mut self, key: &str, flag: Option<&str>) {
+        if let Some(flag) = flag {
+            self.set(key.to_string(), Value::Str(flag.to_string()), Source::Flag);
+        }
+    }
+
+    /// Returns the configured value for `key`, ignoring built-in defaults.
+    pub fn get(&self, key: &str) -> Option<&Setting> {
+        self.values.get(key)
+    }
+
+    /// Resolves `key` with an optional CLI flag taking precedence over every
+    /// configured layer, falling back to the built-in default.
+    pub fn resolve(&self, key: &str, flag: Option<&str>) -> Option<Setting> {
+        if let Some(flag) = flag {
+            return Some(Setting {
+                value: Value::Str(flag.to_string()),
+                source: Source::Flag,
+            });
+        }
+        self.get(key).cloned().or_else(|| {
+            self.default_value(key).map(|value| Setting {
+                value: Value::Str(value),
+                source: Source::Default,
+            })
+        })
+    }
+
+    /// Like `resolve`, but returns only the string value.
+    pub fn resolve_str(&self, key: &str, flag: Option<&str>) -> Option<String> {
+        self.resolve(key, flag)
+            .and_then(|setting| setting.as_str().map(String::from))
+    }
+
+    pub fn trunk(&self) -> String {
+        self.resolve_str(TRUNK, None)
+            .unwrap_or_else(|| String::from("main"))
+    }
+
+    pub fn remote(&self) -> String {
+        self.resolve_str(REMOTE, None)
+            .unwrap_or_else(|| String::from(DEFAULT_REMOTE))
+    }
+
+    /// Whether fetches and pushes should be skipped, with pushes queued for
+    /// `lk push --pending`.
+    pub fn offline(&self) -> bool {
+        matches!(
+            self.resolve_str(OFFLINE, None).as_deref(),
+            Some("true" | "1" | "yes" | "on")
+        )
+    }
+
+    fn default_value(&self, key: &str) -> Option<String> {
+        match key {
+            TRUNK => Some(String::from("main")),
+            REMOTE => Some(String::from(DEFAULT_REMOTE)),
+            OFFLINE => Some(String::from("false")),
+            BRANCH_MAX_LENGTH => Some(String::from("64")),
+            BRANCH_TEMPLATE => Some(String::from("{slug}")),
+            BRANCH_TICKET_PATTERN => Some(String::from("[A-Z][A-Z0-9]+-[0-9]+")),
+            NEW_BASE | WORKTREE_BASE => Some(format!("{}/{}", self.remote(), self.trunk())),
+            REBASE_TARGET => Some(self.trunk()),
+            _ => None,
+        }
+    }
+
+    /// Every known key plus any extra keys found in config, with its
+    /// effective value (if any).
+    pub fn effective(&self) -> Vec<(String, Option<Setting>)> {
+        let mut rows: Vec<(String, Option<Setting>)> = KEYS
+            .iter()
+            .map(|key| (key.name.to_string(), self.resolve(key.name, None)))
+            .collect();
+        for (key, setting) in &self.values {
+            if !KEYS.iter().any(|k| k.name == key) {
+                rows.push((key.clone(), Some(setting.clone())));
+            }
+        }
+        rows
+    }
+}
+
+/// `$LOKI_CONFIG`, else `$XDG_CONFIG_HOME/loki/config.toml`, else
+/// `~/.config/loki/config.toml`.
+fn global_config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(LOKI_CONFIG).filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+    Some(config_dir.join("loki").join("config.toml"))
+}
+
+fn repo_config_path(git: &dyn GitRunner) -> Option<PathBuf> {
+    git.stdout("find repo root", &["rev-parse", "--show-toplevel"])
+        .ok()?
+        .into_iter()
+        .next()
+        .map(|root| PathBuf::from(root.trim()).join(REPO_CONFIG_FILE))
+}
+
+/// Flattens nested TOML tables into dotted keys (`[branch] prefix` → `branch.prefix`).
+fn flatten_table(prefix: &str, table: &toml::Table, out: &mut BTreeMap<String, Value>) {
+    for (key, value) in table {
+        let full_key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            toml::Value::Table(inner) => flatten_table(&full_key, inner, out),
+            toml::Value::Array(items) => {
+                let items = items.iter().map(toml_scalar).collect();
+                out.insert(full_key, Value::List(items));
+            }
+            other => {
+                out.insert(full_key, Value::Str(toml_scalar(other)));
+            }
+        }
+    }
+}
+
+fn toml_scalar(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global() -> Source {
+        Source::Global(PathBuf::from("config.toml"))
+    }
+
+    fn repo() -> Source {
+        Source::Repo(PathBuf::from(".loki.toml"))
+    }
+
+    #[test]
+    fn defaults_follow_trunk() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.resolve_str(WORKTREE_BASE, None).unwrap(),
+            "origin/main"
+        );
+
+        config.merge_toml("trunk = \"develop\"", repo()).unwrap();
+        assert_eq!(config.resolve_str(REBASE_TARGET, None).unwrap(), "develop");
+        assert_eq!(
+            config.resolve_str(WORKTREE_BASE, None).unwrap(),
+            "origin/develop"
+        );
+    }
+
+    #[test]
+    fn worktree_base_follows_remote_flag() {
+        let mut config = Config::default();
+        config.apply_flag(REMOTE, Some("upstream"));
+        assert_eq!(
+            config.resolve_str(WORKTREE_BASE, None).unwrap(),
+            "upstream/main"
+        );
+        assert_eq!(config.resolve_str(NEW_BASE, None).unwrap(), "upstream/main");
+    }
+
+    #[test]
+    fn nested_tables_flatten_to_dotted_keys() {
+        let mut config = Config::default();
+        config
+            .merge_toml("[branch]\nprefix = \"users/kyle/\"", global())
+            .unwrap();
+        let setting = config.get(BRANCH_PREFIX).unwrap();
+        assert_eq!(setting.as_str(), Some("users/kyle/"));
+        assert_eq!(setting.source, global());
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let mut config = Config::default();
+        config.merge_toml("trunk = \"global\"", global()).unwrap();
+        config.merge_toml("trunk = \"repo\"", repo()).unwrap();
+        assert_eq!(config.get(TRUNK).unwrap().source, repo());
+
+        config.merge_git_config(vec![String::from("loki.trunk git")]);
+        assert_eq!(config.trunk(), "git");
+
+        config.merge_env(|var| (var == LOKI_TRUNK).then(|| String::from("env")));
+        assert_eq!(config.get(TRUNK).unwrap().source, Source::Env(LOKI_TRUNK));
+
+        let flagged = config.resolve(TRUNK, Some("flag")).unwrap();
+        assert_eq!(flagged.source, Source::Flag);
+        assert_eq!(flagged.as_str(), Some("flag"));
+    }
+
+    #[test]
+    fn empty_env_vars_are_ignored() {
+        let mut config = Config::default();
+        config.merge_env(|_| Some(String::new()));
+        assert!(config.get(BRANCH_PREFIX).is_none());
+    }
+
+    #[test]
+    fn repeated_git_config_keys_become_lists() {
+        let mut config = Config::default();
+        config.merge_git_config(vec![
+            String::from("loki.branch.protected main"),
+            String::from("loki.branch.protected release/*"),
+        ]);
+        assert_eq!(
+            config.get("branch.protected").unwrap().value,
+            Value::List(vec![String::from("main"), String::from("release/*")])
+        );
+    }
+
+    #[test]
+    fn invalid_toml_is_an_error() {
+        let mut config = Config::default();
+        assert!(config.merge_toml("trunk = ", repo()).is_err());
+    }
+
+    #[test]
+    fn lenient_load_skips_invalid_files() {
+        let root = std::env::temp_dir().join(format!("loki-config-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join(REPO_CONFIG_FILE), "trunk = ").unwrap();
+        let git = crate::git::ScriptedRunner::new()
+            .with("rev-parse --show-toplevel", &root.to_string_lossy())
+            .with("config --get-regexp ^loki\\.", "loki.trunk develop");
+
+        assert!(Config::load(&git).is_err());
+        let (config, errors) = Config::load_lenient(&git);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(config.trunk(), "develop");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+use std::{fmt, io};
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// Errors produced by loki commands. Each variant maps to a distinct process
+/// exit code (see [`Error::exit_code`]) so scripts wrapping `lk` can react.
+#[derive(Debug)]
+pub enum Error {
+    /// A git command exited non-zero.
+    Exit {
+        name: String,
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+        /// Whether git's stderr was already shown on the terminal, in which
+        /// case it is left out of the message.
+        streamed: bool,
+    },
+    /// A requirement for the command was not met (bad arguments, detached
+    /// HEAD, missing worktree, ...).
+    Precondition(String),
+    /// git (or another process) could not be started.
+    Spawn {
+        name: String,
+        command: String,
+        source: io::Error,
+    },
+    /// Output from git or a config file could not be understood.
+    Parse(String),
+    /// Any other I/O failure (reading pipes, changing directories, ...).
+    Io { context: String, source: io::Error },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Exit code when a git command fails.
+pub const EXIT_GIT_FAILED: u8 = 1;
+/// Exit code when a precondition is not met.
+pub const EXIT_PRECONDITION: u8 = 2;
+/// Exit code when git cannot be started.
+pub const EXIT_SPAWN: u8 = 3;
+/// Exit code when output cannot be parsed.
+pub const EXIT_PARSE: u8 = 4;
+/// Exit code for other I/O failures.
+pub const EXIT_IO: u8 = 5;
+
+impl Error {
+    pub fn precondition(message: impl Into<String>) -> Self {
+        Error::Precondition(message.into())
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Error::Parse(message.into())
+    }
+
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Exit { .. } => EXIT_GIT_FAILED,
+            Error::Precondition(_) => EXIT_PRECONDITION,
+            Error::Spawn { .. } => EXIT_SPAWN,
+            Error::Parse(_) => EXIT_PARSE,
+            Error::Io { .. } => EXIT_IO,
+        }
+    }
+
+    /// Stderr captured from a failed git command, if any.
+    pub fn stderr(&self) -> Option<&str> {
+        match self {
+            Error::Exit { stderr, .. } => Some(stderr.as_str()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Exit {
+                name,
+                command,
+                code,
+                stderr,
+                streamed,
+            } => {
+                match code {
+                    Some(code) => write!(f, "{name} failed with exit code {code}: {command}")?,
+                    None => write!(f, "{name} was terminated by a signal: {command}")?,
+                }
+                let stderr = stderr.trim();
+                if !stderr.is_empty() && !streamed {
+                    write!(f, "\n{stderr}")?;
+                }
+                Ok(())
+            }
+            Error::Precondition(message) | Error::Parse(message) => write!(f, "{message}"),
+            Error::Spawn {
+                name,
+                command,
+                source,
+            } => write!(f, "{name} failed to run: {command}\n{source}"),
+            Error::Io { context, source } => write!(f, "{context}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Spawn { source, .. } | Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes as `{ "message", "exit_code", "stderr" }` for `--format json`.
+/// `stderr` is git's stderr for failed git commands and `null` otherwise.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 3)?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("exit_code", &self.exit_code())?;
+        state.serialize_field("stderr", &self.stderr().map(str::trim))?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            Error::Exit {
+                name: String::from("push"),
+                command: String::from("git push"),
+                code: Some(128),
+                stderr: String::new(),
+                streamed: false,
+            },
+            Error::precondition("detached"),
+            Error::Spawn {
+                name: String::from("push"),
+                command: String::from("git push"),
+                source: io::Error::from(io::ErrorKind::NotFound),
+            },
+            Error::parse("bad"),
+            Error::io("read", io::Error::from(io::ErrorKind::BrokenPipe)),
+        ];
+        let mut codes: Vec<u8> = errors.iter().map(Error::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0));
+    }
+
+    #[test]
+    fn exit_error_includes_stderr() {
+        let err = Error::Exit {
+            name: String::from("push"),
+            command: String::from("git push origin main"),
+            code: Some(1),
+            stderr: String::from("  ! [rejected] main -> main (fetch first)\n"),
+            streamed: false,
+        };
+        assert_eq!(
+            err.to_string(),
+            "push failed with exit code 1: git push origin main\n! [rejected] main -> main (fetch first)"
+        );
+    }
+
+    #[test]
+    fn streamed_stderr_is_not_repeated() {
+        let err = Error::Exit {
+            name: String::from("push"),
+            command: String::from("git push origin main"),
+            code: Some(1),
+            stderr: String::from("! [rejected] main -> main (fetch first)\n"),
+            streamed: true,
+        };
+        assert_eq!(
+            err.stderr(),
+            Some("! [rejected] main -> main (fetch first)\n")
+        );
+        assert_eq!(
+            err.to_string(),
+            "push failed with exit code 1: git push origin main"
+        );
+    }
+
+    #[test]
+    fn serializes_message_and_exit_code() {
+        let json = serde_json::to_value(Error::precondition("name cannot be empty.")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "message": "name cannot be empty.",
+                "exit_code": EXIT_PRECONDITION,
+                "stderr": null,
+            })
+        );
+    }
+}
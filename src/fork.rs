@@ -0,0 +1,280 @@
+//! The triangular fork workflow behind `lk fork setup`.
+//!
+//! Branches come from and rebase onto the upstream repository but are pushed
+//! to a personal fork. `lk fork setup` renames, adds or repoints the two
+//! remotes as needed (a clone's `origin` becomes the upstream), then writes
+//! `remote.pushDefault = <fork>` so pushes go to the fork and
+//! `loki.remote = <upstream>` so `lk` fetches and rebases from upstream, and
+//! sets the local trunk to track `<upstream>/<trunk>`.
+
+use colored::Colorize;
+
+use crate::error::{Error, Result};
+use crate::git::GitRunner;
+use crate::remote::{remote_names, DEFAULT_REMOTE};
+
+/// Options for `lk fork setup`.
+pub struct ForkSetup<'a> {
+    /// URL of the personal fork branches are pushed to.
+    pub fork_url: &'a str,
+    /// Remote name for the fork.
+    pub fork_name: &'a str,
+    /// Remote name for the upstream repository.
+    pub upstream_name: &'a str,
+    /// URL of the upstream repository, when it isn't already a remote.
+    pub upstream_url: Option<&'a str>,
+    pub trunk: &'a str,
+}
+
+/// A change to the repository's remotes needed for the fork model.
+#[derive(Debug, PartialEq, Eq)]
+enum RemoteAction {
+    Rename { from: String, to: String },
+    Add { name: String, url: String },
+    SetUrl { name: String, url: String },
+}
+
+impl RemoteAction {
+    fn args(&self) -> Vec<&str> {
+        match self {
+            RemoteAction::Rename { from, to } => vec!["remote", "rename", from, to],
+            RemoteAction::Add { name, url } => vec!["remote", "add", name, url],
+            RemoteAction::SetUrl { name, url } => vec!["remote", "set-url", name, url],
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            RemoteAction::Rename { from, to } => format!("Renamed remote {from} → {}", to.cyan()),
+            RemoteAction::Add { name, url } => format!("Added remote {} ({url})", name.cyan()),
+            RemoteAction::SetUrl { name, url } => {
+                format!("Pointed remote {} at {url}", name.cyan())
+            }
+        }
+    }
+}
+
+/// Works out which remotes to rename, add or repoint given the existing
+/// `(name, url)` remotes. When no upstream remote exists and no URL was
+/// given, a clone's `origin` is assumed to be the upstream repository.
+fn plan_remotes(existing: &[(String, String)], setup: &ForkSetup) -> Result<Vec<RemoteAction>> {
+    let url_of = |name: &str| {
+        existing
+            .iter()
+            .find(|(remote, _)| remote == name)
+            .map(|(_, url)| url.as_str())
+    };
+    let mut actions = Vec::new();
+    let mut renamed_origin = false;
+
+    match (url_of(setup.upstream_name), setup.upstream_url) {
+        (Some(current), Some(url)) if current != url => actions.push(RemoteAction::SetUrl {
+            name: setup.upstream_name.to_string(),
+            url: url.to_string(),
+        }),
+        (Some(_), _) => {}
+        (None, Some(url)) => actions.push(RemoteAction::Add {
+            name: setup.upstream_name.to_string(),
+            url: url.to_string(),
+        }),
+        (None, None) => match url_of(DEFAULT_REMOTE) {
+            Some(origin_url) if origin_url != setup.fork_url => {
+                actions.push(RemoteAction::Rename {
+                    from: DEFAULT_REMOTE.to_string(),
+                    to: setup.upstream_name.to_string(),
+                });
+                renamed_origin = true;
+            }
+            _ => {
+                return Err(Error::precondition(format!(
+                    "No `{}` remote found. Pass --upstream-url <url> for the upstream repository.",
+                    setup.upstream_name
+                )))
+            }
+        },
+    }
+
+    // A renamed `origin` frees the name up for the fork (the `gh` convention).
+    let fork_current =
+        url_of(setup.fork_name).filter(|_| !(renamed_origin && setup.fork_name == DEFAULT_REMOTE));
+    match fork_current {
+        Some(current) if current == setup.fork_url => {}
+        Some(_) => actions.push(RemoteAction::SetUrl {
+            name: setup.fork_name.to_string(),
+            url: setup.fork_url.to_string(),
+        }),
+        None => actions.push(RemoteAction::Add {
+            name: setup.fork_name.to_string(),
+            url: setup.fork_url.to_string(),
+        }),
+    }
+
+    Ok(actions)
+}
+
+/// Configures the current repository for the triangular fork workflow:
+/// fetch and rebase from the upstream remote, push branches to the fork.
+pub fn fork_setup(git: &dyn GitRunner, setup: &ForkSetup) -> Result<()> {
+    if setup.fork_name == setup.upstream_name {
+        return Err(Error::precondition(
+            "The fork and upstream remotes must have different names.",
+        ));
+    }
+
+    let existing: Vec<(String, String)> = remote_names(git)
+        .into_iter()
+        .map(|name| {
+            let url = git
+                .stdout("get remote url", &["remote", "get-url", &name])
+                .ok()
+                .and_then(|lines| lines.into_iter().next())
+                .unwrap_or_default();
+            (name, url)
+        })
+        .collect();
+
+    for action in plan_remotes(&existing, setup)? {
+        git.status_quiet("configure remote", &action.args())?;
+        eprintln!("{}", action.describe());
+    }
+
+    git.status_quiet(
+        "set push remote",
+        &["config", "remote.pushDefault", setup.fork_name],
+    )?;
+    git.status_quiet(
+        "set loki remote",
+        &["config", "loki.remote", setup.upstream_name],
+    )?;
+
+    eprintln!("Fetching {} and {}…", setup.upstream_name, setup.fork_name);
+    git.status_quiet(
+        "fetch",
+        &["fetch", "--multiple", setup.upstream_name, setup.fork_name],
+    )?;
+
+    // Keep the local trunk following upstream rather than the fork.
+    let trunk_upstream = format!("{}/{}", setup.upstream_name, setup.trunk);
+    if let Err(err) = git.status_quiet(
+        "track upstream trunk",
+        &[
+            "branch",
+            "--set-upstream-to",
+            trunk_upstream.as_str(),
+            setup.trunk,
+        ],
+    ) {
+        eprintln!(
+            "{} {} still tracks its old upstream, could not point it at {trunk_upstream}: {err}",
+            "warning:".yellow(),
+            setup.trunk
+        );
+    }
+
+    eprintln!(
+        "\n{} fetch from {}, push to {}",
+        "Fork workflow ready:".green().bold(),
+        setup.upstream_name.cyan(),
+        setup.fork_name.cyan()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup<'a>(upstream_url: Option<&'a str>) -> ForkSetup<'a> {
+        ForkSetup {
+            fork_url: "git@github.com:me/repo.git",
+            fork_name: "fork",
+            upstream_name: "upstream",
+            upstream_url,
+            trunk: "main",
+        }
+    }
+
+    fn remote(name: &str, url: &str) -> (String, String) {
+        (name.to_string(), url.to_string())
+    }
+
+    #[test]
+    fn renames_origin_to_upstream_and_adds_fork() {
+        let existing = vec![remote("origin", "git@github.com:org/repo.git")];
+        let actions = plan_remotes(&existing, &setup(None)).unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                RemoteAction::Rename {
+                    from: String::from("origin"),
+                    to: String::from("upstream"),
+                },
+                RemoteAction::Add {
+                    name: String::from("fork"),
+                    url: String::from("git@github.com:me/repo.git"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fork_can_take_over_the_origin_name() {
+        let existing = vec![remote("origin", "git@github.com:org/repo.git")];
+        let setup = ForkSetup {
+            fork_name: "origin",
+            ..setup(None)
+        };
+        let actions = plan_remotes(&existing, &setup).unwrap();
+        assert_eq!(
+            actions[1],
+            RemoteAction::Add {
+                name: String::from("origin"),
+                url: String::from("git@github.com:me/repo.git"),
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_existing_remotes_that_already_match() {
+        let existing = vec![
+            remote("upstream", "git@github.com:org/repo.git"),
+            remote("fork", "git@github.com:me/repo.git"),
+        ];
+        assert!(plan_remotes(&existing, &setup(None)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn repoints_fork_with_a_different_url() {
+        let existing = vec![
+            remote("upstream", "git@github.com:org/repo.git"),
+            remote("fork", "git@github.com:old/repo.git"),
+        ];
+        let actions = plan_remotes(&existing, &setup(None)).unwrap();
+        assert_eq!(
+            actions,
+            vec![RemoteAction::SetUrl {
+                name: String::from("fork"),
+                url: String::from("git@github.com:me/repo.git"),
+            }]
+        );
+    }
+
+    #[test]
+    fn adds_upstream_from_url_when_origin_is_the_fork() {
+        let existing = vec![remote("origin", "git@github.com:me/repo.git")];
+        let actions = plan_remotes(&existing, &setup(Some("git@github.com:org/repo.git"))).unwrap();
+        assert_eq!(
+            actions[0],
+            RemoteAction::Add {
+                name: String::from("upstream"),
+                url: String::from("git@github.com:org/repo.git"),
+            }
+        );
+    }
+
+    #[test]
+    fn errors_without_a_way_to_find_upstream() {
+        let existing = vec![remote("origin", "git@github.com:me/repo.git")];
+        assert!(plan_remotes(&existing, &setup(None)).is_err());
+    }
+}
@@ -1,75 +1,368 @@
 use std::{
-    collections::HashSet,
-    ffi::OsStr,
-    io::{BufRead, BufReader},
-    process::{Command, Stdio},
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    rc::Rc,
     sync::mpsc,
 };
 
+use colored::Colorize;
+
+use crate::error::{Error, Result};
+
 const GIT: &str = "git";
 
-/// Execute the git command returning an error if it fails. No redirection is done.
-pub fn git_command_status<I, S>(name: &str, args: I) -> Result<(), String>
+/// Runs git on behalf of a command. Commands take a runner instead of
+/// shelling out directly so their flows can be exercised against canned
+/// output (see `ScriptedRunner`).
+pub trait GitRunner {
+    /// Runs a command that changes the repository. Stdout is inherited and
+    /// stderr is shown as it arrives.
+    fn status(&self, name: &str, args: &[&str]) -> Result<()>;
+
+    /// Like `status`, but with stdout suppressed and stderr only reported on
+    /// failure.
+    fn status_quiet(&self, name: &str, args: &[&str]) -> Result<()>;
+
+    /// Runs a query and returns its stdout lines.
+    fn stdout(&self, name: &str, args: &[&str]) -> Result<Vec<String>>;
+
+    /// Runs a command and returns its stderr followed by its stdout lines.
+    /// This always runs, so callers must ask git for a dry run themselves.
+    fn lines(&self, name: &str, args: &[&str]) -> Result<Vec<String>>;
+
+    /// Runs `git <first> | git <second>` with `input` on the first command's
+    /// stdin and returns the second command's stdout lines.
+    fn pipe(&self, name: &str, input: &str, first: &[&str], second: &[&str])
+        -> Result<Vec<String>>;
+
+    /// True when mutating commands are recorded instead of run.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+
+    /// The directory commands run in (`git -C`); `None` means the process's
+    /// current directory.
+    fn dir(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Runs git as a child process, in the current directory or in `dir`.
+#[derive(Debug, Default, Clone)]
+pub struct ProcessRunner {
+    dir: Option<PathBuf>,
+    stdout_to_stderr: bool,
+}
+
+impl ProcessRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs every command with `git -C <dir>`.
+    pub fn in_dir(dir: impl AsRef<Path>) -> Self {
+        ProcessRunner {
+            dir: Some(dir.as_ref().to_path_buf()),
+            ..Default::default()
+        }
+    }
+
+    /// Sends the stdout of streamed commands to stderr, keeping our stdout
+    /// clean for `--format json`.
+    pub fn stdout_to_stderr(mut self) -> Self {
+        self.stdout_to_stderr = true;
+        self
+    }
+
+    fn args(&self, args: &[&str]) -> Vec<OsString> {
+        let dir = self
+            .dir
+            .iter()
+            .flat_map(|dir| [OsString::from("-C"), dir.clone().into_os_string()]);
+        dir.chain(args.iter().map(OsString::from)).collect()
+    }
+}
+
+impl GitRunner for ProcessRunner {
+    fn status(&self, name: &str, args: &[&str]) -> Result<()> {
+        if self.stdout_to_stderr {
+            git_command_status_to_stderr(name, self.args(args))
+        } else {
+            git_command_status(name, self.args(args))
+        }
+    }
+
+    fn status_quiet(&self, name: &str, args: &[&str]) -> Result<()> {
+        git_command_status_quiet(name, self.args(args))
+    }
+
+    fn stdout(&self, name: &str, args: &[&str]) -> Result<Vec<String>> {
+        git_command_stdout(name, self.args(args))
+    }
+
+    fn lines(&self, name: &str, args: &[&str]) -> Result<Vec<String>> {
+        git_command_lines(name, self.args(args))
+    }
+
+    fn pipe(
+        &self,
+        name: &str,
+        input: &str,
+        first: &[&str],
+        second: &[&str],
+    ) -> Result<Vec<String>> {
+        git_command_pipe(name, input, &self.args(first), &self.args(second))
+    }
+
+    fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+}
+
+/// Backs `--dry-run`: mutating commands are printed (with their working
+/// directory) to stderr and recorded instead of run. Queries still go to
+/// the inner runner so the preview is accurate.
+pub struct RecordingRunner<R> {
+    inner: R,
+    commands: RefCell<Vec<String>>,
+}
+
+impl<R: GitRunner> RecordingRunner<R> {
+    pub fn new(inner: R) -> Self {
+        RecordingRunner {
+            inner,
+            commands: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The mutating commands recorded so far, formatted as shell commands.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.borrow().clone()
+    }
+
+    fn record(&self, args: &[&str]) {
+        let command = display_command(args);
+        let cwd = match self.inner.dir() {
+            Some(dir) => dir.display().to_string(),
+            None => std::env::current_dir()
+                .map(|cwd| cwd.display().to_string())
+                .unwrap_or_default(),
+        };
+        eprintln!("{} {}$ {command}", "[dry-run]".yellow(), cwd.dimmed());
+        self.commands.borrow_mut().push(command);
+    }
+}
+
+impl<R: GitRunner> GitRunner for RecordingRunner<R> {
+    fn status(&self, _name: &str, args: &[&str]) -> Result<()> {
+        self.record(args);
+        Ok(())
+    }
+
+    fn status_quiet(&self, _name: &str, args: &[&str]) -> Result<()> {
+        self.record(args);
+        Ok(())
+    }
+
+    fn stdout(&self, name: &str, args: &[&str]) -> Result<Vec<String>> {
+        self.inner.stdout(name, args)
+    }
+
+    fn lines(&self, name: &str, args: &[&str]) -> Result<Vec<String>> {
+        self.inner.lines(name, args)
+    }
+
+    fn pipe(
+        &self,
+        name: &str,
+        input: &str,
+        first: &[&str],
+        second: &[&str],
+    ) -> Result<Vec<String>> {
+        self.inner.pipe(name, input, first, second)
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+
+    fn dir(&self) -> Option<&Path> {
+        self.inner.dir()
+    }
+}
+
+/// Builds a `git` process. Every git invocation goes through here.
+pub fn git_command() -> Command {
+    Command::new(GIT)
+}
+
+/// Quotes an argument for display so recorded commands can be copy-pasted.
+pub fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,^~".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Formats a git invocation as a shell command line.
+pub fn display_command<S: AsRef<OsStr>>(args: &[S]) -> String {
+    std::iter::once(String::from(GIT))
+        .chain(
+            args.iter()
+                .map(|arg| shell_quote(&arg.as_ref().to_string_lossy())),
+        )
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Error for a git command that could not be started.
+pub fn spawn_error<S: AsRef<OsStr>>(name: &str, args: &[S], source: std::io::Error) -> Error {
+    Error::Spawn {
+        name: name.to_string(),
+        command: display_command(args),
+        source,
+    }
+}
+
+/// Error for a git command that exited non-zero.
+pub fn exit_error<S: AsRef<OsStr>>(
+    name: &str,
+    args: &[S],
+    status: ExitStatus,
+    stderr: &[u8],
+    streamed: bool,
+) -> Error {
+    Error::Exit {
+        name: name.to_string(),
+        command: display_command(args),
+        code: status.code(),
+        stderr: String::from_utf8_lossy(stderr).into_owned(),
+        streamed,
+    }
+}
+
+/// Execute the git command returning an error if it fails. Stdin and stdout
+/// are inherited; stderr is echoed as it arrives and also captured for the
+/// error.
+pub fn git_command_status<I, S>(name: &str, args: I) -> Result<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    git_command_status_with_stdout(name, args, Stdio::inherit())
+}
+
+/// Like [`git_command_status`], but git's stdout is sent to our stderr so
+/// stdout stays free for machine-readable output.
+pub fn git_command_status_to_stderr<I, S>(name: &str, args: I) -> Result<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    git_command_status_with_stdout(name, args, Stdio::from(std::io::stderr()))
+}
+
+fn git_command_status_with_stdout<I, S>(name: &str, args: I, stdout: Stdio) -> Result<()>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    if let Some(error) = Command::new(GIT).args(args).status().err() {
-        return Err(format!("{} failed to run: {}", name, error));
+    let args: Vec<_> = args.into_iter().collect();
+
+    let mut child = git_command()
+        .args(&args)
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| spawn_error(name, &args, err))?;
+
+    let mut stderr_pipe = child.stderr.take();
+    let tee = std::thread::spawn(move || {
+        let mut captured = Vec::new();
+        let mut buf = [0u8; 4096];
+        let mut stderr = std::io::stderr();
+        while let Some(Ok(n)) = stderr_pipe.as_mut().map(|pipe| pipe.read(&mut buf)) {
+            if n == 0 {
+                break;
+            }
+            let _ = stderr.write_all(&buf[..n]);
+            let _ = stderr.flush();
+            captured.extend_from_slice(&buf[..n]);
+        }
+        captured
+    });
+
+    let status = child
+        .wait()
+        .map_err(|err| Error::io(format!("{name} failed to wait"), err))?;
+    let captured = tee.join().unwrap_or_default();
+
+    if !status.success() {
+        return Err(exit_error(name, &args, status, &captured, true));
     }
     Ok(())
 }
 
 /// Execute the git command with stdout suppressed. Stderr is captured and
 /// included in error messages. Use when stdout must stay clean for piping.
-pub fn git_command_status_quiet<I, S>(name: &str, args: I) -> Result<(), String>
+pub fn git_command_status_quiet<I, S>(name: &str, args: I) -> Result<()>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
     let args: Vec<_> = args.into_iter().collect();
-    let args_display: Vec<_> = args.iter().map(|a| a.as_ref().to_string_lossy()).collect();
 
-    let output = Command::new(GIT)
+    let output = git_command()
         .args(&args)
         .stdout(Stdio::null())
         .stderr(Stdio::piped())
         .output()
-        .map_err(|e| format!("{name} failed to run: git {}\n{e}", args_display.join(" ")))?;
+        .map_err(|err| spawn_error(name, &args, err))?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!(
-            "{name} failed: git {}\n{}",
-            args_display.join(" "),
-            stderr.trim()
+        return Err(exit_error(
+            name,
+            &args,
+            output.status,
+            &output.stderr,
+            false,
         ));
     }
     Ok(())
 }
 
 /// Execute the git command and return an iterator over its output lines (both stdout and stderr) as they arrive.
-pub fn git_command_stream<I, S>(name: &str, args: I) -> Result<impl Iterator<Item = String>, String>
+pub fn git_command_stream<I, S>(name: &str, args: I) -> Result<impl Iterator<Item = String>>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    let mut child = Command::new(GIT)
-        .args(args)
+    let args: Vec<_> = args.into_iter().collect();
+    let mut child = git_command()
+        .args(&args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .map_err(|err| format!("{} failed to start: {}", name, err))?;
+        .map_err(|err| spawn_error(name, &args, err))?;
 
     // Get handles to stdout and stderr
     let stdout = child
         .stdout
         .take()
-        .ok_or_else(|| format!("{} failed to capture stdout", name))?;
+        .ok_or_else(|| Error::precondition(format!("{name} failed to capture stdout")))?;
     let stderr = child
         .stderr
         .take()
-        .ok_or_else(|| format!("{} failed to capture stderr", name))?;
+        .ok_or_else(|| Error::precondition(format!("{name} failed to capture stderr")))?;
 
     // Create channel for collecting output lines
     let (sender, receiver) = mpsc::channel();
@@ -104,31 +397,40 @@ where
     Ok(std::iter::from_fn(move || receiver.recv().ok()))
 }
 
-/// Execute the list of git commands in order, returning on the first failure. No redirection is done.
-pub fn git_commands_status<C, I, S>(commands: C) -> Result<(), String>
-where
-    C: IntoIterator<Item = (&'static str, I)>,
-    I: IntoIterator<Item = S>,
-    S: AsRef<OsStr>,
-{
+/// Execute the list of git commands in order, returning on the first failure.
+pub fn git_commands_status(git: &dyn GitRunner, commands: &[(&str, Vec<&str>)]) -> Result<()> {
     commands
-        .into_iter()
-        .try_for_each(|(name, cmd)| git_command_status(name, cmd))
+        .iter()
+        .try_for_each(|(name, args)| git.status(name, args))
 }
 
-/// Execute a git command and return an iterator over its output lines (both stdout and stderr).
-pub fn git_command_iter<I, S>(name: &str, args: I) -> Result<impl Iterator<Item = String>, String>
+/// Execute a git command and return an iterator over its output lines (both
+/// stdout and stderr). Fails if git exits non-zero.
+pub fn git_command_iter<I, S>(name: &str, args: I) -> Result<impl Iterator<Item = String>>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    let output = Command::new(GIT)
-        .args(args)
+    let args: Vec<_> = args.into_iter().collect();
+    let output = git_command()
+        .args(&args)
         .output()
-        .map_err(|err| format!("{} failed: {}", name, err))?;
+        .map_err(|err| spawn_error(name, &args, err))?;
+
+    if !output.status.success() {
+        return Err(exit_error(
+            name,
+            &args,
+            output.status,
+            &output.stderr,
+            false,
+        ));
+    }
 
-    let stderr = String::from_utf8(output.stderr).map_err(|e| format!("{e}"))?;
-    let stdout = String::from_utf8(output.stdout).map_err(|e| format!("{e}"))?;
+    let stderr = String::from_utf8(output.stderr)
+        .map_err(|e| Error::parse(format!("{name} wrote invalid UTF-8: {e}")))?;
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| Error::parse(format!("{name} wrote invalid UTF-8: {e}")))?;
 
     // Combine stderr and stdout lines into a single iterator
     let lines = stderr
@@ -141,28 +443,316 @@ where
     Ok(lines)
 }
 
-pub fn git_current_branch() -> Result<String, String> {
-    let mut lines = git_command_iter(
-        "get current branch",
-        vec!["rev-parse", "--abbrev-ref", "HEAD"],
-    )?;
+/// The installed git's `(major, minor)` version, from `git --version`, e.g.
+/// `git version 2.43.0` or `git version 2.39.5 (Apple Git-154)`.
+pub fn git_version(git: &dyn GitRunner) -> Option<(u32, u32)> {
+    let output = git.stdout("get git version", &["--version"]).ok()?;
+    let version = output.first()?.split_whitespace().nth(2)?;
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    Some((parts.next()?.ok()?, parts.next()?.ok()?))
+}
 
-    lines
+pub fn git_current_branch(git: &dyn GitRunner) -> Result<String> {
+    git.lines("get current branch", &["rev-parse", "--abbrev-ref", "HEAD"])?
+        .into_iter()
         .next()
         .map(|line| line.trim().to_string())
-        .ok_or_else(|| "No output from git rev-parse".to_string())
+        .ok_or_else(|| Error::parse("No output from git rev-parse"))
+}
+
+/// Maps each local branch to its configured upstream (e.g. `origin/main`), if any.
+pub fn git_branch_upstreams(git: &dyn GitRunner) -> Result<HashMap<String, Option<String>>> {
+    let lines = git.stdout(
+        "get branches",
+        &[
+            "for-each-ref",
+            "--format=%(refname:short) %(upstream:short)",
+            "refs/heads",
+        ],
+    )?;
+    Ok(lines
+        .into_iter()
+        .filter_map(|line| {
+            let (branch, upstream) = line.split_once(' ')?;
+            let upstream = upstream.trim();
+            Some((
+                branch.to_string(),
+                (!upstream.is_empty()).then(|| upstream.to_string()),
+            ))
+        })
+        .collect())
 }
 
-pub fn git_branches() -> Result<HashSet<String>, String> {
-    let branches: HashSet<String> =
-        git_command_iter("get branches", vec!["branch", "--format=%(refname:short)"])?.collect();
-    Ok(branches)
+/// Execute a git command and return its stdout lines, failing with the captured
+/// stderr if git exits non-zero. Unlike `git_command_iter`, stderr never leaks
+/// into the returned lines.
+pub fn git_command_stdout<I, S>(name: &str, args: I) -> Result<Vec<String>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<_> = args.into_iter().collect();
+    let output = git_command()
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| spawn_error(name, &args, err))?;
+
+    if !output.status.success() {
+        return Err(exit_error(
+            name,
+            &args,
+            output.status,
+            &output.stderr,
+            false,
+        ));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| Error::parse(format!("{name} wrote invalid UTF-8: {e}")))?;
+    Ok(stdout.lines().map(String::from).collect())
 }
 
-pub fn git_command_lines<I, S>(name: &str, args: I) -> Result<Vec<String>, String>
+pub fn git_command_lines<I, S>(name: &str, args: I) -> Result<Vec<String>>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
     Ok(git_command_iter(name, args)?.collect())
 }
+
+/// Execute `git <first> | git <second>`, feeding `input` to the first
+/// command's stdin, and return the second command's stdout lines. Stderr of
+/// both commands is inherited.
+pub fn git_command_pipe<S: AsRef<OsStr>>(
+    name: &str,
+    input: &str,
+    first: &[S],
+    second: &[S],
+) -> Result<Vec<String>> {
+    let mut upstream = git_command()
+        .args(first.iter())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|err| spawn_error(name, first, err))?;
+
+    let upstream_stdout = upstream
+        .stdout
+        .take()
+        .ok_or_else(|| Error::precondition(format!("{name} failed to capture stdout")))?;
+
+    let mut downstream = git_command()
+        .args(second.iter())
+        .stdin(Stdio::from(upstream_stdout))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|err| spawn_error(name, second, err))?;
+
+    // Writer thread feeds the first command's stdin while the second command
+    // consumes its stdout concurrently, avoiding deadlock on a full pipe buffer.
+    let mut upstream_stdin = upstream
+        .stdin
+        .take()
+        .ok_or_else(|| Error::precondition(format!("{name} failed to capture stdin")))?;
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || -> std::io::Result<()> {
+        upstream_stdin.write_all(input.as_bytes())?;
+        drop(upstream_stdin);
+        Ok(())
+    });
+
+    let downstream_stdout = downstream
+        .stdout
+        .take()
+        .ok_or_else(|| Error::precondition(format!("{name} failed to capture stdout")))?;
+    let lines = BufReader::new(downstream_stdout)
+        .lines()
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|err| Error::io(format!("Failed to read {name} output"), err))?;
+
+    writer
+        .join()
+        .map_err(|_| Error::precondition(format!("{name} writer thread panicked")))?
+        .map_err(|err| Error::io(format!("{name} failed writing input"), err))?;
+
+    for (mut child, args) in [(upstream, first), (downstream, second)] {
+        let status = child
+            .wait()
+            .map_err(|err| Error::io(format!("{name} failed to wait"), err))?;
+        if !status.success() {
+            return Err(exit_error(name, args, status, &[], true));
+        }
+    }
+
+    Ok(lines)
+}
+
+type Response = std::result::Result<Vec<String>, (i32, String)>;
+
+/// A fake runner for tests: answers queries with canned output keyed by the
+/// space-joined arguments (`"rev-parse --abbrev-ref HEAD"`) and records every
+/// command it sees. Unscripted queries return no output and unscripted
+/// mutating commands succeed. Clones share the call log, so a clone can be
+/// handed to a [`crate::Repo`] and inspected afterwards.
+#[derive(Debug, Default, Clone)]
+pub struct ScriptedRunner {
+    responses: HashMap<String, Vec<Response>>,
+    calls: Rc<RefCell<Vec<String>>>,
+}
+
+impl ScriptedRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers `command` with `output`, one line per line of text.
+    pub fn with(mut self, command: &str, output: &str) -> Self {
+        let lines = output.lines().map(String::from).collect();
+        self.responses.insert(command.to_string(), vec![Ok(lines)]);
+        self
+    }
+
+    /// Answers the next run of `command` with `output`, after the answers
+    /// already scripted for it. The last answer repeats.
+    pub fn then(mut self, command: &str, output: &str) -> Self {
+        let lines = output.lines().map(String::from).collect();
+        self.responses
+            .entry(command.to_string())
+            .or_default()
+            .push(Ok(lines));
+        self
+    }
+
+    /// Makes `command` exit with `code` and `stderr`.
+    pub fn fail(mut self, command: &str, code: i32, stderr: &str) -> Self {
+        self.responses
+            .insert(command.to_string(), vec![Err((code, stderr.to_string()))]);
+        self
+    }
+
+    /// Every command run so far, as space-joined arguments.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.borrow().clone()
+    }
+
+    fn respond(&self, name: &str, command: String) -> Result<Vec<String>> {
+        let runs = self
+            .calls
+            .borrow()
+            .iter()
+            .filter(|call| **call == command)
+            .count();
+        self.calls.borrow_mut().push(command.clone());
+        let response = self
+            .responses
+            .get(&command)
+            .and_then(|responses| responses.get(runs).or_else(|| responses.last()));
+        match response {
+            None => Ok(Vec::new()),
+            Some(Ok(lines)) => Ok(lines.clone()),
+            Some(Err((code, stderr))) => Err(Error::Exit {
+                name: name.to_string(),
+                command: format!("{GIT} {command}"),
+                code: Some(*code),
+                stderr: stderr.clone(),
+                streamed: false,
+            }),
+        }
+    }
+}
+
+impl GitRunner for ScriptedRunner {
+    fn status(&self, name: &str, args: &[&str]) -> Result<()> {
+        self.respond(name, args.join(" ")).map(|_| ())
+    }
+
+    fn status_quiet(&self, name: &str, args: &[&str]) -> Result<()> {
+        self.respond(name, args.join(" ")).map(|_| ())
+    }
+
+    fn stdout(&self, name: &str, args: &[&str]) -> Result<Vec<String>> {
+        self.respond(name, args.join(" "))
+    }
+
+    fn lines(&self, name: &str, args: &[&str]) -> Result<Vec<String>> {
+        self.respond(name, args.join(" "))
+    }
+
+    fn pipe(
+        &self,
+        name: &str,
+        _input: &str,
+        first: &[&str],
+        second: &[&str],
+    ) -> Result<Vec<String>> {
+        self.respond(name, format!("{} | {}", first.join(" "), second.join(" ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("git version 2.43.0", Some((2, 43)) ; "plain")]
+    #[test_case("git version 2.39.5 (Apple Git-154)", Some((2, 39)) ; "apple")]
+    #[test_case("git version 2.45.1.windows.1", Some((2, 45)) ; "windows")]
+    #[test_case("", None ; "no output")]
+    fn parses_git_version(output: &str, expected: Option<(u32, u32)>) {
+        let git = ScriptedRunner::new().with("--version", output);
+        assert_eq!(git_version(&git), expected);
+    }
+
+    #[test]
+    fn scripted_runner_answers_in_order_then_repeats() {
+        let git = ScriptedRunner::new().with("x", "1").then("x", "2");
+        let answers: Vec<Vec<String>> = (0..3).map(|_| git.stdout("x", &["x"]).unwrap()).collect();
+        assert_eq!(answers, vec![vec!["1"], vec!["2"], vec!["2"]]);
+    }
+
+    #[test]
+    fn display_command_leaves_plain_args_alone() {
+        assert_eq!(
+            display_command(&["push", "--set-upstream", "origin", "users/kyle/fix"]),
+            "git push --set-upstream origin users/kyle/fix"
+        );
+    }
+
+    #[test]
+    fn display_command_quotes_args_with_spaces() {
+        assert_eq!(
+            display_command(&["commit", "--message", "it's done"]),
+            r"git commit --message 'it'\''s done'"
+        );
+    }
+
+    #[test]
+    fn recording_runner_records_mutations_and_passes_queries_through() {
+        let git = RecordingRunner::new(
+            ScriptedRunner::new().with("rev-parse --abbrev-ref HEAD", "feature\n"),
+        );
+        assert_eq!(git_current_branch(&git).unwrap(), "feature");
+        git.status("push", &["push", "origin", "feature"]).unwrap();
+        assert!(git.is_dry_run());
+        assert_eq!(git.commands(), vec!["git push origin feature"]);
+        assert_eq!(git.inner.calls(), vec!["rev-parse --abbrev-ref HEAD"]);
+    }
+
+    #[test]
+    fn recording_runner_reports_the_inner_runners_dir() {
+        let git = RecordingRunner::new(ProcessRunner::in_dir("/repo_feature"));
+        assert_eq!(git.dir(), Some(Path::new("/repo_feature")));
+        assert_eq!(RecordingRunner::new(ProcessRunner::new()).dir(), None);
+    }
+
+    #[test]
+    fn scripted_runner_fails_with_stderr() {
+        let git = ScriptedRunner::new().fail("push origin main", 1, "! [rejected]");
+        let err = git.status("push", &["push", "origin", "main"]).unwrap_err();
+        assert_eq!(err.stderr(), Some("! [rejected]"));
+        assert_eq!(git.calls(), vec!["push origin main"]);
+    }
+}
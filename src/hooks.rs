@@ -0,0 +1,163 @@
+//! `lk`'s own hooks, configured in the `[hook]` table.
+//!
+//! ```toml
+//! [hook]
+//! pre-push = "cargo test"
+//! post-worktree-add = ["direnv allow", "code ."]
+//! post-fetch = "notify-send \"pruned: $LOKI_DELETED_BRANCHES\""
+//! ```
+//!
+//! `pre-<command>` scripts run before the command and abort it when they
+//! fail. `post-<command>` scripts run after it succeeds. Both receive a
+//! [`Context`] as `LOKI_*` environment variables.
+
+use std::{collections::BTreeMap, process::Stdio};
+
+use colored::Colorize;
+
+use crate::config::{Config, Value};
+use crate::error::Result;
+use crate::repo::Repo;
+use crate::shell::run_script;
+use crate::vars::{
+    LOKI_BASE_REF, LOKI_BRANCH, LOKI_COMMAND, LOKI_DELETED_BRANCHES, LOKI_HOOK, LOKI_HOOK_REMOTE,
+    LOKI_UPSTREAM, LOKI_WORKTREE_PATH,
+};
+
+/// Config table hooks are read from (`hook.<name>`).
+pub const HOOK_PREFIX: &str = "hook.";
+
+/// Whether a hook runs before or after its command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Pre,
+    Post,
+}
+
+impl Phase {
+    fn prefix(self) -> &'static str {
+        match self {
+            Phase::Pre => "pre",
+            Phase::Post => "post",
+        }
+    }
+}
+
+/// What a hook is told about the command, passed as environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    vars: BTreeMap<&'static str, String>,
+}
+
+impl Context {
+    /// A context for `command`, e.g. `worktree-add`.
+    pub fn new(command: &str) -> Self {
+        let mut context = Context::default();
+        context.set(LOKI_COMMAND, command);
+        context
+    }
+
+    /// The command this context describes.
+    pub fn command(&self) -> &str {
+        self.vars.get(LOKI_COMMAND).map_or("", String::as_str)
+    }
+
+    pub fn set(&mut self, var: &'static str, value: impl Into<String>) -> &mut Self {
+        self.vars.insert(var, value.into());
+        self
+    }
+
+    pub fn branch(&mut self, branch: impl Into<String>) -> &mut Self {
+        self.set(LOKI_BRANCH, branch)
+    }
+
+    pub fn remote(&mut self, remote: impl Into<String>) -> &mut Self {
+        self.set(LOKI_HOOK_REMOTE, remote)
+    }
+
+    pub fn upstream(&mut self, upstream: impl Into<String>) -> &mut Self {
+        self.set(LOKI_UPSTREAM, upstream)
+    }
+
+    pub fn worktree_path(&mut self, path: impl Into<String>) -> &mut Self {
+        self.set(LOKI_WORKTREE_PATH, path)
+    }
+
+    pub fn base_ref(&mut self, base: impl Into<String>) -> &mut Self {
+        self.set(LOKI_BASE_REF, base)
+    }
+
+    /// Deleted branches, one per line.
+    pub fn deleted_branches(&mut self, branches: &[String]) -> &mut Self {
+        self.set(LOKI_DELETED_BRANCHES, branches.join("\n"))
+    }
+
+    pub fn vars(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.vars.iter().map(|(var, value)| (*var, value.as_str()))
+    }
+}
+
+/// The scripts configured for `hook.<phase>-<command>`.
+pub fn scripts(config: &Config, phase: Phase, command: &str) -> Vec<String> {
+    let key = format!("{HOOK_PREFIX}{}-{command}", phase.prefix());
+    match config.get(&key).map(|setting| &setting.value) {
+        Some(Value::Str(script)) => vec![script.clone()],
+        Some(Value::List(scripts)) => scripts.clone(),
+        None => Vec::new(),
+    }
+}
+
+/// Runs the `phase` hooks for the context's command in order, stopping at
+/// the first failure. Hook output goes to stderr so `lk`'s stdout stays
+/// clean for `cd` lines and JSON.
+pub fn run(repo: &Repo, phase: Phase, context: &Context) -> Result<()> {
+    let hook = format!("{}-{}", phase.prefix(), context.command());
+    for script in scripts(repo.config(), phase, context.command()) {
+        eprintln!("{} $ {script}", format!("[{hook}]").cyan());
+        run_script(repo, &format!("{hook} hook"), &script, |command| {
+            command.env(LOKI_HOOK, &hook);
+            command.envs(context.vars());
+            command.stdout(Stdio::from(std::io::stderr()));
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_accept_a_string_or_a_list() {
+        let mut config = Config::default();
+        config.apply_flag("hook.pre-push", Some("cargo test"));
+        assert_eq!(
+            scripts(&config, Phase::Pre, "push"),
+            vec![String::from("cargo test")]
+        );
+        assert!(scripts(&config, Phase::Post, "push").is_empty());
+    }
+
+    #[test]
+    fn context_joins_deleted_branches_with_newlines() {
+        let mut context = Context::new("fetch");
+        context.deleted_branches(&[String::from("a"), String::from("b")]);
+        assert_eq!(
+            context.vars().collect::<Vec<_>>(),
+            vec![(LOKI_COMMAND, "fetch"), (LOKI_DELETED_BRANCHES, "a\nb")]
+        );
+    }
+
+    #[test]
+    fn lk_run_by_a_hook_keeps_the_configured_remote() {
+        let mut context = Context::new("push");
+        context.remote("fork");
+        let vars: BTreeMap<&str, &str> = context.vars().collect();
+        assert_eq!(vars.get(LOKI_HOOK_REMOTE), Some(&"fork"));
+
+        // A child `lk` reads its environment like this.
+        let mut config = Config::default();
+        config.merge_env(|var| vars.get(var).map(|value| value.to_string()));
+        assert_eq!(config.remote(), "origin");
+    }
+}
@@ -0,0 +1,50 @@
+//! Loki: 🚀 A Git productivity tool.
+//!
+//! This crate backs the `lk` command line tool and can be used directly from
+//! Rust. Open a [`Repo`] and pass it to the operations in [`worktree`],
+//! [`pruning`] and [`stats`]. They return structured results instead of
+//! printing, and fail with an [`Error`] that maps to `lk`'s exit codes.
+//!
+//! ```no_run
+//! use loki::{worktree, Repo};
+//!
+//! # fn main() -> loki::Result<()> {
+//! let repo = Repo::open(".")?;
+//! let info = worktree::add(
+//!     &repo,
+//!     &worktree::AddOptions {
+//!         name: "fix-auth".into(),
+//!         ..Default::default()
+//!     },
+//! )?;
+//! println!("created {} on {}", info.path.display(), info.branch);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! To preview what an operation would change, build the repo with a
+//! [`git::RecordingRunner`] via [`Repo::new`]; to test against canned git
+//! output, use a [`git::ScriptedRunner`].
+
+pub mod alias;
+pub mod branch;
+pub mod complete;
+pub mod config;
+pub mod error;
+pub mod fork;
+pub mod git;
+pub mod hooks;
+pub mod oplog;
+pub mod pending;
+pub mod pruning;
+pub mod rejection;
+pub mod remote;
+pub mod repo;
+pub mod shell;
+pub mod stack;
+pub mod stats;
+pub mod vars;
+pub mod worktree;
+
+pub use error::{Error, Result};
+pub use repo::Repo;
@@ -1,12 +1,7 @@
-pub mod git;
-pub mod pruning;
-pub mod vars;
-pub mod worktree;
-
 use std::{
     collections::HashMap,
-    io::{BufRead, Write},
-    process::{Command, Stdio},
+    io::{IsTerminal, Write},
+    process::ExitCode,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -14,16 +9,42 @@ use std::{
     time::Duration,
 };
 
-use chrono::{DateTime, Duration as ChronoDuration, Months, NaiveDate, Utc};
+use chrono::NaiveDate;
 use clap::{
     builder::{styling::AnsiColor, Styles},
-    Parser, Subcommand,
+    parser::ValueSource,
+    ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
+use clap_complete::{
+    engine::{ArgValueCandidates, CompletionCandidate},
+    env::Shells,
+    CompleteEnv,
 };
 use colored::Colorize;
-use git::{
-    git_branches, git_command_iter, git_command_status, git_commands_status, git_current_branch,
+use loki::alias::{self, Step};
+use loki::branch::{self, Template};
+use loki::config::{self, Config, Setting};
+use loki::git::{
+    git_commands_status, git_current_branch, shell_quote, GitRunner, ProcessRunner, RecordingRunner,
+};
+use loki::hooks::{self, Context, Phase};
+use loki::oplog::{self, Entry, Snapshot};
+use loki::pending::{self, Outcome, PendingPush};
+use loki::pruning::{
+    highlight_branch_name, highlight_pruned_branch_line, PrunableBranch, PruneOptions, PruneReason,
+    PruneReport, PruneSource, SkipReason, SkippedBranch,
+};
+use loki::rejection::{self, Rejection};
+use loki::remote::Remotes;
+use loki::shell::{self, Shell};
+use loki::stack::{self, Restacked};
+use loki::stats::{format_active_span, parse_naive_date, AuthorStats, StatsOptions};
+use loki::vars::{LOKI_COMPLETE, NO_HOOKS};
+use loki::worktree::{
+    infer_worktree_name, normalize_path, RemovedWorktree, WorktreeEntry, WorktreeInfo,
 };
-use pruning::{highlight_branch_name, highlight_pruned_branch_line, is_pruned_branch};
+use loki::{complete, fork, pruning, stats, worktree, Error, Repo, Result};
+use serde::Serialize;
 
 fn styles() -> clap::builder::Styles {
     Styles::styled()
@@ -33,8 +54,6 @@ fn styles() -> clap::builder::Styles {
         .placeholder(AnsiColor::Cyan.on_default())
 }
 
-use vars::{LOKI_NEW_PREFIX, LOKI_REBASE_TARGET, LOKI_WORKTREE_BASE, NO_HOOKS};
-
 #[derive(Debug, Parser)]
 struct CommitOptions {
     /// Stage and commit all changes with --all
@@ -45,6 +64,14 @@ struct CommitOptions {
     #[clap(short, long, default_value = "false")]
     update: bool,
 
+    /// Move the changes to a new branch NAME (created like `lk new`) first
+    #[clap(long, value_name = "NAME")]
+    new_branch: Option<String>,
+
+    /// Commit even when HEAD (or the --new-branch) is a protected branch (`branch.protected`)
+    #[clap(long)]
+    allow_protected: bool,
+
     /// Optional message to include. Each MESSAGE will be joined on whitespace.
     message: Vec<String>,
 }
@@ -105,6 +132,22 @@ struct RepoStatsOptions {
     no_dedup: bool,
 }
 
+impl From<&RepoStatsOptions> for StatsOptions {
+    fn from(options: &RepoStatsOptions) -> Self {
+        StatsOptions {
+            days: options.days,
+            weeks: options.weeks,
+            months: options.months,
+            from: options.from,
+            to: options.to,
+            first_parent: options.first_parent,
+            names: options.names.clone(),
+            emails: options.emails.clone(),
+            no_dedup: options.no_dedup,
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum RepoSubcommand {
     /// Analyze commits by author over time.
@@ -117,15 +160,15 @@ enum WorktreeSubcommand {
     /// Create a new worktree and branch.
     #[clap(visible_alias = "a")]
     Add {
-        /// Optional prefix to prepend to the branch name.
-        #[clap(long, env = LOKI_NEW_PREFIX)]
+        /// Optional prefix to prepend to the branch name [config: branch.prefix, env: LOKI_NEW_PREFIX]
+        #[clap(long)]
         prefix: Option<String>,
 
-        /// Base ref to create the worktree from.
-        #[clap(short, long, default_value = "origin/main", env = LOKI_WORKTREE_BASE)]
-        base: String,
+        /// Base ref to create the worktree from [config: worktree.base, env: LOKI_WORKTREE_BASE, default: <remote>/<trunk>]
+        #[clap(short, long, add = ArgValueCandidates::new(ref_candidates))]
+        base: Option<String>,
 
-        /// Name parts joined with dashes to form the worktree and branch name.
+        /// Name parts, slugified into the worktree and branch name (`Fix: auth bug` → `fix-auth-bug`).
         name: Vec<String>,
     },
 
@@ -137,6 +180,7 @@ enum WorktreeSubcommand {
         force: bool,
 
         /// Worktree name. If omitted, inferred from the current directory.
+        #[clap(add = ArgValueCandidates::new(worktree_candidates))]
         name: Vec<String>,
     },
 
@@ -151,38 +195,190 @@ enum WorktreeSubcommand {
     #[clap(visible_alias = "s")]
     Switch {
         /// Worktree name. If omitted, switches to the main worktree.
+        #[clap(add = ArgValueCandidates::new(worktree_candidates))]
         name: Vec<String>,
     },
 }
 
 #[derive(Parser)]
 #[clap(version, about, author, color = clap::ColorChoice::Auto, styles = styles())]
-enum Cli {
-    /// Create a new branch from HEAD and push it to origin.
-    /// Set a prefix for all new branch names with `--prefix` or `LOKI_NEW_PREFIX`.
+struct Cli {
+    /// Remote to fetch from and push to [config: remote, env: LOKI_REMOTE, default: origin]
+    #[clap(long, global = true)]
+    remote: Option<String>,
+
+    /// Print the git commands that would change the repository instead of running them.
+    #[clap(long, global = true)]
+    dry_run: bool,
+
+    /// Don't fetch or push; queue pushes for `lk push --pending` [config: offline, env: LOKI_OFFLINE]
+    #[clap(long, global = true)]
+    offline: bool,
+
+    /// Output format. `json` prints one JSON document per command to stdout.
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Colored text for people.
+    #[default]
+    Human,
+    /// A single JSON document on stdout; git's own output goes to stderr.
+    Json,
+}
+
+impl OutputFormat {
+    fn is_json(self) -> bool {
+        self == OutputFormat::Json
+    }
+
+    /// Prints `doc` as the command's result when `--format json` is active.
+    fn json(self, doc: &impl Serialize) -> Result<()> {
+        if self.is_json() {
+            let json = serde_json::to_string_pretty(doc)
+                .map_err(|err| Error::parse(format!("Failed to serialize output: {err}")))?;
+            println!("{json}");
+        }
+        Ok(())
+    }
+}
+
+/// A branch pushed with `--set-upstream` by `lk new`, `lk push` or `lk save`.
+#[derive(Debug, Serialize)]
+struct PushedBranch {
+    branch: String,
+    remote: String,
+    upstream: String,
+    /// The remote was offline, so the push waits for `lk push --pending`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    queued: bool,
+    /// The first push was rejected, so the branch was rebased onto the
+    /// remote one and pushed again.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    rebased: bool,
+}
+
+impl PushedBranch {
+    fn new(branch: String, remote: String) -> Self {
+        let upstream = format!("{remote}/{branch}");
+        PushedBranch {
+            branch,
+            remote,
+            upstream,
+            queued: false,
+            rebased: false,
+        }
+    }
+
+    fn with_outcome(mut self, outcome: Outcome) -> Self {
+        self.queued = outcome == Outcome::Queued;
+        self
+    }
+
+    fn describe(&self, context: &mut Context) {
+        context
+            .branch(&self.branch)
+            .remote(&self.remote)
+            .upstream(&self.upstream);
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Fetch, then create a new branch from the remote trunk and push it to the remote.
+    /// Set a prefix for all new branch names with `--prefix`, `branch.prefix` config or `LOKI_NEW_PREFIX`.
     #[clap(visible_alias = "n")]
     New {
-        /// Optional prefix to prepend to the generated branch name.
-        #[clap(long, env = LOKI_NEW_PREFIX)]
+        /// Optional prefix to prepend to the generated branch name [config: branch.prefix, env: LOKI_NEW_PREFIX]
+        #[clap(long)]
         prefix: Option<String>,
 
-        /// List of names to join with dashes to form a valid branch name.
+        /// Ref to branch from; `HEAD` branches from the current commit [config: new.base, env: LOKI_NEW_BASE, default: <remote>/<trunk>]
+        #[clap(long, add = ArgValueCandidates::new(ref_candidates))]
+        from: Option<String>,
+
+        /// Bring uncommitted changes to the new branch instead of refusing
+        #[clap(long)]
+        carry: bool,
+
+        /// Name parts, slugified into the branch name (`Fix: auth bug` → `fix-auth-bug`).
         name: Vec<String>,
     },
 
-    /// Push the current branch to origin with --set-upstream
+    /// Push the current branch to its push remote with --set-upstream
     #[clap(visible_alias = "p")]
     Push {
         /// Use --force-with-lease
         #[clap(short, long)]
         force: bool,
+
+        /// Run the pushes queued while offline instead
+        #[clap(long, conflicts_with = "force")]
+        pending: bool,
+
+        /// Push even when HEAD is on a protected branch (`branch.protected`)
+        #[clap(long)]
+        allow_protected: bool,
+
+        /// When the remote branch has moved, fetch it, rebase onto it and push again
+        #[clap(long, conflicts_with = "pending")]
+        rebase_on_reject: bool,
     },
 
     /// Pull with --prune deleting local branches pruned from the remote.
-    Pull,
+    Pull {
+        /// Also delete pruned branches with commits the remote never had
+        #[clap(short, long)]
+        force: bool,
+
+        /// Remove clean worktrees that have a pruned branch checked out, then delete it
+        #[clap(long)]
+        remove_worktrees: bool,
+    },
 
     /// Fetch with --prune deleting local branches pruned from the remote.
-    Fetch,
+    Fetch {
+        /// Also delete pruned branches with commits the remote never had
+        #[clap(short, long)]
+        force: bool,
+
+        /// Remove clean worktrees that have a pruned branch checked out, then delete it
+        #[clap(long)]
+        remove_worktrees: bool,
+    },
+
+    /// Delete local branches that are done with, without fetching.
+    #[clap(group = clap::ArgGroup::new("mode").required(true).multiple(true))]
+    Prune {
+        /// Delete branches whose upstream is gone, e.g. pruned by an earlier fetch
+        #[clap(long, group = "mode")]
+        gone: bool,
+
+        /// Delete branches whose changes are in the trunk, including squash merges
+        #[clap(long, group = "mode")]
+        merged: bool,
+
+        /// Branch to check --merged against [default: <trunk>]
+        #[clap(long, requires = "merged", add = ArgValueCandidates::new(ref_candidates))]
+        into: Option<String>,
+
+        /// Delete merged branches without asking first
+        #[clap(short, long)]
+        yes: bool,
+
+        /// Also delete gone branches with commits no remote has
+        #[clap(short, long)]
+        force: bool,
+
+        /// Remove clean worktrees that have a pruned branch checked out, then delete it
+        #[clap(long)]
+        remove_worktrees: bool,
+    },
 
     /// Add, commit, and push using a timestamp based commit message.
     ///
@@ -198,9 +394,9 @@ enum Cli {
 
     /// Rebase the current branch onto the target branch after fetching.
     Rebase {
-        /// The branch to rebase onto.
-        #[clap(default_value = "main", env = LOKI_REBASE_TARGET)]
-        target: String,
+        /// The branch to rebase onto [config: rebase.target, env: LOKI_REBASE_TARGET, default: <trunk>]
+        #[clap(add = ArgValueCandidates::new(remote_branch_candidates))]
+        target: Option<String>,
 
         /// Start an interactive rebase.
         #[clap(short, long)]
@@ -230,167 +426,709 @@ enum Cli {
     /// Push the main branch to the release branch.
     #[clap(visible_alias = "r")]
     Release,
+
+    /// Stacked branches: branches built on top of other feature branches.
+    Stack {
+        #[clap(subcommand)]
+        command: StackSubcommand,
+    },
+
+    /// Set up and use the fork (triangular) workflow.
+    Fork {
+        #[clap(subcommand)]
+        command: ForkSubcommand,
+    },
+
+    /// Print a shell function wrapping lk that changes directory after
+    /// worktree commands, followed by completions.
+    ///
+    /// bash: eval "$(lk shell-init bash)"
+    /// zsh: eval "$(lk shell-init zsh)"
+    /// fish: lk shell-init fish | source
+    /// PowerShell: lk shell-init pwsh | Out-String | Invoke-Expression
+    ShellInit {
+        /// The shell to emit the wrapper for.
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+
+    /// Print a script registering dynamic completions for lk.
+    ///
+    /// Completes worktree names, branches and refs from the current repository.
+    Completions {
+        /// The shell to emit completions for.
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+
+    /// Show the operations lk recorded, newest first.
+    Oplog {
+        /// Number of entries to show.
+        #[clap(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+    },
+
+    /// Restore the branches and HEAD changed by an earlier lk command.
+    ///
+    /// Undoes the latest operation by default. Undo is itself recorded, so it
+    /// can be undone too.
+    Undo {
+        /// The oplog entry to undo (see `lk oplog`).
+        id: Option<u64>,
+
+        /// Undo even if the branches have moved since.
+        #[clap(short, long)]
+        force: bool,
+    },
+
+    /// Inspect loki configuration.
+    Config {
+        #[clap(subcommand)]
+        command: ConfigSubcommand,
+    },
+
+    /// An alias from the `[alias]` config table.
+    #[clap(external_subcommand)]
+    Alias(Vec<String>),
+}
+
+#[derive(Debug, Subcommand)]
+enum ForkSubcommand {
+    /// Configure remotes to fetch from upstream and push to your fork.
+    ///
+    /// Renames `origin` to the upstream name (unless an upstream remote or
+    /// --upstream-url is given), adds the fork remote, and sets
+    /// `remote.pushDefault` and `loki.remote` so every command follows suit.
+    Setup {
+        /// URL of your personal fork.
+        url: String,
+
+        /// Remote name for your fork.
+        #[clap(long, default_value = "fork")]
+        fork: String,
+
+        /// Remote name for the upstream repository.
+        #[clap(long, default_value = "upstream")]
+        upstream: String,
+
+        /// URL of the upstream repository, if it isn't already a remote.
+        #[clap(long)]
+        upstream_url: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum StackSubcommand {
+    /// Create a branch on top of the current one, remembering it as the parent, and push it.
+    New {
+        /// Optional prefix to prepend to the generated branch name [config: branch.prefix, env: LOKI_NEW_PREFIX]
+        #[clap(long)]
+        prefix: Option<String>,
+
+        /// Name parts, slugified into the branch name.
+        name: Vec<String>,
+    },
+
+    /// Show stacked branches as a tree under their parents.
+    #[clap(visible_alias = "l")]
+    List,
+
+    /// Rebase every stacked branch onto its parent's current tip, parents
+    /// first, pushing each rebased branch with --force-with-lease.
+    Restack,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigSubcommand {
+    /// Print the effective configuration and where each value came from.
+    Show,
 }
 
-fn main() -> Result<(), String> {
+fn main() -> ExitCode {
+    CompleteEnv::with_factory(Cli::command)
+        .var(LOKI_COMPLETE)
+        .complete();
+
     let cli = Cli::parse();
+    let format = cli.format;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            #[derive(Serialize)]
+            struct Failure<'a> {
+                error: &'a Error,
+            }
+
+            if format.is_json() {
+                let _ = format.json(&Failure { error: &err });
+            } else {
+                eprintln!("{} {err}", "error:".red().bold());
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let format = cli.format;
+    let process = if format.is_json() {
+        ProcessRunner::new().stdout_to_stderr()
+    } else {
+        ProcessRunner::new()
+    };
+    let git: Box<dyn GitRunner> = if cli.dry_run {
+        Box::new(RecordingRunner::new(process))
+    } else {
+        Box::new(process)
+    };
+    let mut config = if needs_config(&cli.command) {
+        Config::load(git.as_ref())?
+    } else {
+        let (config, errors) = Config::load_lenient(git.as_ref());
+        for err in errors {
+            eprintln!("{} {err}", "warning:".yellow());
+        }
+        config
+    };
+    config.apply_flag(config::REMOTE, cli.remote.as_deref());
+    config.apply_flag(config::OFFLINE, cli.offline.then_some("true"));
+    let remotes = Remotes::resolve(&config, cli.remote.as_deref());
+    let repo = Repo::new(git, config, remotes);
+
+    // Dry runs change nothing, and outside a repository there is nothing to log.
+    let before = (!cli.dry_run && changes_refs(&cli.command))
+        .then(|| Snapshot::take(repo.git()).ok())
+        .flatten();
+    let result = dispatch(&repo, &cli.command, format);
+    if let Some(before) = before {
+        if let Err(err) = oplog::record(&repo, &command_line(), &before) {
+            eprintln!(
+                "{} failed to record oplog entry: {err}",
+                "warning:".yellow()
+            );
+        }
+    }
+    result
+}
+
+/// Whether `command` should fail on a config file that doesn't parse. The
+/// rest warn and carry on, so `lk config show` can still help debug it.
+fn needs_config(command: &Commands) -> bool {
+    !matches!(
+        command,
+        Commands::ShellInit { .. } | Commands::Completions { .. } | Commands::Config { .. }
+    )
+}
+
+/// Whether `command` can move refs and so should be recorded in the oplog.
+fn changes_refs(command: &Commands) -> bool {
+    !matches!(
+        command,
+        Commands::Repo { .. }
+            | Commands::Worktree {
+                command: WorktreeSubcommand::List | WorktreeSubcommand::Switch { .. },
+            }
+            | Commands::Fork { .. }
+            | Commands::Stack {
+                command: StackSubcommand::List,
+            }
+            | Commands::ShellInit { .. }
+            | Commands::Completions { .. }
+            | Commands::Config { .. }
+            | Commands::Oplog { .. }
+    )
+}
+
+/// This invocation as a shell command line, for the oplog.
+fn command_line() -> String {
+    std::iter::once(String::from("lk"))
+        .chain(std::env::args().skip(1).map(|arg| shell_quote(&arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs a built-in command, or a config-defined alias, against `repo`,
+/// surrounded by its `pre-` and `post-` hooks.
+fn dispatch(repo: &Repo, command: &Commands, format: OutputFormat) -> Result<()> {
+    let name = hook_name(command).filter(|name| {
+        !hooks::scripts(repo.config(), Phase::Pre, name).is_empty()
+            || !hooks::scripts(repo.config(), Phase::Post, name).is_empty()
+    });
+    let Some(name) = name else {
+        return execute(repo, command, format, &mut Context::default());
+    };
+
+    let mut context = Context::new(name);
+    context.remote(repo.remotes().fetch_remote());
+    if let Ok(branch) = git_current_branch(repo.git()) {
+        if branch != "HEAD" {
+            context.branch(branch);
+        }
+    }
+    hooks::run(repo, Phase::Pre, &context)?;
+    execute(repo, command, format, &mut context)?;
+    hooks::run(repo, Phase::Post, &context)
+}
+
+/// The name hooks use for `command` (`hook.pre-<name>`), if it has hooks.
+fn hook_name(command: &Commands) -> Option<&'static str> {
+    let name = match command {
+        Commands::New { .. } => "new",
+        Commands::Push { .. } => "push",
+        Commands::Pull { .. } => "pull",
+        Commands::Fetch { .. } => "fetch",
+        Commands::Prune { .. } => "prune",
+        Commands::Save(_) => "save",
+        Commands::Commit(_) => "commit",
+        Commands::Rebase { .. } => "rebase",
+        Commands::Repo { .. } => "repo-stats",
+        Commands::Worktree { command } => match command {
+            WorktreeSubcommand::Add { .. } => "worktree-add",
+            WorktreeSubcommand::Remove { .. } => "worktree-remove",
+            WorktreeSubcommand::List => "worktree-list",
+            WorktreeSubcommand::Switch { .. } => "worktree-switch",
+        },
+        Commands::Release => "release",
+        Commands::Stack { command } => match command {
+            StackSubcommand::New { .. } => "stack-new",
+            StackSubcommand::List => "stack-list",
+            StackSubcommand::Restack => "stack-restack",
+        },
+        Commands::Fork { .. } => "fork-setup",
+        Commands::Undo { .. } => "undo",
+        // `no-hooks` skips hooks by definition; the rest only print, or
+        // (aliases) run steps that have hooks of their own.
+        Commands::NoHooks { .. }
+        | Commands::ShellInit { .. }
+        | Commands::Completions { .. }
+        | Commands::Config { .. }
+        | Commands::Oplog { .. }
+        | Commands::Alias(_) => return None,
+    };
+    Some(name)
+}
 
-    match &cli {
-        Cli::New { name, prefix } => new_branch(name, prefix.as_deref()),
-        Cli::Push { force } => push_branch(*force),
-        Cli::Pull => pull_prune(),
-        Cli::Fetch => fetch_prune(),
-        Cli::Save(commit_options) => save(commit_options),
-        Cli::Commit(commit_options) => commit(commit_options),
-        Cli::Rebase {
+/// Runs `command`, recording what it did in `context` for `post-` hooks.
+fn execute(
+    repo: &Repo,
+    command: &Commands,
+    format: OutputFormat,
+    context: &mut Context,
+) -> Result<()> {
+    let config = repo.config();
+
+    match command {
+        Commands::New {
+            name,
+            prefix,
+            from,
+            carry,
+        } => {
+            let pushed = new_branch(
+                repo,
+                name,
+                config.resolve(config::BRANCH_PREFIX, prefix.as_deref()),
+                config
+                    .resolve_str(config::NEW_BASE, from.as_deref())
+                    .unwrap_or_else(|| String::from("HEAD")),
+                *carry,
+                true,
+            )?;
+            pushed.describe(context);
+            format.json(&pushed)
+        }
+        Commands::Push { pending: true, .. } => push_pending(repo, format),
+        Commands::Push {
+            force,
+            allow_protected,
+            rebase_on_reject,
+            ..
+        } => {
+            guard_protected(repo, "push", *allow_protected)?;
+            let pushed = push_branch(repo, *force, *rebase_on_reject)?;
+            pushed.describe(context);
+            format.json(&pushed)
+        }
+        Commands::Pull {
+            force,
+            remove_worktrees,
+        } => {
+            let options = PruneOptions {
+                force: *force,
+                remove_worktrees: *remove_worktrees,
+                ..Default::default()
+            };
+            let report = prune(repo, PruneSource::Pull, &options, format)?;
+            context.deleted_branches(&report.deleted);
+            Ok(())
+        }
+        Commands::Fetch {
+            force,
+            remove_worktrees,
+        } => {
+            let options = PruneOptions {
+                force: *force,
+                remove_worktrees: *remove_worktrees,
+                ..Default::default()
+            };
+            let report = prune(repo, PruneSource::Fetch, &options, format)?;
+            context.deleted_branches(&report.deleted);
+            Ok(())
+        }
+        Commands::Prune {
+            gone,
+            merged,
+            into,
+            yes,
+            force,
+            remove_worktrees,
+        } => {
+            let options = PruneOptions {
+                gone: *gone,
+                merged_into: merged.then(|| into.clone().unwrap_or_else(|| config.trunk())),
+                force: *force,
+                remove_worktrees: *remove_worktrees,
+            };
+            let report = prune_local(repo, &options, *yes, format)?;
+            context.deleted_branches(&report.deleted);
+            Ok(())
+        }
+        Commands::Save(commit_options) => {
+            save(repo, commit_options, format)?.describe(context);
+            Ok(())
+        }
+        Commands::Commit(commit_options) => commit(repo, commit_options, format),
+        Commands::Rebase {
             target,
             interactive,
-        } => rebase(target, *interactive),
-        Cli::NoHooks { command } => no_hooks(command),
-        Cli::Repo {
+        } => {
+            let target = config
+                .resolve_str(config::REBASE_TARGET, target.as_deref())
+                .unwrap_or_else(|| config.trunk());
+            context.base_ref(&target);
+            rebase(repo, &target, *interactive, format)
+        }
+        Commands::NoHooks { command } => no_hooks(repo, command, format),
+        Commands::Repo {
             command: RepoSubcommand::Stats(options),
-        } => repo_stats(options),
-        Cli::Worktree { command } => match command {
+        } => repo_stats(repo, options, format),
+        Commands::Worktree { command } => match command {
             WorktreeSubcommand::Add { name, base, prefix } => {
-                worktree::worktree_add(name, base, prefix.as_deref())
+                let info = worktree_add(
+                    repo,
+                    worktree::AddOptions {
+                        name: name.join(" "),
+                        base: base.clone(),
+                        prefix: prefix.clone(),
+                    },
+                    format,
+                )?;
+                context
+                    .branch(&info.branch)
+                    .worktree_path(info.path.to_string_lossy())
+                    .base_ref(&info.base);
+                Ok(())
+            }
+            WorktreeSubcommand::Remove { name, force } => {
+                let removed = worktree_remove(repo, name, *force, format)?;
+                context.worktree_path(removed.path.to_string_lossy());
+                if let Some(branch) = removed.branch.filter(|_| removed.branch_deleted) {
+                    context.deleted_branches(&[branch]);
+                }
+                Ok(())
+            }
+            WorktreeSubcommand::List => worktree_list(repo, format),
+            WorktreeSubcommand::Switch { name } => worktree_switch(repo, name, format),
+        },
+        Commands::Release => release(repo, format),
+        Commands::Stack { command } => match command {
+            StackSubcommand::New { prefix, name } => {
+                let pushed = stack_new(
+                    repo,
+                    name,
+                    config.resolve(config::BRANCH_PREFIX, prefix.as_deref()),
+                )?;
+                pushed.describe(context);
+                format.json(&pushed)
             }
-            WorktreeSubcommand::Remove { name, force } => worktree::worktree_remove(name, *force),
-            WorktreeSubcommand::List => worktree::worktree_list(),
-            WorktreeSubcommand::Switch { name } => worktree::worktree_switch(name),
+            StackSubcommand::List => stack_list(repo, format),
+            StackSubcommand::Restack => stack_restack(repo, format),
         },
-        Cli::Release => release(),
+        Commands::ShellInit { shell } => shell_init(*shell),
+        Commands::Completions { shell } => write_completions(*shell, &mut std::io::stdout()),
+        Commands::Config {
+            command: ConfigSubcommand::Show,
+        } => config_show(config, format),
+        Commands::Fork {
+            command:
+                ForkSubcommand::Setup {
+                    url,
+                    fork,
+                    upstream,
+                    upstream_url,
+                },
+        } => {
+            fork::fork_setup(
+                repo.git(),
+                &fork::ForkSetup {
+                    fork_url: url,
+                    fork_name: fork,
+                    upstream_name: upstream,
+                    upstream_url: upstream_url.as_deref(),
+                    trunk: &config.trunk(),
+                },
+            )?;
+            format.json(&serde_json::json!({ "fork": fork, "upstream": upstream }))
+        }
+        Commands::Oplog { limit } => show_oplog(repo, *limit, format),
+        Commands::Undo { id, force } => {
+            let entry = oplog::undo(repo, *id, *force)?;
+            if format.is_json() {
+                return format.json(&entry);
+            }
+            eprintln!("Undid #{} {}", entry.id, entry.command.cyan());
+            Ok(())
+        }
+        Commands::Alias(args) => run_alias(repo, args, format),
     }
 }
 
-fn release() -> Result<(), String> {
-    git_command_status(
-        "push main to release",
-        vec!["push", "origin", "main:release"],
-    )?;
+fn show_oplog(repo: &Repo, limit: usize, format: OutputFormat) -> Result<()> {
+    let entries = oplog::read(repo)?;
+    let recent: Vec<&Entry> = entries.iter().rev().take(limit).collect();
+    if format.is_json() {
+        return format.json(&recent);
+    }
+    if recent.is_empty() {
+        println!("No operations recorded yet");
+        return Ok(());
+    }
+
+    let short = |sha: &Option<String>| match sha {
+        Some(sha) => sha.chars().take(7).collect::<String>(),
+        None => String::from("(none)"),
+    };
+    for entry in recent {
+        let time = chrono::DateTime::from_timestamp(entry.time, 0)
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        println!(
+            "{} {} {}",
+            format!("#{}", entry.id).yellow(),
+            time.dimmed(),
+            entry.command
+        );
+        for change in &entry.changes {
+            let what = match (&change.before, &change.after) {
+                (None, _) => "created".green(),
+                (_, None) => "deleted".red(),
+                _ => "moved".normal(),
+            };
+            println!(
+                "    {what} {} {} → {}",
+                highlight_branch_name(&change.branch),
+                short(&change.before),
+                short(&change.after)
+            );
+        }
+    }
     Ok(())
 }
 
-fn no_hooks(command: &[impl AsRef<str>]) -> Result<(), String> {
-    if command.is_empty() {
-        return Err(String::from("command cannot be empty."));
+/// Runs the alias named by `args[0]` with the remaining arguments, stopping
+/// at the first step that fails.
+fn run_alias(repo: &Repo, args: &[String], format: OutputFormat) -> Result<()> {
+    let Some((name, args)) = args.split_first() else {
+        return Err(Error::precondition("alias name cannot be empty."));
+    };
+    let alias = alias::find(repo.config(), name)?.ok_or_else(|| {
+        Error::precondition(format!(
+            "Unknown command or alias `{name}`. See `lk --help` or `lk config show`."
+        ))
+    })?;
+
+    let label = format!("[{}]", alias.name).cyan();
+    for (index, step) in alias.steps.iter().enumerate() {
+        let result = step.interpolate(args).and_then(|step| match step {
+            Step::Shell(script) => {
+                eprintln!("{label} $ {script}");
+                shell::run_script(repo, "alias step", &script, |_| {})
+            }
+            Step::Lk(words) => {
+                eprintln!("{label} lk {}", words.join(" "));
+                let invalid = |err: clap::Error| {
+                    Error::precondition(format!("Invalid alias step:\n{err}"))
+                };
+                let matches = Cli::command()
+                    .try_get_matches_from(std::iter::once(String::from("lk")).chain(words))
+                    .map_err(invalid)?;
+                let flags = given_global_flags(&matches);
+                if !flags.is_empty() {
+                    return Err(Error::precondition(format!(
+                        "Alias steps cannot set global flags ({}). Pass them before the alias name instead, as in `lk --dry-run {}`.",
+                        flags.join(", "),
+                        alias.name
+                    )));
+                }
+                let step_cli = Cli::from_arg_matches(&matches).map_err(invalid)?;
+                if matches!(step_cli.command, Commands::Alias(_)) {
+                    return Err(Error::precondition(
+                        "Alias steps cannot call other aliases.",
+                    ));
+                }
+                dispatch(repo, &step_cli.command, format)
+            }
+        });
+        if let Err(err) = result {
+            eprintln!(
+                "{label} stopped at step {} of {}",
+                index + 1,
+                alias.steps.len()
+            );
+            return Err(err);
+        }
     }
+    Ok(())
+}
 
-    let no_hook_args = [String::from("-c"), String::from(NO_HOOKS)];
-    // create iter from no_hook_args and command
-    let args = no_hook_args
-        .iter()
-        .map(|s| s.as_ref())
-        .chain(command.iter().map(|s| s.as_ref()));
+/// The global flags given on the command line `matches` was parsed from.
+fn given_global_flags(matches: &ArgMatches) -> Vec<String> {
+    ["remote", "dry_run", "offline", "format"]
+        .into_iter()
+        .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .map(|id| format!("--{}", id.replace('_', "-")))
+        .collect()
+}
 
-    git_command_status("run command without hooks", args)?;
+/// Prints the `lk` wrapper function for `shell` followed by its completions.
+fn shell_init(shell: Shell) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    writeln!(stdout, "{}", shell::wrapper(shell))
+        .map_err(|err| Error::io("Failed to write shell init script", err))?;
+    write_completions(shell, &mut stdout)
+}
 
-    Ok(())
+/// Writes the script that registers `lk`'s dynamic completions. The shell
+/// calls back into this binary with `LOKI_COMPLETE` set for candidates.
+fn write_completions(shell: Shell, out: &mut dyn Write) -> Result<()> {
+    let name = match shell {
+        Shell::Bash => "bash",
+        Shell::Zsh => "zsh",
+        Shell::Fish => "fish",
+        Shell::Pwsh => "powershell",
+    };
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(name)
+        .ok_or_else(|| Error::precondition(format!("No completions for {name}.")))?;
+    let exe = std::env::current_exe().map_err(|err| Error::io("Failed to locate lk", err))?;
+    completer
+        .write_registration(LOKI_COMPLETE, "lk", "lk", &exe.to_string_lossy(), out)
+        .map_err(|err| Error::io("Failed to write completions", err))
 }
 
-struct TimeRange {
-    start_ts: Option<i64>,
-    end_ts: i64,
-    start_label: String,
-    end_label: String,
-    end_is_latest: bool,
+/// Completion candidates from the repository in the current directory. Any
+/// failure, such as not being in a repository, yields no candidates.
+fn repo_candidates(list: fn(&Repo) -> Result<Vec<String>>) -> Vec<CompletionCandidate> {
+    Repo::open(".")
+        .and_then(|repo| list(&repo))
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
 }
 
-fn repo_stats(options: &RepoStatsOptions) -> Result<(), String> {
-    let progress = start_delayed_progress_meter("Computing repo stats...", Duration::from_secs(1));
+fn worktree_candidates() -> Vec<CompletionCandidate> {
+    repo_candidates(complete::worktree_names)
+}
 
-    let range = resolve_time_range(options)?;
-    if options.top == 0 {
-        return Err(String::from("--top must be greater than zero."));
-    }
+fn remote_branch_candidates() -> Vec<CompletionCandidate> {
+    repo_candidates(complete::remote_branches)
+}
 
-    // Phase 1: collect candidate commits in one `git log` pass.
-    let raw_commits = collect_raw_commits(options, &range)?;
+fn ref_candidates() -> Vec<CompletionCandidate> {
+    repo_candidates(complete::refs)
+}
 
-    // Phase 2: optionally deduplicate by patch-id.
-    let (effective_commits, duplicates_collapsed) = if options.no_dedup {
-        (raw_commits, 0usize)
-    } else {
-        let shas: Vec<&str> = raw_commits.iter().map(|c| c.sha.as_str()).collect();
-        let patch_ids = compute_patch_ids(&shas)?;
-        dedup_commits(raw_commits, &patch_ids)
+fn config_show(config: &Config, format: OutputFormat) -> Result<()> {
+    if format.is_json() {
+        let settings: std::collections::BTreeMap<_, _> = config.effective().into_iter().collect();
+        return format.json(&serde_json::json!({
+            "global_path": config.global_path,
+            "repo_path": config.repo_path,
+            "settings": settings,
+        }));
+    }
+
+    let describe = |path: &Option<std::path::PathBuf>| match path {
+        Some(path) if path.exists() => path.display().to_string(),
+        Some(path) => format!("{} (not found)", path.display()),
+        None => String::from("(none)"),
     };
+    println!("Global config: {}", describe(&config.global_path));
+    println!("Repo config:   {}", describe(&config.repo_path));
+    println!();
+
+    let rows = config.effective();
+    let width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, setting) in rows {
+        match setting {
+            Some(setting) => println!(
+                "{key:width$} = {}  {}",
+                setting.value.to_string().green(),
+                format!("({})", setting.source).dimmed()
+            ),
+            None => println!("{key:width$}   {}", "(unset)".dimmed()),
+        }
+    }
+    Ok(())
+}
 
-    // Phase 3: tally (filters, aliasing, active windows).
-    let mut totals: HashMap<String, usize> = HashMap::new();
-    let mut email_to_name: HashMap<String, String> = HashMap::new();
-    let mut email_aliases: HashMap<String, String> = HashMap::new();
-    let mut name_to_email: HashMap<String, String> = HashMap::new();
-    let mut latest_commit_date_in_range: Option<NaiveDate> = None;
-    let mut latest_commit_ts_by_author: HashMap<String, i64> = HashMap::new();
-    let mut oldest_commit_ts_by_author: HashMap<String, i64> = HashMap::new();
-
-    let name_filters_lower: Vec<String> = options.names.iter().map(|s| s.to_lowercase()).collect();
-    let email_filters_lower: Vec<String> =
-        options.emails.iter().map(|s| s.to_lowercase()).collect();
-
-    // `collect_raw_commits` preserves git log's reverse-chronological order, so
-    // the per-author "latest/oldest" tracking remains correct.
-    for commit in &effective_commits {
-        let email = if commit.email.is_empty() {
-            "Unknown"
-        } else {
-            commit.email.as_str()
-        };
-        let name = commit.name.as_str();
-        let canonical_email =
-            canonicalize_author(email, name, &mut email_aliases, &mut name_to_email);
+fn release(repo: &Repo, format: OutputFormat) -> Result<()> {
+    let remote = repo.remotes().fetch_remote();
+    let refspec = format!("{}:release", repo.config().trunk());
+    repo.git()
+        .status("push main to release", &["push", remote, refspec.as_str()])?;
+    format.json(&serde_json::json!({ "remote": remote, "refspec": refspec }))
+}
 
-        if !matches_author_filters_lowered(
-            name,
-            canonical_email.as_str(),
-            &name_filters_lower,
-            &email_filters_lower,
-        ) {
-            continue;
-        }
+fn no_hooks(repo: &Repo, command: &[impl AsRef<str>], format: OutputFormat) -> Result<()> {
+    if command.is_empty() {
+        return Err(Error::precondition("command cannot be empty."));
+    }
 
-        if !name.is_empty() {
-            email_to_name
-                .entry(canonical_email.clone())
-                .or_insert_with(|| name.to_string());
-        }
+    let no_hook_args = [String::from("-c"), String::from(NO_HOOKS)];
+    // create iter from no_hook_args and command
+    let args: Vec<&str> = no_hook_args
+        .iter()
+        .map(|s| s.as_ref())
+        .chain(command.iter().map(|s| s.as_ref()))
+        .collect();
 
-        let date = DateTime::from_timestamp(commit.timestamp, 0)
-            .ok_or_else(|| format!("Commit timestamp out of range: {}", commit.timestamp))?
-            .date_naive();
-        if latest_commit_date_in_range.is_none() {
-            latest_commit_date_in_range = Some(date);
-        } else if let Some(current_latest) = latest_commit_date_in_range {
-            if date > current_latest {
-                latest_commit_date_in_range = Some(date);
-            }
-        }
+    repo.git().status("run command without hooks", &args)?;
 
-        // Per-author windows: track min and max independently because dedup
-        // may have reordered commits relative to git log's stream.
-        latest_commit_ts_by_author
-            .entry(canonical_email.clone())
-            .and_modify(|ts| {
-                if commit.timestamp > *ts {
-                    *ts = commit.timestamp;
-                }
-            })
-            .or_insert(commit.timestamp);
-        oldest_commit_ts_by_author
-            .entry(canonical_email.clone())
-            .and_modify(|ts| {
-                if commit.timestamp < *ts {
-                    *ts = commit.timestamp;
-                }
-            })
-            .or_insert(commit.timestamp);
+    format.json(&serde_json::json!({ "args": args }))
+}
 
-        *totals.entry(canonical_email.clone()).or_insert(0) += 1;
+fn repo_stats(repo: &Repo, options: &RepoStatsOptions, format: OutputFormat) -> Result<()> {
+    if options.top == 0 {
+        return Err(Error::precondition("--top must be greater than zero."));
     }
 
+    let progress = start_delayed_progress_meter("Computing repo stats...", Duration::from_secs(1));
+    let report = stats::compute(repo, &StatsOptions::from(options))?;
     progress.finish();
 
-    if totals.is_empty() {
+    if format.is_json() {
+        return format.json(&report);
+    }
+
+    let range = &report.range;
+    if report.authors.is_empty() {
         if options.first_parent {
             println!(
                 "No first-parent commits found between {} and {}.",
@@ -405,28 +1143,11 @@ fn repo_stats(options: &RepoStatsOptions) -> Result<(), String> {
         return Ok(());
     }
 
-    let mut author_counts: Vec<(String, usize)> = totals.into_iter().collect();
-    author_counts.sort_by(|(email_a, count_a), (email_b, count_b)| {
-        count_b.cmp(count_a).then_with(|| email_a.cmp(email_b))
-    });
-
-    let total_commits: usize = author_counts.iter().map(|(_, count)| *count).sum();
-    let unique_authors = author_counts.len();
-    let display_author_counts: Vec<(String, usize)> =
-        author_counts.iter().take(options.top).cloned().collect();
-
-    let resolved_end_label = if range.end_is_latest {
-        latest_commit_date_in_range
-            .map(|date| format!("{date} (latest commit)"))
-            .unwrap_or_else(|| range.end_label.clone())
-    } else {
-        range.end_label.clone()
-    };
-
     // Dashboard-style stats list
     println!("Repository Statistics");
-    println!("  Range: {} to {}", range.start_label, resolved_end_label);
-    let total_commits_str = total_commits.to_string().green();
+    println!("  Range: {} to {}", range.start_label, report.end_label());
+    let total_commits_str = report.total_commits.to_string().green();
+    let duplicates_collapsed = report.duplicates_collapsed;
     if duplicates_collapsed > 0 {
         println!(
             "  Total commits: {total_commits_str} ({} duplicate patch{} collapsed; --no-dedup to disable)",
@@ -436,522 +1157,39 @@ fn repo_stats(options: &RepoStatsOptions) -> Result<(), String> {
     } else {
         println!("  Total commits: {total_commits_str}");
     }
-    println!("  Authors: {}", unique_authors.to_string().green());
+    println!("  Authors: {}", report.authors.len().to_string().green());
 
-    let display_author_counts_with_names: Vec<(String, usize)> = display_author_counts
-        .into_iter()
-        .map(|(email, count)| {
-            let display = if let Some(name) = email_to_name.get(&email) {
-                format!("{} <{}>", name, email)
-            } else {
-                email
-            };
-            (display, count)
-        })
-        .collect();
-    print_author_graph(
-        &display_author_counts_with_names,
-        &latest_commit_ts_by_author,
-        &oldest_commit_ts_by_author,
-    );
+    let top = report.authors.len().min(options.top);
+    print_author_graph(&report.authors[..top]);
 
     Ok(())
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct RawCommit {
-    sha: String,
-    timestamp: i64,
-    name: String,
-    email: String,
-}
-
-fn collect_raw_commits(
-    options: &RepoStatsOptions,
-    range: &TimeRange,
-) -> Result<Vec<RawCommit>, String> {
-    let mut git_args: Vec<String> = vec!["log".to_string()];
-    if options.first_parent {
-        git_args.push("--first-parent".to_string());
-    }
-    git_args.push("--pretty=format:%H%x09%ct%x09%an%x09%ae".to_string());
-    if let Some(start_ts) = range.start_ts {
-        git_args.push(format!("--since=@{start_ts}"));
-    }
-    if !range.end_is_latest {
-        git_args.push(format!("--until=@{}", range.end_ts));
-    }
-    git_args.push("HEAD".to_string());
-
-    let mut child = Command::new("git")
-        .args(git_args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .map_err(|err| format!("collect author stats failed to start: {err}"))?;
-    let stdout = child
-        .stdout
-        .take()
-        .ok_or_else(|| String::from("collect author stats failed to capture stdout"))?;
-    let reader = std::io::BufReader::new(stdout);
-
-    let mut commits = Vec::new();
-    for raw_line in reader.lines() {
-        let raw_line = raw_line.map_err(|err| format!("Failed to read git log output: {err}"))?;
-        let trimmed = raw_line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-
-        let mut parts = trimmed.splitn(4, '\t');
-        let (sha_part, timestamp_part, name_part, email_part) = match (
-            parts.next(),
-            parts.next(),
-            parts.next(),
-            parts.next(),
-        ) {
-            (Some(sha), Some(ts), Some(name), Some(email)) => (sha, ts, name, email),
-            _ => {
-                return Err(format!(
-                        "Unexpected git log output (expected `<sha>\\t<timestamp>\\t<name>\\t<email>`): `{trimmed}`"
-                    ));
-            }
-        };
-        if sha_part.is_empty() || timestamp_part.is_empty() {
-            return Err(format!(
-                "Unexpected git log output (expected `<sha>\\t<timestamp>\\t<name>\\t<email>`): `{trimmed}`"
-            ));
-        }
-
-        let timestamp = timestamp_part.parse::<i64>().map_err(|err| {
-            format!("Failed to parse git log timestamp `{timestamp_part}`: {err}")
-        })?;
-
-        commits.push(RawCommit {
-            sha: sha_part.to_string(),
-            timestamp,
-            name: name_part.trim().to_string(),
-            email: email_part.trim().to_string(),
-        });
-    }
-
-    let status = child
-        .wait()
-        .map_err(|err| format!("collect author stats failed to wait: {err}"))?;
-    if !status.success() {
-        return Err(format!(
-            "collect author stats failed with exit code: {}",
-            status.code().unwrap_or(-1)
-        ));
-    }
-
-    Ok(commits)
-}
-
-/// Resolve each commit SHA to a `git patch-id --stable` value, when one
-/// exists. Returns a map from SHA → patch-id. Commits with no patch
-/// (empty diffs, merge commits) simply won't appear in the map.
-fn compute_patch_ids(shas: &[&str]) -> Result<HashMap<String, String>, String> {
-    if shas.is_empty() {
-        return Ok(HashMap::new());
-    }
-
-    // git diff-tree --stdin -p < SHAs  |  git patch-id --stable
-    let mut diff_tree = Command::new("git")
-        .args(["diff-tree", "--stdin", "-p"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .map_err(|err| format!("failed to spawn `git diff-tree`: {err}"))?;
-
-    let diff_tree_stdout = diff_tree
-        .stdout
-        .take()
-        .ok_or_else(|| String::from("failed to capture `git diff-tree` stdout"))?;
-
-    let mut patch_id = Command::new("git")
-        .args(["patch-id", "--stable"])
-        .stdin(Stdio::from(diff_tree_stdout))
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .map_err(|err| format!("failed to spawn `git patch-id`: {err}"))?;
-
-    // Writer thread feeds SHAs into diff-tree's stdin; the chained patch-id
-    // process consumes diff-tree's stdout concurrently to avoid deadlock on
-    // a full pipe buffer.
-    let mut diff_tree_stdin = diff_tree
-        .stdin
-        .take()
-        .ok_or_else(|| String::from("failed to capture `git diff-tree` stdin"))?;
-    let shas_owned: Vec<String> = shas.iter().map(|s| s.to_string()).collect();
-    let writer = std::thread::spawn(move || -> std::io::Result<()> {
-        for sha in &shas_owned {
-            writeln!(diff_tree_stdin, "{sha}")?;
-        }
-        drop(diff_tree_stdin);
-        Ok(())
-    });
-
-    let patch_id_stdout = patch_id
-        .stdout
-        .take()
-        .ok_or_else(|| String::from("failed to capture `git patch-id` stdout"))?;
-    let reader = std::io::BufReader::new(patch_id_stdout);
-    let mut map: HashMap<String, String> = HashMap::new();
-    for line in reader.lines() {
-        let line = line.map_err(|err| format!("Failed to read patch-id output: {err}"))?;
-        // Each line is "<patch-id> <commit-sha>".
-        let mut parts = line.split_whitespace();
-        let (Some(pid), Some(commit_sha)) = (parts.next(), parts.next()) else {
-            continue;
-        };
-        map.insert(commit_sha.to_string(), pid.to_string());
-    }
-
-    writer
-        .join()
-        .map_err(|_| String::from("patch-id writer thread panicked"))?
-        .map_err(|err| format!("failed writing SHAs to `git diff-tree`: {err}"))?;
-
-    let diff_tree_status = diff_tree
-        .wait()
-        .map_err(|err| format!("`git diff-tree` failed to wait: {err}"))?;
-    if !diff_tree_status.success() {
-        return Err(format!(
-            "`git diff-tree` failed with exit code: {}",
-            diff_tree_status.code().unwrap_or(-1)
-        ));
-    }
-    let patch_id_status = patch_id
-        .wait()
-        .map_err(|err| format!("`git patch-id` failed to wait: {err}"))?;
-    if !patch_id_status.success() {
-        return Err(format!(
-            "`git patch-id` failed with exit code: {}",
-            patch_id_status.code().unwrap_or(-1)
-        ));
-    }
-
-    Ok(map)
-}
-
-/// Collapse commits that share a `git patch-id`. Commits without an
-/// entry in `patch_ids` are treated as unique (their SHA becomes their
-/// own dedup key). For each patch-id group, the winner is the commit
-/// with the smallest `(timestamp, sha)` tuple — i.e. earliest author
-/// date, with SHA breaking ties deterministically.
-///
-/// Returns `(winners, duplicates_collapsed)`.
-fn dedup_commits(
-    commits: Vec<RawCommit>,
-    patch_ids: &HashMap<String, String>,
-) -> (Vec<RawCommit>, usize) {
-    let original_count = commits.len();
-    let mut by_key: HashMap<String, RawCommit> = HashMap::new();
-    for commit in commits {
-        let key = match patch_ids.get(&commit.sha) {
-            Some(pid) => format!("p:{pid}"),
-            None => format!("s:{}", commit.sha),
-        };
-        match by_key.entry(key) {
-            std::collections::hash_map::Entry::Vacant(e) => {
-                e.insert(commit);
-            }
-            std::collections::hash_map::Entry::Occupied(mut e) => {
-                let existing = e.get();
-                let new_key = (commit.timestamp, commit.sha.as_str());
-                let existing_key = (existing.timestamp, existing.sha.as_str());
-                if new_key < existing_key {
-                    e.insert(commit);
-                }
-            }
-        }
-    }
-    let winners: Vec<RawCommit> = by_key.into_values().collect();
-    let dups = original_count - winners.len();
-    (winners, dups)
-}
-
-fn canonicalize_author(
-    email: &str,
-    name: &str,
-    email_aliases: &mut HashMap<String, String>,
-    name_to_email: &mut HashMap<String, String>,
-) -> String {
-    if let Some(existing_email) = email_aliases.get(email) {
-        return existing_email.clone();
-    }
-
-    if !name.is_empty() {
-        if let Some(existing_email) = name_to_email.get(name) {
-            email_aliases.insert(email.to_string(), existing_email.clone());
-            return existing_email.clone();
-        } else {
-            name_to_email.insert(name.to_string(), email.to_string());
-        }
-    }
-
-    let canonical = email.to_string();
-    email_aliases
-        .entry(canonical.clone())
-        .or_insert_with(|| canonical.clone());
-    canonical
-}
-
-fn matches_author_filters_lowered(
-    name: &str,
-    email: &str,
-    name_filters_lower: &[String],
-    email_filters_lower: &[String],
-) -> bool {
-    if !name_filters_lower.is_empty() {
-        if name.is_empty() {
-            return false;
-        }
-        let name_lower = name.to_lowercase();
-        if !name_filters_lower
-            .iter()
-            .any(|filter| name_lower.contains(filter))
-        {
-            return false;
-        }
-    }
-
-    if !email_filters_lower.is_empty() {
-        if email.is_empty() {
-            return false;
-        }
-        let email_lower = email.to_lowercase();
-        if !email_filters_lower
-            .iter()
-            .any(|filter| email_lower.contains(filter))
-        {
-            return false;
-        }
-    }
-
-    true
-}
-
-fn active_weeks_inclusive(latest_ts: i64, oldest_ts: i64) -> f64 {
-    let span_seconds = latest_ts.saturating_sub(oldest_ts).max(0);
-    // Inclusive day window keeps single-commit authors reasonable (1 day => 1/7 week),
-    // and still properly boosts authors who only started partway through the range.
-    let active_days = (span_seconds / 86_400) + 1;
-    (active_days as f64) / 7.0
-}
-
-fn active_days_inclusive(latest_ts: i64, oldest_ts: i64) -> i64 {
-    let span_seconds = latest_ts.saturating_sub(oldest_ts).max(0);
-    (span_seconds / 86_400) + 1
-}
-
-fn format_active_span(latest_ts: i64, oldest_ts: i64) -> String {
-    // Use an average Gregorian year/month to avoid jumpy “calendar” math.
-    let days = active_days_inclusive(latest_ts, oldest_ts) as f64;
-    let years = days / 365.25;
-    if years >= 1.0 {
-        let rounded = (years * 10.0).round() / 10.0;
-        let unit = if (rounded - 1.0).abs() < 1e-9 {
-            "year"
-        } else {
-            "years"
-        };
-        format!("{rounded:.1} {unit}")
-    } else {
-        let months = days / (365.25 / 12.0);
-        let rounded = (months * 10.0).round() / 10.0;
-        let unit = if (rounded - 1.0).abs() < 1e-9 {
-            "month"
-        } else {
-            "months"
-        };
-        format!("{rounded:.1} {unit}")
-    }
-}
-
-fn print_author_graph(
-    author_counts: &[(String, usize)],
-    latest_commit_ts_by_author: &HashMap<String, i64>,
-    oldest_commit_ts_by_author: &HashMap<String, i64>,
-) {
-    if author_counts.is_empty() {
-        return;
-    }
+fn print_author_graph(authors: &[AuthorStats]) {
+    if authors.is_empty() {
+        return;
+    }
 
     const MIN_COMMITS_FOR_RATE: usize = 3;
 
     println!("Commits by author:");
-    for (author_display, count) in author_counts {
+    for author in authors {
         // Color the count green
-        let count_str = count.to_string().green();
-
-        // Colorize email addresses (extract email from "Name <email>" format or use as-is)
-        let colored_author = if let Some(start) = author_display.find('<') {
-            if let Some(end) = author_display.find('>') {
-                let name = &author_display[..start].trim();
-                let email = &author_display[start + 1..end];
-                format!("{} <{}>", name, email.yellow().to_string())
-            } else {
-                author_display.yellow().to_string()
-            }
-        } else {
-            // If no angle brackets, assume the whole thing is an email
-            author_display.yellow().to_string()
-        };
+        let count_str = author.commits.to_string().green();
 
-        let commits_per_week_suffix: String = if *count >= MIN_COMMITS_FOR_RATE {
-            let email_key = extract_email_key(author_display);
-            email_key
-                .and_then(|email| {
-                    let latest_ts = latest_commit_ts_by_author.get(email)?;
-                    let oldest_ts = oldest_commit_ts_by_author.get(email)?;
-                    let weeks = active_weeks_inclusive(*latest_ts, *oldest_ts);
-                    if weeks <= 0.0 {
-                        return None;
-                    }
-                    let commits_per_week = (*count as f64) / weeks;
-                    let span = format_active_span(*latest_ts, *oldest_ts);
-                    Some(
-                        format!("({commits_per_week:.1}/wk over {span})")
-                            .purple()
-                            .to_string(),
-                    )
-                })
-                .unwrap_or_default()
-        } else {
-            String::new()
+        let colored_author = match &author.name {
+            Some(name) => format!("{name} <{}>", author.email.yellow()),
+            None => author.email.yellow().to_string(),
         };
 
-        if commits_per_week_suffix.is_empty() {
-            println!("({count_str}) {colored_author}");
+        if author.commits >= MIN_COMMITS_FOR_RATE {
+            let span = format_active_span(author.latest_ts, author.oldest_ts);
+            let rate = format!("({:.1}/wk over {span})", author.commits_per_week()).purple();
+            println!("({count_str}) {colored_author} {rate}");
         } else {
-            println!("({count_str}) {colored_author} {commits_per_week_suffix}");
-        }
-    }
-}
-
-fn extract_email_key(author_display: &str) -> Option<&str> {
-    if let Some(start) = author_display.find('<') {
-        let end = author_display.rfind('>')?;
-        (start < end).then(|| author_display[start + 1..end].trim())
-    } else {
-        Some(author_display.trim())
-    }
-}
-
-fn resolve_time_range(options: &RepoStatsOptions) -> Result<TimeRange, String> {
-    let now = Utc::now();
-    let (reference_end_dt, end_label, end_is_latest, end_ts) = if let Some(to_date) = options.to {
-        let end_naive = to_date
-            .and_hms_opt(23, 59, 59)
-            .ok_or_else(|| String::from("invalid --to date"))?;
-        let dt = DateTime::<Utc>::from_naive_utc_and_offset(end_naive, Utc);
-        (dt, to_date.to_string(), false, dt.timestamp())
-    } else {
-        (now, String::from("latest commit"), true, i64::MAX)
-    };
-
-    let mut start_label = String::from("initial commit");
-    let mut start_dt: Option<DateTime<Utc>> = None;
-
-    if let Some(from_date) = options.from {
-        let from_naive = from_date
-            .and_hms_opt(0, 0, 0)
-            .ok_or_else(|| String::from("invalid --from date"))?;
-        start_dt = Some(DateTime::<Utc>::from_naive_utc_and_offset(from_naive, Utc));
-        start_label = from_date.to_string();
-    } else if let Some(days) = options.days {
-        if days == 0 {
-            return Err(String::from("--days must be greater than zero."));
-        }
-        let dt = reference_end_dt - ChronoDuration::days(days as i64);
-        start_label = format!(
-            "{} (last {} day{})",
-            dt.format("%Y-%m-%d"),
-            days,
-            if days == 1 { "" } else { "s" }
-        );
-        start_dt = Some(dt);
-    } else if let Some(weeks) = options.weeks {
-        if weeks == 0 {
-            return Err(String::from("--weeks must be greater than zero."));
-        }
-        let dt = reference_end_dt - ChronoDuration::weeks(weeks as i64);
-        start_label = format!(
-            "{} (last {} week{})",
-            dt.format("%Y-%m-%d"),
-            weeks,
-            if weeks == 1 { "" } else { "s" }
-        );
-        start_dt = Some(dt);
-    } else if let Some(months_count) = options.months {
-        if months_count == 0 {
-            return Err(String::from("--months must be greater than zero."));
-        }
-        let end_date = reference_end_dt.date_naive();
-        let start_date = end_date
-            .checked_sub_months(Months::new(months_count))
-            .ok_or_else(|| String::from("month subtraction overflow"))?;
-        let start_naive = start_date
-            .and_hms_opt(0, 0, 0)
-            .ok_or_else(|| String::from("invalid computed month range"))?;
-        let dt = DateTime::<Utc>::from_naive_utc_and_offset(start_naive, Utc);
-        start_label = format!(
-            "{} (last {} month{})",
-            start_date,
-            months_count,
-            if months_count == 1 { "" } else { "s" }
-        );
-        start_dt = Some(dt);
-    }
-
-    if let Some(start_dt_value) = start_dt {
-        if start_dt_value > reference_end_dt {
-            return Err(String::from(
-                "The computed start date occurs after the end date. Check your filters.",
-            ));
+            println!("({count_str}) {colored_author}");
         }
     }
-
-    Ok(TimeRange {
-        start_ts: start_dt.map(|dt| dt.timestamp()),
-        end_ts,
-        start_label,
-        end_label,
-        end_is_latest,
-    })
-}
-
-fn matches_author_filters(name: &str, email: &str, options: &RepoStatsOptions) -> bool {
-    if !options.names.is_empty()
-        && (name.is_empty()
-            || !options
-                .names
-                .iter()
-                .any(|filter| name.to_lowercase().contains(&filter.to_lowercase())))
-    {
-        return false;
-    }
-
-    if !options.emails.is_empty()
-        && (email.is_empty()
-            || !options
-                .emails
-                .iter()
-                .any(|filter| email.to_lowercase().contains(&filter.to_lowercase())))
-    {
-        return false;
-    }
-
-    true
-}
-
-fn parse_naive_date(value: &str) -> Result<NaiveDate, String> {
-    NaiveDate::parse_from_str(value, "%Y-%m-%d")
-        .map_err(|err| format!("Invalid date `{value}` (expected YYYY-MM-DD): {err}"))
 }
 
 struct ProgressMeter {
@@ -1010,11 +1248,17 @@ fn start_delayed_progress_meter(message: &'static str, delay: Duration) -> Progr
     }
 }
 
-fn rebase(target: &str, interactive: bool) -> Result<(), String> {
+fn rebase(repo: &Repo, target: &str, interactive: bool, format: OutputFormat) -> Result<()> {
     let fetch_target = format!("{target}:{target}");
-    git_command_status(
+    repo.git().status(
         "fetch target",
-        vec!["-c", NO_HOOKS, "fetch", "origin", fetch_target.as_str()],
+        &[
+            "-c",
+            NO_HOOKS,
+            "fetch",
+            repo.remotes().fetch_remote(),
+            fetch_target.as_str(),
+        ],
     )?;
 
     let mut rebase_args = vec!["-c", NO_HOOKS, "rebase"];
@@ -1023,24 +1267,76 @@ fn rebase(target: &str, interactive: bool) -> Result<(), String> {
     }
     rebase_args.push(target);
 
-    git_command_status("rebase", rebase_args)?;
+    repo.git().status("rebase", &rebase_args)?;
 
-    Ok(())
+    format.json(&serde_json::json!({ "target": target, "interactive": interactive }))
+}
+
+fn save(repo: &Repo, options: &CommitOptions, format: OutputFormat) -> Result<PushedBranch> {
+    commit_branch(repo, options, "save")?;
+    let message = create_commit(repo, options)?;
+    let pushed = push_branch(repo, false, false)?;
+    format.json(&serde_json::json!({ "message": message, "pushed": pushed }))?;
+    Ok(pushed)
+}
+
+fn commit(repo: &Repo, options: &CommitOptions, format: OutputFormat) -> Result<()> {
+    commit_branch(repo, options, "commit")?;
+    let message = create_commit(repo, options)?;
+    format.json(&serde_json::json!({ "message": message }))
 }
 
-fn save(options: &CommitOptions) -> Result<(), String> {
-    commit(options)?;
-    push_branch(false)?;
+/// Makes sure `action` doesn't commit to a protected branch: with
+/// `--new-branch` the changes move to a new branch first, otherwise HEAD must
+/// not be protected unless `--allow-protected` is given.
+fn commit_branch(repo: &Repo, options: &CommitOptions, action: &str) -> Result<()> {
+    let Some(name) = &options.new_branch else {
+        return guard_protected(repo, action, options.allow_protected);
+    };
+    let config = repo.config();
+    new_branch(
+        repo,
+        std::slice::from_ref(name),
+        config.resolve(config::BRANCH_PREFIX, None),
+        String::from("HEAD"),
+        true,
+        options.allow_protected,
+    )?;
     Ok(())
 }
 
-fn commit(
+/// Refuses `action` when the current branch matches a `branch.protected`
+/// glob, unless `allow` is set.
+fn guard_protected(repo: &Repo, action: &str, allow: bool) -> Result<()> {
+    if allow {
+        return Ok(());
+    }
+    let current = git_current_branch(repo.git())?;
+    let Some(glob) = branch::is_protected(repo.config(), &current) else {
+        return Ok(());
+    };
+    let hint = if action == "push" {
+        format!("Pass --allow-protected to {action} anyway.")
+    } else {
+        format!(
+            "Run `lk {action} --new-branch <name>` to move your changes to a new branch, or pass --allow-protected to {action} anyway."
+        )
+    };
+    Err(Error::precondition(format!(
+        "{current} is protected (branch.protected = \"{glob}\"). {hint}"
+    )))
+}
+
+/// Stages (per the options) and commits, returning the commit message used.
+fn create_commit(
+    repo: &Repo,
     CommitOptions {
         all,
         update,
         message,
+        ..
     }: &CommitOptions,
-) -> Result<(), String> {
+) -> Result<String> {
     let add_type = if *update {
         Some("--update")
     } else if *all {
@@ -1063,104 +1359,456 @@ fn commit(
 
     commands.push(("commit", vec!["commit", "--message", message.as_str()]));
 
-    git_commands_status(commands)?;
+    git_commands_status(repo.git(), &commands)?;
 
-    Ok(())
+    Ok(message)
 }
 
-fn new_branch(name: &[String], prefix: Option<&str>) -> Result<(), String> {
+/// Creates and pushes a branch from `base`. Unless `base` is `HEAD` the
+/// remote is fetched first, uncommitted changes need `carry`, and commits
+/// only HEAD has are pointed out since the new branch won't have them. A
+/// name matching `branch.protected` needs `allow_protected`.
+fn new_branch(
+    repo: &Repo,
+    name: &[String],
+    prefix: Option<Setting>,
+    base: String,
+    carry: bool,
+    allow_protected: bool,
+) -> Result<PushedBranch> {
     if name.is_empty() {
-        return Err(String::from("name cannot be empty."));
+        return Err(Error::precondition("name cannot be empty."));
     }
 
-    let mut name = name.join("-");
+    let git = repo.git();
+    let remotes = repo.remotes();
+    let prefix = prefix.as_ref().and_then(|prefix| {
+        let value = prefix.as_str()?;
+        eprintln!("Using branch prefix `{value}` (from {}).", prefix.source);
+        Some(value)
+    });
+    let name = Template::new(repo.config())?.branch(repo, prefix, &name.join(" "))?;
+    branch::validate(repo, &name)?;
+    if let Some(glob) = branch::is_protected(repo.config(), &name).filter(|_| !allow_protected) {
+        return Err(Error::precondition(format!(
+            "{name} is protected (branch.protected = \"{glob}\"). Pick another name, or pass --allow-protected to use it anyway."
+        )));
+    }
+
+    let push_remote = remotes.push_remote(git, &name);
+    let mut create_args = vec!["switch", "--create", name.as_str()];
+    let mut online = !repo.config().offline();
 
-    if let Some(prefix) = prefix {
-        eprintln!("Using branch prefix `{prefix}` (set via --prefix or {LOKI_NEW_PREFIX}).");
-        name = format!("{prefix}{name}");
+    if !base.eq_ignore_ascii_case("head") {
+        if !carry && has_uncommitted_changes(git) {
+            return Err(Error::precondition(format!(
+                "You have uncommitted changes. Commit or stash them, or pass --carry to take them to {name} (or --from HEAD to branch from here)."
+            )));
+        }
+        if remotes.is_triangular(git) {
+            eprintln!(
+                "Fork workflow: branching from {} and pushing to {}",
+                base.cyan(),
+                push_remote.cyan()
+            );
+        } else {
+            eprintln!("Branching from {}", base.cyan());
+        }
+        if online {
+            eprintln!("Fetching latest from {}…", remotes.fetch_remote());
+        }
+        pending::fetch(repo, remotes.fetch_remote(), &mut online)?;
+        warn_unpushed_commits(git, &base, &name);
+        create_args.extend(["--no-track", base.as_str()]);
     }
 
-    git::git_commands_status(vec![
-        (
-            "create new branch",
-            vec!["switch", "--create", name.as_str()],
-        ),
-        (
-            "push to origin",
-            vec!["push", "--set-upstream", "origin", name.as_str()],
-        ),
-    ])?;
+    git.status("create new branch", &create_args)?;
+    let outcome = pending::push(
+        repo,
+        PendingPush::new(&name, &push_remote, false),
+        online,
+        false,
+    )?;
 
-    Ok(())
+    Ok(PushedBranch::new(name, push_remote).with_outcome(outcome))
 }
 
-fn push_branch(force: bool) -> Result<(), String> {
-    let current_branch = git_current_branch()?;
+/// True when tracked files have staged or unstaged changes.
+fn has_uncommitted_changes(git: &dyn GitRunner) -> bool {
+    git.stdout(
+        "check for uncommitted changes",
+        &["status", "--porcelain", "--untracked-files=no"],
+    )
+    .is_ok_and(|lines| lines.iter().any(|line| !line.trim().is_empty()))
+}
+
+/// Warns about commits on HEAD that no remote has and `base` doesn't
+/// contain, since a branch made from `base` leaves them behind.
+fn warn_unpushed_commits(git: &dyn GitRunner, base: &str, branch: &str) {
+    let count = git
+        .stdout(
+            "count unpushed commits",
+            &["rev-list", "--count", "HEAD", "--not", base, "--remotes"],
+        )
+        .ok()
+        .and_then(|lines| lines.first()?.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if count == 0 {
+        return;
+    }
+    let current = git_current_branch(git).unwrap_or_else(|_| String::from("HEAD"));
+    eprintln!(
+        "{} {} has {count} unpushed commit{} that {branch} won't include.",
+        "warning:".yellow(),
+        current.cyan(),
+        if count == 1 { "" } else { "s" },
+    );
+}
+
+/// Pushes the current branch. A rejected push fails with the reason; with
+/// `rebase_on_reject` a non-fast-forward is retried once after rebasing onto
+/// the remote branch.
+fn push_branch(repo: &Repo, force: bool, rebase_on_reject: bool) -> Result<PushedBranch> {
+    let git = repo.git();
+    let current_branch = git_current_branch(git)?;
 
     if current_branch.eq_ignore_ascii_case("head") {
-        return Err(String::from(
+        return Err(Error::precondition(
             "HEAD is currently detached, no branch to push!",
         ));
     }
 
-    let mut args = vec!["push", "--set-upstream"];
-    if force {
-        args.push("--force-with-lease");
+    let push_remote = repo.remotes().push_remote(git, &current_branch);
+    let online = !repo.config().offline();
+    let push = PendingPush::new(&current_branch, &push_remote, force);
+    let mut rebased = false;
+    let outcome = match pending::push(repo, push.clone(), online, false) {
+        Err(err)
+            if rebase_on_reject
+                && err.stderr().and_then(Rejection::parse) == Some(Rejection::NonFastForward) =>
+        {
+            eprintln!(
+                "{} {push_remote}/{current_branch} has commits you don't have; rebasing onto it and pushing again.",
+                "rejected:".yellow()
+            );
+            rejection::rebase_onto_remote(repo, &push_remote, &current_branch)?;
+            rebased = true;
+            pending::push(repo, push, online, false)
+        }
+        result => result,
+    }
+    .map_err(|err| rejection::explain(err, &push_remote, &current_branch))?;
+
+    let mut pushed = PushedBranch::new(current_branch, push_remote).with_outcome(outcome);
+    pushed.rebased = rebased;
+    Ok(pushed)
+}
+
+/// Creates a branch from HEAD and records the current branch as its parent.
+fn stack_new(repo: &Repo, name: &[String], prefix: Option<Setting>) -> Result<PushedBranch> {
+    let git = repo.git();
+    let parent = git_current_branch(git)?;
+    if parent.eq_ignore_ascii_case("head") {
+        return Err(Error::precondition(
+            "HEAD is currently detached, check out the parent branch first.",
+        ));
     }
-    args.push("origin");
-    args.push(current_branch.as_str());
-    let args = args;
+    let parent_tip = stack::tip(git, "HEAD")
+        .ok_or_else(|| Error::precondition("The current branch has no commits to stack on."))?;
 
-    git_command_status("push", args)?;
+    let pushed = new_branch(repo, name, prefix, String::from("HEAD"), true, true)?;
+    stack::set_parent(git, &pushed.branch, &parent, &parent_tip)?;
+    eprintln!("Stacked {} on {}", pushed.branch.green(), parent.cyan());
+    Ok(pushed)
+}
 
+fn stack_list(repo: &Repo, format: OutputFormat) -> Result<()> {
+    let trees = stack::trees(&stack::branches(repo.git()));
+    if format.is_json() {
+        return format.json(&trees);
+    }
+    if trees.is_empty() {
+        println!("No stacked branches. Create one with `lk stack new <name>`.");
+        return Ok(());
+    }
+
+    fn print(node: &stack::Node, depth: usize, current: &str) {
+        let label = if node.branch == current {
+            format!("{} *", node.branch.green().bold())
+        } else {
+            node.branch.clone()
+        };
+        if depth == 0 {
+            println!("{label}");
+        } else {
+            println!("{}└─ {label}", "   ".repeat(depth - 1));
+        }
+        for child in &node.children {
+            print(child, depth + 1, current);
+        }
+    }
+    let current = git_current_branch(repo.git()).unwrap_or_default();
+    for tree in &trees {
+        print(tree, 0, &current);
+    }
     Ok(())
 }
 
-fn pull_prune() -> Result<(), String> {
-    prune("pull")
+/// Restacks every recorded branch, then returns to the branch we started on.
+fn stack_restack(repo: &Repo, format: OutputFormat) -> Result<()> {
+    #[derive(Serialize)]
+    struct RestackedBranch {
+        branch: String,
+        parent: String,
+        result: Restacked,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pushed: Option<PushedBranch>,
+    }
+
+    let git = repo.git();
+    let original = git_current_branch(git)?;
+    let order = stack::restack_order(&stack::branches(git));
+    if order.is_empty() {
+        eprintln!("No stacked branches. Create one with `lk stack new <name>`.");
+        return format.json(&Vec::<RestackedBranch>::new());
+    }
+
+    let mut report = Vec::new();
+    for stacked in order {
+        let result = stack::restack(repo, &stacked)?;
+        let pushed = match result {
+            Restacked::Rebased => {
+                eprintln!(
+                    "Restacked {} onto {}",
+                    stacked.branch.green(),
+                    stacked.parent.cyan()
+                );
+                Some(push_branch(repo, true, false)?)
+            }
+            Restacked::UpToDate => {
+                eprintln!(
+                    "{} is up to date with {}",
+                    stacked.branch,
+                    stacked.parent.cyan()
+                );
+                None
+            }
+            Restacked::MissingParent => {
+                eprintln!(
+                    "{} skipped {}: its parent {} no longer exists.",
+                    "warning:".yellow(),
+                    stacked.branch,
+                    stacked.parent.cyan()
+                );
+                None
+            }
+        };
+        report.push(RestackedBranch {
+            branch: stacked.branch,
+            parent: stacked.parent,
+            result,
+            pushed,
+        });
+    }
+
+    if !original.eq_ignore_ascii_case("head")
+        && git_current_branch(git).is_ok_and(|current| current != original)
+    {
+        git.status_quiet("switch back", &["switch", original.as_str()])?;
+    }
+    format.json(&report)
 }
 
-fn fetch_prune() -> Result<(), String> {
-    prune("fetch")
+/// Replays the pushes queued while offline.
+fn push_pending(repo: &Repo, format: OutputFormat) -> Result<()> {
+    let report = pending::replay(repo)?;
+    if format.is_json() {
+        return format.json(&report);
+    }
+
+    if report.pushed.is_empty() && report.failed.is_empty() {
+        eprintln!("No pending pushes.");
+        return Ok(());
+    }
+    for push in &report.pushed {
+        eprintln!("Pushed {} to {}", push.branch.green(), push.remote.cyan());
+    }
+    for failed in &report.failed {
+        eprintln!(
+            "Still pending: {} to {} ({})",
+            failed.push.branch.yellow(),
+            failed.push.remote.cyan(),
+            failed.error
+        );
+    }
+    if !report.failed.is_empty() {
+        return Err(Error::precondition(format!(
+            "{} push{} still pending.",
+            report.failed.len(),
+            if report.failed.len() == 1 { "" } else { "es" }
+        )));
+    }
+    Ok(())
 }
 
-fn prune(cmd: &str) -> Result<(), String> {
-    let current_branch = git_current_branch()?;
-    let branches = git_branches()?;
+fn prune(
+    repo: &Repo,
+    source: PruneSource,
+    options: &PruneOptions,
+    format: OutputFormat,
+) -> Result<PruneReport> {
+    let report = pruning::prune(repo, source, options)?;
+    if format.is_json() {
+        format.json(&report)?;
+        return Ok(report);
+    }
 
-    let mut pruned_branches = Vec::new();
+    for line in &report.lines {
+        match &line.pruned {
+            Some(pruned) => println!("{}", highlight_pruned_branch_line(&line.text, pruned)),
+            None => println!("{}", line.text),
+        }
+    }
 
-    for line in git_command_iter("pull with pruning", vec![cmd, "--prune"])? {
-        if let Some(pruned_branch) = is_pruned_branch(line.clone()) {
-            println!("{}", highlight_pruned_branch_line(&line, &pruned_branch));
-            if branches.contains(&pruned_branch) && pruned_branch != current_branch {
-                pruned_branches.push(pruned_branch);
-            }
-        } else {
-            println!("{line}");
+    print_prune_report(
+        repo,
+        &report,
+        |_| String::from("pruned from remote"),
+        format,
+    )?;
+    Ok(report)
+}
+
+/// `lk prune`: deletes gone and/or merged local branches. Merged branches
+/// are listed and confirmed first unless `yes` is set or this is a dry run.
+fn prune_local(
+    repo: &Repo,
+    options: &PruneOptions,
+    yes: bool,
+    format: OutputFormat,
+) -> Result<PruneReport> {
+    let plan = pruning::plan(repo, options)?;
+    let merged: Vec<&PrunableBranch> = plan
+        .branches
+        .iter()
+        .filter(|candidate| candidate.reason != PruneReason::Gone)
+        .collect();
+    if !merged.is_empty() && !yes && !repo.git().is_dry_run() {
+        for candidate in &merged {
+            eprintln!(
+                "  {} ({})",
+                highlight_branch_name(&candidate.branch),
+                candidate.reason
+            );
+        }
+        let question = format!(
+            "Delete {} merged branch{}?",
+            merged.len(),
+            if merged.len() == 1 { "" } else { "es" }
+        );
+        if !confirm(&question)? {
+            return Err(Error::precondition("Nothing was deleted."));
         }
     }
 
-    if pruned_branches.is_empty() {
+    let report = pruning::delete(repo, plan, options.remove_worktrees)?;
+    let reasons: HashMap<&str, String> = report
+        .planned
+        .iter()
+        .map(|candidate| (candidate.branch.as_str(), candidate.reason.to_string()))
+        .collect();
+    print_prune_report(
+        repo,
+        &report,
+        |branch| reasons.get(branch).cloned().unwrap_or_default(),
+        format,
+    )?;
+    Ok(report)
+}
+
+/// Asks a yes/no question on stderr. Without a terminal to ask on, fails
+/// with a hint to pass `--yes`.
+fn confirm(question: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Err(Error::precondition(format!(
+            "{question} Pass --yes to confirm when not running in a terminal."
+        )));
+    }
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|err| Error::io("Failed to read the answer", err))?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Prints what pruning deleted, skipped or failed to delete; `why` says why
+/// a branch was up for deletion, e.g. `pruned from remote`.
+fn print_prune_report(
+    repo: &Repo,
+    report: &PruneReport,
+    why: impl Fn(&str) -> String,
+    format: OutputFormat,
+) -> Result<()> {
+    if format.is_json() {
+        return format.json(report);
+    }
+
+    if report.deleted.is_empty() && report.failed.is_empty() && report.skipped.is_empty() {
         println!("No pruned branches found");
         return Ok(());
     }
 
-    for pruned_branch in pruned_branches {
-        let branch_delete_cmd = vec!["branch", "-D", pruned_branch.as_str()];
-        let branch_delete = git_command_status(
-            format!("💣 delete branch {pruned_branch}").as_str(),
-            branch_delete_cmd,
+    for SkippedBranch { branch, reason } in &report.skipped {
+        let held = match reason {
+            SkipReason::CurrentBranch => String::from("it is the current branch"),
+            SkipReason::CheckedOut {
+                worktree,
+                dirty: true,
+            } => format!("it is checked out in {worktree}, which has uncommitted changes"),
+            SkipReason::CheckedOut {
+                worktree,
+                dirty: false,
+            } => format!("it is checked out in {worktree} (--remove-worktrees removes it)"),
+            SkipReason::Protected { glob } => format!("it is protected by `{glob}`"),
+            SkipReason::Unpushed { commits } => format!(
+                "it has {} commit{} the remote never had (`lk prune --gone --force` deletes it anyway)",
+                commits.len(),
+                if commits.len() == 1 { "" } else { "s" }
+            ),
+        };
+        eprintln!("Kept {}: {held}", highlight_branch_name(branch));
+        if let SkipReason::Unpushed { commits } = reason {
+            for commit in commits {
+                eprintln!("    {commit}");
+            }
+        }
+    }
+    for (branch, err) in &report.failed {
+        eprintln!(
+            "Failed to delete pruned branch {}: {err}",
+            highlight_branch_name(branch)
         );
-        if let Err(err) = branch_delete {
-            eprintln!(
-                "Failed to delete pruned branch {}: {err:?}",
-                highlight_branch_name(&pruned_branch)
-            )
+    }
+    for worktree in &report.removed_worktrees {
+        if repo.git().is_dry_run() {
+            println!("Would remove worktree {}", worktree.red());
+        } else {
+            println!("🧹 Removed worktree {}", worktree.red());
+        }
+    }
+    for branch in &report.deleted {
+        if repo.git().is_dry_run() {
+            println!(
+                "Would delete local branch {} ({})",
+                highlight_branch_name(branch),
+                why(branch)
+            );
         } else {
             println!(
-                "💣 Deleted local branch {} (pruned from remote)",
-                highlight_branch_name(&pruned_branch)
+                "💣 Deleted local branch {} ({})",
+                highlight_branch_name(branch),
+                why(branch)
             );
         }
     }
@@ -1168,381 +1816,406 @@ fn prune(cmd: &str) -> Result<(), String> {
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
+fn worktree_add(
+    repo: &Repo,
+    options: worktree::AddOptions,
+    format: OutputFormat,
+) -> Result<WorktreeInfo> {
+    if !repo.config().offline() {
+        eprintln!("Fetching latest from {}…", repo.remotes().fetch_remote());
+    }
+    let info = worktree::add(repo, &options)?;
 
-    #[test]
-    fn canonicalize_author_reuses_first_email_for_name() {
-        let mut email_aliases = HashMap::new();
-        let mut name_to_email = HashMap::new();
-
-        let first = canonicalize_author(
-            "alias@microsoft.com",
-            "msuser",
-            &mut email_aliases,
-            &mut name_to_email,
+    if info.existing_branch {
+        eprintln!(
+            "Found existing branch {} — checked out into worktree",
+            info.branch.cyan()
         );
-        assert_eq!(first, "alias@microsoft.com");
-
-        let second = canonicalize_author(
-            "msuser@microsoft.com",
-            "msuser",
-            &mut email_aliases,
-            &mut name_to_email,
+    } else {
+        eprintln!(
+            "Created worktree at {}",
+            info.path.to_string_lossy().green()
         );
-        assert_eq!(second, "alias@microsoft.com");
+        if let Some(prefix) = &info.prefix {
+            if let Some(value) = prefix.as_str() {
+                eprintln!("Using branch prefix `{value}` (from {}).", prefix.source);
+            }
+        }
     }
 
-    #[test]
-    fn canonicalize_author_handles_name_change_after_alias() {
-        let mut email_aliases = HashMap::new();
-        let mut name_to_email = HashMap::new();
-
-        canonicalize_author(
-            "alias@microsoft.com",
-            "msuser",
-            &mut email_aliases,
-            &mut name_to_email,
-        );
-        canonicalize_author(
-            "msuser@microsoft.com",
-            "msuser",
-            &mut email_aliases,
-            &mut name_to_email,
-        );
-        let reused = canonicalize_author(
-            "msuser@microsoft.com",
-            "display name",
-            &mut email_aliases,
-            &mut name_to_email,
-        );
-        assert_eq!(reused, "alias@microsoft.com");
+    eprintln!("\n{}", "Worktree ready!".green().bold());
+    if format.is_json() {
+        format.json(&info)?;
+    } else {
+        emit_cd(repo, &info.path.to_string_lossy())?;
     }
+    Ok(info)
+}
 
-    #[test]
-    fn matches_author_filters_by_name_exact() {
-        let mut options = RepoStatsOptions::default();
-        options.names = vec![String::from("Example User")];
-
-        assert!(matches_author_filters(
-            "Example User",
-            "user@example.com",
-            &options
-        ));
-        assert!(!matches_author_filters(
-            "Someone Else",
-            "user@example.com",
-            &options
-        ));
+/// Removes a worktree. If `name` is empty the worktree name is inferred
+/// from the current directory. Outputs `cd <main>` to stdout for piping.
+fn worktree_remove(
+    repo: &Repo,
+    name: &[String],
+    force: bool,
+    format: OutputFormat,
+) -> Result<RemovedWorktree> {
+    let name = if name.is_empty() {
+        let cwd =
+            std::env::current_dir().map_err(|e| Error::io("Failed to get current directory", e))?;
+        let dir_name = cwd
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let inferred = infer_worktree_name(&dir_name).to_string();
+        eprintln!("Inferred worktree name: {}", inferred.cyan());
+        inferred
+    } else {
+        name.join(" ")
+    };
+
+    // Move out of the worktree so the OS can delete it
+    let main_root = worktree::find(repo, None)?;
+    std::env::set_current_dir(&main_root)
+        .map_err(|e| Error::io("Failed to change to main worktree", e))?;
+
+    let removed = worktree::remove(repo, &name, force)?;
+    eprintln!("Removed worktree {}", removed.path.to_string_lossy().red());
+
+    if let Some(branch) = &removed.branch {
+        if removed.branch_deleted {
+            eprintln!("Deleted branch {}", branch.red());
+        } else {
+            eprintln!(
+                "Branch {} not found locally (may already be deleted)",
+                branch.yellow()
+            );
+        }
     }
 
-    #[test]
-    fn matches_author_filters_by_name_fuzzy() {
-        let mut options = RepoStatsOptions::default();
-        options.names = vec![String::from("example")];
-
-        // Fuzzy match: "example" is a substring of "Example User"
-        assert!(matches_author_filters(
-            "Example User",
-            "user@example.com",
-            &options
-        ));
-        // Case insensitive fuzzy match
-        assert!(matches_author_filters(
-            "EXAMPLE USER",
-            "user@example.com",
-            &options
-        ));
-        // No match
-        assert!(!matches_author_filters(
-            "Someone Else",
-            "user@example.com",
-            &options
-        ));
+    if format.is_json() {
+        format.json(&removed)?;
+    } else {
+        emit_cd(repo, &removed.main.to_string_lossy())?;
     }
+    Ok(removed)
+}
 
-    #[test]
-    fn matches_author_filters_by_name_case_insensitive() {
-        let mut options = RepoStatsOptions::default();
-        options.names = vec![String::from("EXAMPLE USER")];
-
-        assert!(matches_author_filters(
-            "example user",
-            "user@example.com",
-            &options
-        ));
-        assert!(matches_author_filters(
-            "Example User",
-            "user@example.com",
-            &options
-        ));
+/// Outputs `cd <path>` for the named worktree, or the main worktree if no
+/// name is given. Designed for `eval` / `Invoke-Expression` piping.
+fn worktree_switch(repo: &Repo, name: &[String], format: OutputFormat) -> Result<()> {
+    let name = (!name.is_empty()).then(|| name.join(" "));
+    let target = worktree::find(repo, name.as_deref())?;
+    if format.is_json() {
+        return format.json(&serde_json::json!({ "path": target }));
     }
+    emit_cd(repo, &target.to_string_lossy())
+}
 
-    #[test]
-    fn matches_author_filters_by_email_exact() {
-        let mut options = RepoStatsOptions::default();
-        options.emails = vec![String::from("user@example.com")];
-
-        assert!(matches_author_filters(
-            "Example User",
-            "user@example.com",
-            &options
-        ));
-        assert!(!matches_author_filters(
-            "Example User",
-            "other@example.com",
-            &options
-        ));
+/// Lists all worktrees, highlighting the current one and showing switch hints.
+fn worktree_list(repo: &Repo, format: OutputFormat) -> Result<()> {
+    /// A worktree entry plus whether we are inside it, for `--format json`.
+    #[derive(Serialize)]
+    struct ListedWorktree {
+        #[serde(flatten)]
+        entry: WorktreeEntry,
+        current: bool,
     }
 
-    #[test]
-    fn matches_author_filters_by_email_fuzzy() {
-        let mut options = RepoStatsOptions::default();
-        options.emails = vec![String::from("example.com")];
-
-        // Fuzzy match: "example.com" is a substring of "user@example.com"
-        assert!(matches_author_filters(
-            "Example User",
-            "user@example.com",
-            &options
-        ));
-        // Also matches other emails from the same domain
-        assert!(matches_author_filters(
-            "Example User",
-            "other@example.com",
-            &options
-        ));
-        // No match for different domain
-        assert!(!matches_author_filters(
-            "Example User",
-            "user@other.com",
-            &options
-        ));
+    let cwd = std::env::current_dir()
+        .ok()
+        .map(|p| normalize_path(&p.to_string_lossy()));
+
+    let listed: Vec<ListedWorktree> = worktree::list(repo)?
+        .into_iter()
+        .map(|entry| {
+            let current = cwd.as_ref().is_some_and(|c| {
+                let normalized = normalize_path(&entry.path);
+                *c == normalized || c.starts_with(&format!("{normalized}/"))
+            });
+            ListedWorktree { entry, current }
+        })
+        .collect();
+
+    if format.is_json() {
+        return format.json(&listed);
     }
 
-    #[test]
-    fn matches_author_filters_by_email_case_insensitive() {
-        let mut options = RepoStatsOptions::default();
-        options.emails = vec![String::from("USER@EXAMPLE.COM")];
-
-        assert!(matches_author_filters(
-            "Example User",
-            "user@example.com",
-            &options
-        ));
-        assert!(matches_author_filters(
-            "Example User",
-            "User@Example.Com",
-            &options
-        ));
+    for ListedWorktree { entry, current } in &listed {
+        let label = entry.display_label();
+        if *current {
+            println!("{}", format!("* {label}").green().bold());
+        } else {
+            let hint = switch_hint(&entry.name).dimmed();
+            println!("  {label}  {hint}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the platform-appropriate command to switch to a worktree.
+fn switch_hint(name: &str) -> String {
+    if cfg!(windows) {
+        format!("lk w s {name} | iex")
+    } else {
+        format!("eval \"$(lk w s {name})\"")
+    }
+}
+
+/// Hands `path` to the `lk shell-init` wrapper, or outputs `cd <path>` to
+/// stdout when not running under it. When stdout is a terminal (not piped),
+/// prints a platform-appropriate tip for piping. Dry runs only report the
+/// directory on stderr so a piped shell stays put.
+fn emit_cd(repo: &Repo, path: &str) -> Result<()> {
+    if repo.git().is_dry_run() {
+        eprintln!("{} cd {path}", "[dry-run]".yellow());
+        return Ok(());
+    }
+    if shell::request_cd(path)? {
+        return Ok(());
+    }
+    println!("cd {path}");
+    if std::io::stdout().is_terminal() {
+        let hint = if cfg!(windows) {
+            "| iex"
+        } else {
+            "through eval"
+        };
+        eprintln!(
+            "\n{}",
+            format!(
+                "Tip: pipe this command {hint}, or use `lk shell-init`, to switch automatically."
+            )
+            .dimmed()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loki::error;
+    use loki::git::ScriptedRunner;
+
+    // `loki::repo::scripted_repo` is test-only, so it isn't built for the
+    // binary's tests.
+    fn scripted_repo(git: &ScriptedRunner) -> Repo {
+        scripted_repo_with(git, Config::default())
+    }
+
+    fn scripted_repo_with(git: &ScriptedRunner, config: Config) -> Repo {
+        let remotes = Remotes::resolve(&config, None);
+        Repo::new(Box::new(git.clone()), config, remotes)
     }
 
     #[test]
-    fn matches_author_filters_requires_all_filters() {
-        let mut options = RepoStatsOptions::default();
-        options.names = vec![String::from("Example User")];
-        options.emails = vec![String::from("user@example.com")];
-
-        assert!(matches_author_filters(
-            "Example User",
-            "user@example.com",
-            &options
-        ));
-        assert!(!matches_author_filters(
-            "Example User",
-            "other@other.com",
-            &options
-        ));
-        assert!(!matches_author_filters(
-            "Another User",
-            "user@example.com",
-            &options
-        ));
+    fn push_branch_refuses_detached_head() {
+        let git = ScriptedRunner::new().with("rev-parse --abbrev-ref HEAD", "HEAD");
+        let err = push_branch(&scripted_repo(&git), false, false).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_PRECONDITION);
+        assert!(!git.calls().iter().any(|call| call.starts_with("push")));
     }
 
     #[test]
-    fn matches_author_filters_fuzzy_with_multiple_filters() {
-        let mut options = RepoStatsOptions::default();
-        options.names = vec![String::from("john"), String::from("jane")];
-
-        // Matches first filter
-        assert!(matches_author_filters(
-            "John Smith",
-            "john@example.com",
-            &options
-        ));
-        // Matches second filter
-        assert!(matches_author_filters(
-            "Jane Doe",
-            "jane@example.com",
-            &options
-        ));
-        // No match
-        assert!(!matches_author_filters(
-            "Bob Wilson",
-            "bob@example.com",
-            &options
-        ));
+    fn push_branch_explains_rejections() {
+        let git = ScriptedRunner::new()
+            .with("rev-parse --abbrev-ref HEAD", "fix")
+            .fail(
+                "push --set-upstream origin fix",
+                1,
+                " ! [rejected]        fix -> fix (non-fast-forward)",
+            );
+        let err = push_branch(&scripted_repo(&git), false, false).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_PRECONDITION);
+        assert!(err.to_string().contains("--rebase-on-reject"));
+        assert!(!git.calls().iter().any(|call| call.starts_with("rebase")));
     }
 
     #[test]
-    fn active_weeks_inclusive_counts_single_day_as_one_seventh_week() {
-        // Same-day activity => 1 active day => 1/7 week.
-        let weeks = active_weeks_inclusive(1_700_000_000, 1_700_000_000);
-        assert!((weeks - (1.0 / 7.0)).abs() < 1e-9, "weeks={weeks}");
+    fn push_branch_rebases_on_reject_and_retries_once() {
+        let git = ScriptedRunner::new()
+            .with("rev-parse --abbrev-ref HEAD", "fix")
+            .fail(
+                "push --set-upstream origin fix",
+                1,
+                " ! [rejected]        fix -> fix (fetch first)",
+            );
+        push_branch(&scripted_repo(&git), false, true).unwrap_err();
+        let calls = git.calls();
+        let pushes = calls.iter().filter(|call| call.starts_with("push")).count();
+        assert_eq!(pushes, 2);
+        assert!(calls.contains(&String::from("rebase origin/fix")));
+    }
+
+    fn protected_repo(git: &ScriptedRunner) -> Repo {
+        let mut config = Config::default();
+        config.apply_flag(config::BRANCH_PROTECTED, Some("release/*"));
+        scripted_repo_with(git, config)
     }
 
     #[test]
-    fn active_weeks_inclusive_increases_with_span_days() {
-        // 8 days inclusive => 8/7 weeks.
-        let oldest = 1_700_000_000;
-        let latest = oldest + (7 * 86_400);
-        let weeks = active_weeks_inclusive(latest, oldest);
-        assert!((weeks - (8.0 / 7.0)).abs() < 1e-9, "weeks={weeks}");
+    fn alias_steps_reject_global_flags() {
+        let git = ScriptedRunner::new();
+        let mut config = Config::default();
+        config.apply_flag("alias.ship", Some("push --dry-run --force"));
+        let repo = scripted_repo_with(&git, config);
+
+        let err = run_alias(&repo, &[String::from("ship")], OutputFormat::Json).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_PRECONDITION);
+        assert!(err.to_string().contains("(--dry-run)"));
+        assert!(git.calls().is_empty());
     }
 
     #[test]
-    fn format_active_span_uses_months_below_one_year() {
-        // 6 months-ish
-        let oldest = 1_700_000_000;
-        let latest = oldest + (183 * 86_400);
-        let span = format_active_span(latest, oldest);
-        assert!(
-            span.contains("month"),
-            "expected months in span, got `{span}`"
-        );
+    fn commit_refuses_protected_branches() {
+        let git = ScriptedRunner::new().with("rev-parse --abbrev-ref HEAD", "release/2.0");
+        let options = CommitOptions::parse_from(["c", "-a", "fix"]);
+        let err = commit(&protected_repo(&git), &options, OutputFormat::Json).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_PRECONDITION);
+        assert!(err.to_string().contains("--new-branch"));
+        assert!(!git.calls().iter().any(|call| call.starts_with("commit")));
+
+        let options = CommitOptions::parse_from(["c", "--allow-protected", "fix"]);
+        commit(&protected_repo(&git), &options, OutputFormat::Json).unwrap();
+        assert!(git.calls().contains(&String::from("commit --message fix")));
     }
 
     #[test]
-    fn format_active_span_uses_years_at_or_above_one_year() {
-        // ~400 days
-        let oldest = 1_700_000_000;
-        let latest = oldest + (399 * 86_400);
-        let span = format_active_span(latest, oldest);
-        assert!(
-            span.contains("year"),
-            "expected years in span, got `{span}`"
-        );
+    fn commit_moves_changes_to_a_new_branch() {
+        let git = ScriptedRunner::new().with("rev-parse --abbrev-ref HEAD", "release/2.0");
+        let options = CommitOptions::parse_from(["c", "--new-branch", "Hot fix", "fix"]);
+        commit(&protected_repo(&git), &options, OutputFormat::Json).unwrap();
+        let calls = git.calls();
+        let switch = calls
+            .iter()
+            .position(|call| call == "switch --create hot-fix")
+            .unwrap();
+        let commit = calls
+            .iter()
+            .position(|call| call == "commit --message fix")
+            .unwrap();
+        assert!(switch < commit);
     }
 
-    fn raw(sha: &str, ts: i64, name: &str, email: &str) -> RawCommit {
-        RawCommit {
-            sha: sha.to_string(),
-            timestamp: ts,
-            name: name.to_string(),
-            email: email.to_string(),
-        }
+    #[test]
+    fn commit_refuses_a_protected_new_branch() {
+        let git = ScriptedRunner::new().with("rev-parse --abbrev-ref HEAD", "fix");
+        let options = CommitOptions::parse_from(["c", "--new-branch", "release/3", "fix"]);
+        let err = commit(&protected_repo(&git), &options, OutputFormat::Json).unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_PRECONDITION);
+        assert!(err.to_string().contains("release/3 is protected"));
+        assert!(!git.calls().iter().any(|call| call.starts_with("switch")));
+
+        let options = CommitOptions::parse_from([
+            "c",
+            "--new-branch",
+            "release/3",
+            "--allow-protected",
+            "fix",
+        ]);
+        commit(&protected_repo(&git), &options, OutputFormat::Json).unwrap();
+        assert!(git
+            .calls()
+            .contains(&String::from("switch --create release/3")));
     }
 
     #[test]
-    fn dedup_commits_no_patch_ids_means_all_unique() {
-        let commits = vec![
-            raw("aaa", 1000, "Alice", "a@example.com"),
-            raw("bbb", 1100, "Bob", "b@example.com"),
-            raw("ccc", 1200, "Cara", "c@example.com"),
-        ];
-        let patch_ids: HashMap<String, String> = HashMap::new();
-        let (winners, dups) = dedup_commits(commits.clone(), &patch_ids);
-        assert_eq!(dups, 0);
-        assert_eq!(winners.len(), 3);
+    fn push_branch_uses_branch_push_remote() {
+        let git = ScriptedRunner::new()
+            .with("rev-parse --abbrev-ref HEAD", "feature")
+            .with("config --get branch.feature.pushRemote", "fork");
+        let pushed = push_branch(&scripted_repo(&git), true, false).unwrap();
+        assert_eq!(
+            git.calls().last().unwrap(),
+            "push --set-upstream --force-with-lease fork feature"
+        );
+        assert_eq!(pushed.upstream, "fork/feature");
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
     }
 
     #[test]
-    fn dedup_commits_collapses_shared_patch_id_keeping_earliest() {
-        // Two commits share patch-id `pid1`. The earlier author date wins.
-        let commits = vec![
-            raw("late", 2000, "Alice", "a@example.com"),
-            raw("early", 1000, "Bob", "b@example.com"),
-            raw("solo", 1500, "Cara", "c@example.com"),
-        ];
-        let mut patch_ids = HashMap::new();
-        patch_ids.insert("late".to_string(), "pid1".to_string());
-        patch_ids.insert("early".to_string(), "pid1".to_string());
-        patch_ids.insert("solo".to_string(), "pid2".to_string());
-
-        let (mut winners, dups) = dedup_commits(commits, &patch_ids);
-        winners.sort_by(|a, b| a.sha.cmp(&b.sha));
-
-        assert_eq!(dups, 1);
-        assert_eq!(winners.len(), 2);
-
-        let by_sha: HashMap<&str, &RawCommit> =
-            winners.iter().map(|c| (c.sha.as_str(), c)).collect();
-        // Earliest-by-timestamp (1000) wins, attributed to Bob.
-        assert_eq!(by_sha["early"].name, "Bob");
-        // `solo` is unaffected.
-        assert_eq!(by_sha["solo"].name, "Cara");
-        // `late` was dropped.
-        assert!(!by_sha.contains_key("late"));
+    fn new_branch_fetches_and_branches_from_base() {
+        let git = ScriptedRunner::new();
+        let pushed = new_branch(
+            &scripted_repo(&git),
+            &names(&["Fix", "auth"]),
+            None,
+            String::from("origin/main"),
+            false,
+            true,
+        )
+        .unwrap();
+        assert_eq!(pushed.branch, "fix-auth");
+        let calls = git.calls();
+        assert!(calls.contains(&String::from("fetch origin")));
+        assert!(calls.contains(&String::from(
+            "switch --create fix-auth --no-track origin/main"
+        )));
     }
 
     #[test]
-    fn dedup_commits_tiebreaks_equal_timestamps_by_sha() {
-        // Two commits share patch-id AND timestamp. Lower SHA wins for
-        // determinism.
-        let commits = vec![
-            raw("b_sha", 1000, "Alice", "a@example.com"),
-            raw("a_sha", 1000, "Bob", "b@example.com"),
-        ];
-        let mut patch_ids = HashMap::new();
-        patch_ids.insert("b_sha".to_string(), "pid1".to_string());
-        patch_ids.insert("a_sha".to_string(), "pid1".to_string());
-
-        let (winners, dups) = dedup_commits(commits, &patch_ids);
-        assert_eq!(dups, 1);
-        assert_eq!(winners.len(), 1);
-        assert_eq!(winners[0].sha, "a_sha");
-        assert_eq!(winners[0].name, "Bob");
+    fn new_branch_refuses_uncommitted_changes_without_carry() {
+        let git =
+            ScriptedRunner::new().with("status --porcelain --untracked-files=no", " M src/main.rs");
+        let repo = scripted_repo(&git);
+        let err = new_branch(
+            &repo,
+            &names(&["fix"]),
+            None,
+            "origin/main".into(),
+            false,
+            true,
+        )
+        .unwrap_err();
+        assert_eq!(err.exit_code(), error::EXIT_PRECONDITION);
+        assert!(!git.calls().iter().any(|call| call.starts_with("switch")));
+
+        new_branch(
+            &repo,
+            &names(&["fix"]),
+            None,
+            "origin/main".into(),
+            true,
+            true,
+        )
+        .unwrap();
+        assert!(git.calls().iter().any(|call| call.starts_with("switch")));
     }
 
     #[test]
-    fn dedup_commits_commits_without_patch_id_are_unique_by_sha() {
-        // Two commits with no patch-id mapping (e.g. empty diffs or
-        // merge commits) must NOT collapse together even though they
-        // share author/timestamp.
-        let commits = vec![
-            raw("merge1", 1000, "Alice", "a@example.com"),
-            raw("merge2", 1000, "Alice", "a@example.com"),
-        ];
-        let patch_ids: HashMap<String, String> = HashMap::new();
-        let (winners, dups) = dedup_commits(commits, &patch_ids);
-        assert_eq!(dups, 0);
-        assert_eq!(winners.len(), 2);
+    fn new_branch_from_head_skips_fetch() {
+        let git =
+            ScriptedRunner::new().with("status --porcelain --untracked-files=no", " M src/main.rs");
+        new_branch(
+            &scripted_repo(&git),
+            &names(&["fix"]),
+            None,
+            "HEAD".into(),
+            false,
+            true,
+        )
+        .unwrap();
+        let calls = git.calls();
+        assert!(calls.contains(&String::from("switch --create fix")));
+        assert!(!calls.iter().any(|call| call.starts_with("fetch")));
+        assert!(!calls.iter().any(|call| call.starts_with("status")));
     }
 
     #[test]
-    fn dedup_commits_mixes_patch_id_and_unmapped_commits() {
-        // A patch-id group of 3, plus an unrelated solo commit, plus an
-        // unmapped (no patch-id) commit. Result: 1 winner from the
-        // group + the solo + the unmapped = 3 winners, 2 duplicates.
-        let commits = vec![
-            raw("x1", 3000, "Alice", "a@example.com"),
-            raw("x2", 1000, "Bob", "b@example.com"),
-            raw("x3", 2000, "Cara", "c@example.com"),
-            raw("solo", 1500, "Dan", "d@example.com"),
-            raw("nopatch", 2500, "Eve", "e@example.com"),
-        ];
-        let mut patch_ids = HashMap::new();
-        patch_ids.insert("x1".to_string(), "pid1".to_string());
-        patch_ids.insert("x2".to_string(), "pid1".to_string());
-        patch_ids.insert("x3".to_string(), "pid1".to_string());
-        patch_ids.insert("solo".to_string(), "pid2".to_string());
-        // `nopatch` intentionally not in the map.
-
-        let (mut winners, dups) = dedup_commits(commits, &patch_ids);
-        winners.sort_by(|a, b| a.sha.cmp(&b.sha));
-
-        assert_eq!(dups, 2);
-        assert_eq!(winners.len(), 3);
-
-        let shas: Vec<&str> = winners.iter().map(|c| c.sha.as_str()).collect();
-        assert!(
-            shas.contains(&"x2"),
-            "earliest (1000) of pid1 group should win, got {shas:?}"
+    fn pushed_branch_serializes_upstream() {
+        let pushed = PushedBranch::new("feature".into(), "origin".into());
+        assert_eq!(
+            serde_json::to_value(&pushed).unwrap(),
+            serde_json::json!({
+                "branch": "feature",
+                "remote": "origin",
+                "upstream": "origin/feature",
+            })
         );
-        assert!(shas.contains(&"solo"));
-        assert!(shas.contains(&"nopatch"));
     }
 }
@@ -0,0 +1,433 @@
+//! The operation log behind `lk oplog` and `lk undo`.
+//!
+//! Every ref-changing `lk` command snapshots the local branches and HEAD
+//! before and after it runs. When anything moved, an [`Entry`] with the
+//! before/after values is appended to `<git common dir>/loki/oplog`, one JSON
+//! document per line. [`undo`] puts the refs of an entry back the way they
+//! were, recreating deleted branches from their recorded tips.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::git::GitRunner;
+use crate::repo::{loki_dir, Repo};
+
+/// What HEAD pointed at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Head {
+    Branch(String),
+    Detached(String),
+}
+
+/// The local branches and HEAD at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub head: Option<Head>,
+    /// Branch name to commit.
+    pub branches: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    pub fn take(git: &dyn GitRunner) -> Result<Snapshot> {
+        let mut branches = BTreeMap::new();
+        for line in git.stdout(
+            "list branches",
+            &[
+                "for-each-ref",
+                "--format=%(objectname) %(refname)",
+                "refs/heads/",
+            ],
+        )? {
+            let Some((sha, refname)) = line.split_once(' ') else {
+                continue;
+            };
+            if let Some(branch) = refname.strip_prefix("refs/heads/") {
+                branches.insert(branch.to_string(), sha.to_string());
+            }
+        }
+
+        let symbolic = git
+            .stdout("read HEAD", &["symbolic-ref", "-q", "HEAD"])
+            .unwrap_or_default();
+        let head = match symbolic.first() {
+            Some(refname) => refname
+                .trim()
+                .strip_prefix("refs/heads/")
+                .map(|branch| Head::Branch(branch.to_string())),
+            None => git
+                .stdout("read HEAD", &["rev-parse", "--verify", "-q", "HEAD"])
+                .unwrap_or_default()
+                .first()
+                .map(|sha| Head::Detached(sha.trim().to_string())),
+        };
+
+        Ok(Snapshot { head, branches })
+    }
+
+    fn current_branch(&self) -> Option<&str> {
+        match &self.head {
+            Some(Head::Branch(branch)) => Some(branch),
+            _ => None,
+        }
+    }
+}
+
+/// A branch that was created, moved or deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefChange {
+    pub branch: String,
+    /// The tip before the command; `None` if the command created the branch.
+    pub before: Option<String>,
+    /// The tip after the command; `None` if the command deleted the branch.
+    pub after: Option<String>,
+}
+
+/// One recorded `lk` command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    /// Unix timestamp of when the command finished.
+    pub time: i64,
+    /// The command line, e.g. `lk commit -a fix`.
+    pub command: String,
+    pub head_before: Option<Head>,
+    pub head_after: Option<Head>,
+    pub changes: Vec<RefChange>,
+}
+
+/// Branches that differ between two snapshots.
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<RefChange> {
+    let mut names: Vec<&String> = before
+        .branches
+        .keys()
+        .chain(after.branches.keys())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter_map(|branch| {
+            let old = before.branches.get(branch);
+            let new = after.branches.get(branch);
+            (old != new).then(|| RefChange {
+                branch: branch.clone(),
+                before: old.cloned(),
+                after: new.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// `<git common dir>/loki/oplog`, shared by every worktree of the repo.
+pub fn path(git: &dyn GitRunner) -> Result<PathBuf> {
+    Ok(loki_dir(git)?.join("oplog"))
+}
+
+/// Every recorded entry, oldest first.
+pub fn read(repo: &Repo) -> Result<Vec<Entry>> {
+    let path = path(repo.git())?;
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|err| {
+                Error::parse(format!("Invalid oplog entry in {}: {err}", path.display()))
+            })
+        })
+        .collect()
+}
+
+/// Compares the repository with `before` and appends an entry for `command`
+/// if any branch or HEAD moved. Returns the new entry.
+pub fn record(repo: &Repo, command: &str, before: &Snapshot) -> Result<Option<Entry>> {
+    let after = Snapshot::take(repo.git())?;
+    let changes = diff(before, &after);
+    if changes.is_empty() && before.head == after.head {
+        return Ok(None);
+    }
+
+    let id = read(repo)?.last().map_or(1, |entry| entry.id + 1);
+    let entry = Entry {
+        id,
+        time: chrono::Utc::now().timestamp(),
+        command: command.to_string(),
+        head_before: before.head.clone(),
+        head_after: after.head,
+        changes,
+    };
+
+    let path = path(repo.git())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| Error::io(format!("Failed to create {}", dir.display()), err))?;
+    }
+    let line = serde_json::to_string(&entry)
+        .map_err(|err| Error::parse(format!("Failed to serialize oplog entry: {err}")))?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{line}"))
+        .map_err(|err| Error::io(format!("Failed to write {}", path.display()), err))?;
+
+    Ok(Some(entry))
+}
+
+/// The git commands that restore `entry`'s refs, given the repository's
+/// `current` state. Unless `force` is set, refuses when a branch (or HEAD)
+/// has moved since the entry was recorded.
+pub fn undo_commands(entry: &Entry, current: &Snapshot, force: bool) -> Result<Vec<Vec<String>>> {
+    if !force {
+        for change in &entry.changes {
+            if current.branches.get(&change.branch) != change.after.as_ref() {
+                return Err(Error::precondition(format!(
+                    "Branch `{}` has changed since #{}; pass --force to undo anyway.",
+                    change.branch, entry.id
+                )));
+            }
+        }
+        if entry.head_before != entry.head_after && current.head != entry.head_after {
+            return Err(Error::precondition(format!(
+                "HEAD has moved since #{}; pass --force to undo anyway.",
+                entry.id
+            )));
+        }
+    }
+
+    let update = |branch: &str, sha: &str| {
+        vec![
+            "update-ref".to_string(),
+            "-m".to_string(),
+            format!("lk undo #{}", entry.id),
+            format!("refs/heads/{branch}"),
+            sha.to_string(),
+        ]
+    };
+
+    let mut commands = Vec::new();
+    let mut checked_out = current.current_branch().map(String::from);
+    let mut restored = None;
+
+    // Put HEAD back first so the branches it leaves can be moved or deleted.
+    let target_head = entry.head_before.as_ref().filter(|head| {
+        entry.head_before != entry.head_after && current.head.as_ref() != Some(*head)
+    });
+    match target_head {
+        Some(Head::Branch(branch)) => {
+            if let Some(sha) = entry
+                .changes
+                .iter()
+                .find(|change| &change.branch == branch)
+                .and_then(|change| change.before.as_ref())
+            {
+                commands.push(update(branch, sha));
+                restored = Some(branch.clone());
+            }
+            commands.push(vec!["switch".to_string(), branch.clone()]);
+            checked_out = Some(branch.clone());
+        }
+        Some(Head::Detached(sha)) => {
+            commands.push(vec![
+                "switch".to_string(),
+                "--detach".to_string(),
+                sha.clone(),
+            ]);
+            checked_out = None;
+        }
+        None => {}
+    }
+
+    for change in &entry.changes {
+        if restored.as_ref() == Some(&change.branch) {
+            continue;
+        }
+        let is_checked_out = checked_out.as_ref() == Some(&change.branch);
+        match &change.before {
+            Some(sha) if is_checked_out => {
+                if current.branches.get(&change.branch) != Some(sha) {
+                    commands.push(vec!["reset".to_string(), "--keep".to_string(), sha.clone()]);
+                }
+            }
+            Some(sha) => commands.push(update(&change.branch, sha)),
+            None if is_checked_out => {
+                return Err(Error::precondition(format!(
+                    "Cannot delete `{}`: it is checked out.",
+                    change.branch
+                )));
+            }
+            None => {
+                if current.branches.contains_key(&change.branch) {
+                    commands.push(vec![
+                        "update-ref".to_string(),
+                        "-d".to_string(),
+                        format!("refs/heads/{}", change.branch),
+                    ]);
+                }
+            }
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Restores the refs changed by entry `id`, or by the latest entry when `id`
+/// is `None`. Returns the entry that was undone.
+pub fn undo(repo: &Repo, id: Option<u64>, force: bool) -> Result<Entry> {
+    let entries = read(repo)?;
+    let entry = match id {
+        Some(id) => entries.into_iter().find(|entry| entry.id == id),
+        None => entries.into_iter().last(),
+    }
+    .ok_or_else(|| match id {
+        Some(id) => Error::precondition(format!("No oplog entry #{id}.")),
+        None => Error::precondition("Nothing to undo: the oplog is empty."),
+    })?;
+
+    let git = repo.git();
+    let current = Snapshot::take(git)?;
+    for command in undo_commands(&entry, &current, force)? {
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        git.status("undo", &args)?;
+    }
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EXIT_PRECONDITION;
+    use crate::git::ScriptedRunner;
+
+    fn snapshot(head: &str, branches: &[(&str, &str)]) -> Snapshot {
+        Snapshot {
+            head: Some(Head::Branch(head.to_string())),
+            branches: branches
+                .iter()
+                .map(|(name, sha)| (name.to_string(), sha.to_string()))
+                .collect(),
+        }
+    }
+
+    fn entry(before: &Snapshot, after: &Snapshot) -> Entry {
+        Entry {
+            id: 7,
+            time: 0,
+            command: String::from("lk test"),
+            head_before: before.head.clone(),
+            head_after: after.head.clone(),
+            changes: diff(before, after),
+        }
+    }
+
+    fn joined(commands: Vec<Vec<String>>) -> Vec<String> {
+        commands
+            .into_iter()
+            .map(|command| command.join(" "))
+            .collect()
+    }
+
+    #[test]
+    fn take_reads_branches_and_head() {
+        let git = ScriptedRunner::new()
+            .with(
+                "for-each-ref --format=%(objectname) %(refname) refs/heads/",
+                "aaa refs/heads/main\nbbb refs/heads/users/me/fix",
+            )
+            .with("symbolic-ref -q HEAD", "refs/heads/main");
+        assert_eq!(
+            Snapshot::take(&git).unwrap(),
+            snapshot("main", &[("main", "aaa"), ("users/me/fix", "bbb")])
+        );
+    }
+
+    #[test]
+    fn take_falls_back_to_detached_head() {
+        let git = ScriptedRunner::new()
+            .fail("symbolic-ref -q HEAD", 1, "")
+            .with("rev-parse --verify -q HEAD", "ccc");
+        assert_eq!(
+            Snapshot::take(&git).unwrap().head,
+            Some(Head::Detached(String::from("ccc")))
+        );
+    }
+
+    #[test]
+    fn diff_reports_created_moved_and_deleted() {
+        let before = snapshot("main", &[("main", "a"), ("gone", "g"), ("same", "s")]);
+        let after = snapshot("main", &[("main", "b"), ("new", "n"), ("same", "s")]);
+        assert_eq!(
+            diff(&before, &after),
+            vec![
+                RefChange {
+                    branch: String::from("gone"),
+                    before: Some(String::from("g")),
+                    after: None,
+                },
+                RefChange {
+                    branch: String::from("main"),
+                    before: Some(String::from("a")),
+                    after: Some(String::from("b")),
+                },
+                RefChange {
+                    branch: String::from("new"),
+                    before: None,
+                    after: Some(String::from("n")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_commit_resets_current_branch() {
+        let before = snapshot("main", &[("main", "a")]);
+        let after = snapshot("main", &[("main", "b")]);
+        assert_eq!(
+            joined(undo_commands(&entry(&before, &after), &after, false).unwrap()),
+            vec!["reset --keep a"]
+        );
+    }
+
+    #[test]
+    fn undo_prune_recreates_deleted_branches() {
+        let before = snapshot("main", &[("main", "a"), ("old", "o")]);
+        let after = snapshot("main", &[("main", "a")]);
+        assert_eq!(
+            joined(undo_commands(&entry(&before, &after), &after, false).unwrap()),
+            vec!["update-ref -m lk undo #7 refs/heads/old o"]
+        );
+    }
+
+    #[test]
+    fn undo_new_switches_back_then_deletes_branch() {
+        let before = snapshot("main", &[("main", "a")]);
+        let after = snapshot("feature", &[("main", "a"), ("feature", "a")]);
+        assert_eq!(
+            joined(undo_commands(&entry(&before, &after), &after, false).unwrap()),
+            vec!["switch main", "update-ref -d refs/heads/feature"]
+        );
+    }
+
+    #[test]
+    fn undo_refuses_when_branch_moved_since() {
+        let before = snapshot("main", &[("main", "a")]);
+        let after = snapshot("main", &[("main", "b")]);
+        let now = snapshot("main", &[("main", "c")]);
+        let err = undo_commands(&entry(&before, &after), &now, false).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_PRECONDITION);
+        assert_eq!(
+            joined(undo_commands(&entry(&before, &after), &now, true).unwrap()),
+            vec!["reset --keep a"]
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Pushes deferred while offline, replayed by `lk push --pending`.
+//!
+//! With `--offline` (or `offline = true` in config), and whenever a fetch or
+//! push fails because the remote can't be reached, `lk new`, `lk save`,
+//! `lk push` and `lk worktree add` finish their local part and append the
+//! push they skipped to `<git common dir>/loki/pending-pushes`, one JSON
+//! document per line.
+
+use std::{fs, path::PathBuf};
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::git::GitRunner;
+use crate::repo::{loki_dir, Repo};
+
+/// A push that still has to happen.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingPush {
+    pub branch: String,
+    pub remote: String,
+    /// Push with `--force-with-lease`.
+    #[serde(default)]
+    pub force: bool,
+    /// When the push was queued, in seconds since the Unix epoch.
+    pub time: i64,
+}
+
+impl PendingPush {
+    pub fn new(branch: impl Into<String>, remote: impl Into<String>, force: bool) -> Self {
+        PendingPush {
+            branch: branch.into(),
+            remote: remote.into(),
+            force,
+            time: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    /// `git push` arguments for this push, always with `--set-upstream`.
+    pub fn args(&self) -> Vec<&str> {
+        let mut args = vec!["push", "--set-upstream"];
+        if self.force {
+            args.push("--force-with-lease");
+        }
+        args.extend([self.remote.as_str(), self.branch.as_str()]);
+        args
+    }
+}
+
+/// Whether a push happened or was queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pushed,
+    Queued,
+}
+
+/// `<git common dir>/loki/pending-pushes`, shared by all worktrees.
+pub fn path(git: &dyn GitRunner) -> Result<PathBuf> {
+    Ok(loki_dir(git)?.join("pending-pushes"))
+}
+
+/// Every queued push, oldest first.
+pub fn read(repo: &Repo) -> Result<Vec<PendingPush>> {
+    let path = path(repo.git())?;
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|err| {
+                Error::parse(format!("Invalid pending push in {}: {err}", path.display()))
+            })
+        })
+        .collect()
+}
+
+fn write(repo: &Repo, pushes: &[PendingPush]) -> Result<()> {
+    let path = path(repo.git())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| Error::io(format!("Failed to create {}", dir.display()), err))?;
+    }
+    let mut contents = String::new();
+    for push in pushes {
+        let line = serde_json::to_string(push)
+            .map_err(|err| Error::parse(format!("Failed to serialize pending push: {err}")))?;
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    fs::write(&path, contents)
+        .map_err(|err| Error::io(format!("Failed to write {}", path.display()), err))
+}
+
+/// Adds `push` to the queue, replacing an earlier entry for the same branch
+/// and remote. A dry run only says so.
+pub fn enqueue(repo: &Repo, push: PendingPush) -> Result<()> {
+    eprintln!(
+        "{} Queued push of {} to {}. Run `lk push --pending` once you're back online.",
+        "offline:".yellow(),
+        push.branch.cyan(),
+        push.remote.cyan()
+    );
+    if repo.git().is_dry_run() {
+        return Ok(());
+    }
+    let mut pushes = read(repo)?;
+    pushes.retain(|queued| queued.branch != push.branch || queued.remote != push.remote);
+    pushes.push(push);
+    write(repo, &pushes)
+}
+
+/// What git prints when it can't reach a remote at all, lowercased.
+const NETWORK_ERRORS: &[&str] = &[
+    "could not resolve host",
+    "could not resolve hostname",
+    "connection refused",
+    "connection timed out",
+    "operation timed out",
+    "network is unreachable",
+    "no route to host",
+    "unable to access",
+];
+
+/// True when `err` is git failing to reach the remote, as opposed to the
+/// remote refusing (bad credentials, no such repository, a rejected push).
+pub fn is_network_error(err: &Error) -> bool {
+    let Some(stderr) = err.stderr() else {
+        return false;
+    };
+    let stderr = stderr.to_lowercase();
+    // HTTPS reports auth and missing repos as "unable to access" too, but
+    // with the HTTP status that came back.
+    if stderr.contains("the requested url returned error") {
+        return false;
+    }
+    NETWORK_ERRORS
+        .iter()
+        .any(|message| stderr.contains(message))
+}
+
+/// Fetches `remote` unless `online` is false. A fetch that fails because the
+/// remote can't be reached only warns and sets `online` to false, so the
+/// rest of the command queues its push instead of failing.
+pub fn fetch(repo: &Repo, remote: &str, online: &mut bool) -> Result<()> {
+    if !*online {
+        eprintln!(
+            "{} Skipping fetch from {remote}; working from the refs you already have.",
+            "offline:".yellow()
+        );
+        return Ok(());
+    }
+    let git = repo.git();
+    match git.status_quiet("fetch", &["fetch", remote]) {
+        Err(err) if is_network_error(&err) => {
+            eprintln!(
+                "{} Could not reach {remote}; continuing offline from the refs you already have.",
+                "offline:".yellow()
+            );
+            *online = false;
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Pushes unless `online` is false, queueing the push instead. A push that
+/// fails because the remote can't be reached is queued too; any other
+/// failure (e.g. a rejected push) is returned. `quiet` hides git's output.
+pub fn push(repo: &Repo, push: PendingPush, online: bool, quiet: bool) -> Result<Outcome> {
+    if !online {
+        enqueue(repo, push)?;
+        return Ok(Outcome::Queued);
+    }
+    let git = repo.git();
+    let result = if quiet {
+        git.status_quiet("push to remote", &push.args())
+    } else {
+        git.status("push to remote", &push.args())
+    };
+    match result {
+        Ok(()) => Ok(Outcome::Pushed),
+        Err(err) if is_network_error(&err) => {
+            enqueue(repo, push)?;
+            Ok(Outcome::Queued)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// A queued push that failed again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailedPush {
+    #[serde(flatten)]
+    pub push: PendingPush,
+    pub error: String,
+}
+
+/// The result of replaying the queue.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    pub pushed: Vec<PendingPush>,
+    /// Pushes that failed and stay queued.
+    pub failed: Vec<FailedPush>,
+}
+
+/// Runs every queued push, oldest first. Pushes that succeed leave the
+/// queue; the rest stay for the next attempt.
+pub fn replay(repo: &Repo) -> Result<ReplayReport> {
+    let git = repo.git();
+    let mut report = ReplayReport::default();
+    let queued = read(repo)?;
+    if queued.is_empty() {
+        return Ok(report);
+    }
+
+    let mut remaining = Vec::new();
+    for push in queued {
+        match git.status("push to remote", &push.args()) {
+            Ok(()) => report.pushed.push(push),
+            Err(err) => {
+                remaining.push(push.clone());
+                report.failed.push(FailedPush {
+                    push,
+                    error: err.to_string(),
+                });
+            }
+        }
+    }
+    if !git.is_dry_run() {
+        write(repo, &remaining)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::ScriptedRunner;
+    use crate::repo::scripted_repo;
+    use test_case::test_case;
+
+    /// A runner whose git dir is a fresh temp directory, so the queue file
+    /// can be written.
+    fn git_with_dir(name: &str) -> (ScriptedRunner, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("loki-pending-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let git = ScriptedRunner::new().with(
+            "rev-parse --path-format=absolute --git-common-dir",
+            &dir.to_string_lossy(),
+        );
+        (git, dir)
+    }
+
+    #[test]
+    fn args_always_set_upstream() {
+        let push = PendingPush::new("fix", "origin", true);
+        assert_eq!(
+            push.args(),
+            vec![
+                "push",
+                "--set-upstream",
+                "--force-with-lease",
+                "origin",
+                "fix"
+            ]
+        );
+    }
+
+    #[test]
+    fn offline_push_is_queued_once_per_branch() {
+        let (git, dir) = git_with_dir("queue");
+        let repo = scripted_repo(&git);
+        for _ in 0..2 {
+            let outcome = push(
+                &repo,
+                PendingPush::new("fix", "origin", false),
+                false,
+                false,
+            );
+            assert_eq!(outcome.unwrap(), Outcome::Queued);
+        }
+        let queued = read(&repo).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].branch, "fix");
+        assert!(!git.calls().iter().any(|call| call.starts_with("push")));
+    }
+
+    #[test]
+    fn unreachable_remote_queues_the_push() {
+        let (git, dir) = git_with_dir("unreachable");
+        let git = git.fail(
+            "push --set-upstream origin fix",
+            128,
+            "ssh: Could not resolve hostname github.com: Name or service not known",
+        );
+        let repo = scripted_repo(&git);
+        let outcome = push(&repo, PendingPush::new("fix", "origin", false), true, true);
+        let queued = read(&repo).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(outcome.unwrap(), Outcome::Queued);
+        assert_eq!(queued.len(), 1);
+    }
+
+    #[test]
+    fn rejected_push_is_an_error() {
+        let git = ScriptedRunner::new().fail(
+            "push --set-upstream origin fix",
+            1,
+            "! [rejected] fix -> fix (non-fast-forward)",
+        );
+        let outcome = push(
+            &scripted_repo(&git),
+            PendingPush::new("fix", "origin", false),
+            true,
+            false,
+        );
+        assert!(outcome.is_err());
+    }
+
+    #[test]
+    fn fetch_failure_from_unreachable_remote_goes_offline() {
+        let git = ScriptedRunner::new().fail(
+            "fetch origin",
+            128,
+            "fatal: unable to access 'https://github.com/o/r/': Could not resolve host: github.com",
+        );
+        let mut online = true;
+        fetch(&scripted_repo(&git), "origin", &mut online).unwrap();
+        assert!(!online);
+    }
+
+    #[test_case(
+        "fatal: unable to access 'https://github.com/o/r/': The requested URL returned error: 403" ;
+        "https forbidden"
+    )]
+    #[test_case(
+        "git@github.com: Permission denied (publickey).\nfatal: Could not read from remote repository." ;
+        "ssh key rejected"
+    )]
+    #[test_case("ERROR: Repository not found." ; "missing repository")]
+    fn fetch_failure_from_reachable_remote_is_an_error(stderr: &str) {
+        let git = ScriptedRunner::new().fail("fetch origin", 128, stderr);
+        let mut online = true;
+        assert!(fetch(&scripted_repo(&git), "origin", &mut online).is_err());
+        assert!(online);
+    }
+
+    #[test]
+    fn replay_keeps_failed_pushes_queued() {
+        let (git, dir) = git_with_dir("replay");
+        let git = git.fail("push --set-upstream origin b", 1, "rejected");
+        let repo = scripted_repo(&git);
+        write(
+            &repo,
+            &[
+                PendingPush::new("a", "origin", false),
+                PendingPush::new("b", "origin", false),
+            ],
+        )
+        .unwrap();
+        let report = replay(&repo).unwrap();
+        let queued = read(&repo).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.pushed.len(), 1);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].branch, "b");
+    }
+}
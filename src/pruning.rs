@@ -1,17 +1,93 @@
-const ORIGIN: &str = "origin/";
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use serde::{ser::SerializeSeq, Serialize, Serializer};
+
+use crate::branch;
+use crate::error::{Error, Result};
+use crate::git::{git_branch_upstreams, git_current_branch, git_version, GitRunner};
+use crate::remote::remote_names;
+use crate::repo::Repo;
+use crate::stats::compute_patch_ids;
+use crate::worktree::{self, WorktreeEntry};
+
 const DELETED: &str = " - [deleted]";
+const ARROW: &str = "-> ";
 const RED: &str = "\u{1b}[31m";
 const RESET: &str = "\u{1b}[0m";
 
-pub fn is_pruned_branch(s: String) -> Option<String> {
-    s.starts_with(DELETED)
-        .then(|| s.find(ORIGIN))
-        .flatten()
-        .map(|ix| String::from(&s[ix + ORIGIN.len()..]))
+/// A remote-tracking branch deleted by `fetch --prune`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrunedRef {
+    pub remote: String,
+    pub branch: String,
+}
+
+impl PrunedRef {
+    pub fn remote_ref(&self) -> String {
+        format!("{}/{}", self.remote, self.branch)
+    }
+}
+
+/// Parses a ` - [deleted] ... -> <remote>/<branch>` line from `fetch --prune`.
+/// This is git's human-readable output, so [`prune`] only relies on it in a
+/// dry run on a git without `fetch --porcelain`.
+pub fn is_pruned_branch(s: &str, remotes: &[String]) -> Option<PrunedRef> {
+    if !s.starts_with(DELETED) {
+        return None;
+    }
+    let ix = s.find(ARROW)?;
+    split_remote_ref(s[ix + ARROW.len()..].trim(), remotes)
 }
 
-pub fn highlight_pruned_branch_line(line: &str, branch: &str) -> String {
-    let remote_branch = format!("{ORIGIN}{branch}");
+/// Splits `<remote>/<branch>`. The longest matching name in `remotes` wins so
+/// remotes containing `/` and branches containing another remote's name are
+/// split correctly. Falls back to the first `/` when no known remote matches.
+pub fn split_remote_ref(remote_ref: &str, remotes: &[String]) -> Option<PrunedRef> {
+    let remote = remotes
+        .iter()
+        .filter(|remote| {
+            remote_ref.len() > remote.len()
+                && remote_ref.starts_with(remote.as_str())
+                && remote_ref[remote.len()..].starts_with('/')
+        })
+        .max_by_key(|remote| remote.len())
+        .cloned()
+        .or_else(|| {
+            remote_ref
+                .split_once('/')
+                .map(|(remote, _)| remote.to_string())
+        })?;
+
+    let branch = remote_ref[remote.len() + 1..].to_string();
+    (!branch.is_empty()).then_some(PrunedRef { remote, branch })
+}
+
+/// Picks the local branch to delete for a pruned remote ref: the branch whose
+/// upstream is the pruned ref, or a same-named branch with no upstream.
+pub fn local_branch_for(
+    pruned: &PrunedRef,
+    upstreams: &HashMap<String, Option<String>>,
+) -> Option<String> {
+    let remote_ref = pruned.remote_ref();
+    let mut tracking: Vec<&String> = upstreams
+        .iter()
+        .filter(|(_, upstream)| upstream.as_deref() == Some(remote_ref.as_str()))
+        .map(|(branch, _)| branch)
+        .collect();
+    tracking.sort();
+    if let Some(branch) = tracking.first() {
+        return Some((*branch).clone());
+    }
+
+    matches!(upstreams.get(&pruned.branch), Some(None)).then(|| pruned.branch.clone())
+}
+
+pub fn highlight_pruned_branch_line(line: &str, pruned: &PrunedRef) -> String {
+    let branch = pruned.branch.as_str();
+    let remote_branch = pruned.remote_ref();
     let highlighted_remote_branch = format!("{RED}{remote_branch}{RESET}");
 
     if line.contains(&remote_branch) {
@@ -26,17 +102,739 @@ pub fn highlight_branch_name(branch: &str) -> String {
     format!("{RED}{branch}{RESET}")
 }
 
+/// The first git version with `git fetch --porcelain`.
+const FETCH_PORCELAIN: (u32, u32) = (2, 41);
+
+/// Turns a `git fetch --porcelain` line (`<flag> <old> <new> <local ref>`)
+/// into a [`PruneLine`] that reads like git's usual output. Up-to-date refs
+/// are left out.
+pub fn porcelain_line(line: &str, remotes: &[String]) -> Option<PruneLine> {
+    let mut chars = line.chars();
+    let flag = chars.next()?;
+    let mut fields = chars.as_str().split_whitespace();
+    let (old, new, local_ref) = (fields.next()?, fields.next()?, fields.next()?);
+    let (kind, short) = if let Some(short) = local_ref.strip_prefix("refs/remotes/") {
+        ("branch", short)
+    } else if let Some(short) = local_ref.strip_prefix("refs/tags/") {
+        ("tag", short)
+    } else {
+        (
+            "ref",
+            local_ref.strip_prefix("refs/heads/").unwrap_or(local_ref),
+        )
+    };
+    let text = match flag {
+        '=' => return None,
+        ' ' => format!("   {old:.7}..{new:.7}  -> {short}"),
+        '+' => format!(" + {old:.7}...{new:.7} -> {short}  (forced update)"),
+        '-' => format!("{DELETED}         (none)     {ARROW}{short}"),
+        't' => format!(" t [tag update]      {ARROW}{short}"),
+        '*' => format!(" * [new {kind}]      {ARROW}{short}"),
+        '!' => format!(" ! [rejected]        {ARROW}{short}"),
+        _ => line.to_string(),
+    };
+    let pruned = (flag == '-' && kind == "branch")
+        .then(|| split_remote_ref(short, remotes))
+        .flatten();
+    Some(PruneLine { text, pruned })
+}
+
+/// The remote-tracking branches in `before` that are missing from `after`,
+/// marked on the `text` lines that report them. Pruned refs no line reports
+/// (e.g. with localized git output) get a line of their own.
+fn pruned_between(
+    before: &HashMap<String, String>,
+    after: &HashMap<String, String>,
+    remotes: &[String],
+    text: Vec<String>,
+) -> Vec<PruneLine> {
+    let mut gone: Vec<&String> = before
+        .keys()
+        .filter(|remote_ref| !after.contains_key(*remote_ref))
+        .collect();
+    gone.sort();
+
+    let mut lines: Vec<PruneLine> = text
+        .into_iter()
+        .map(|text| {
+            let pruned = is_pruned_branch(&text, remotes)
+                .filter(|pruned| gone.contains(&&pruned.remote_ref()));
+            PruneLine { text, pruned }
+        })
+        .collect();
+    for remote_ref in gone {
+        let reported = lines.iter().any(|line| {
+            line.pruned
+                .as_ref()
+                .is_some_and(|pruned| pruned.remote_ref() == *remote_ref)
+        });
+        if !reported {
+            lines.push(PruneLine {
+                text: format!("{DELETED}         (none)     {ARROW}{remote_ref}"),
+                pruned: split_remote_ref(remote_ref, remotes),
+            });
+        }
+    }
+    lines
+}
+
+/// Which git command [`prune`] runs to find pruned branches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneSource {
+    /// `git pull --prune`, which uses the current branch's upstream remote.
+    Pull,
+    /// `git fetch --prune` from the fetch remote, plus the push remote in
+    /// the fork workflow.
+    Fetch,
+}
+
+/// A line of git's output, with the remote branch it reports as pruned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PruneLine {
+    pub text: String,
+    pub pruned: Option<PrunedRef>,
+}
+
+/// Why a branch that could be pruned was left alone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// It is checked out here.
+    CurrentBranch,
+    /// It is checked out in another worktree, which may have uncommitted
+    /// changes.
+    CheckedOut { worktree: String, dirty: bool },
+    /// It matches a `branch.protected` glob.
+    Protected { glob: String },
+    /// It has commits the remote never had, as `<short sha> <subject>`.
+    Unpushed { commits: Vec<String> },
+}
+
+/// A branch [`plan`] chose not to delete.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedBranch {
+    pub branch: String,
+    #[serde(flatten)]
+    pub reason: SkipReason,
+}
+
+/// Why `lk prune` deletes a branch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum PruneReason {
+    /// Its upstream is `[gone]`.
+    Gone,
+    /// It is an ancestor of `into`.
+    Merged { into: String },
+    /// Every commit has a patch-equivalent commit in `into`, e.g. after a
+    /// rebase merge.
+    Rebased { into: String },
+    /// Its combined changes match one commit in `into`.
+    Squashed { into: String },
+}
+
+impl fmt::Display for PruneReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PruneReason::Gone => write!(f, "upstream gone"),
+            PruneReason::Merged { into } => write!(f, "merged into {into}"),
+            PruneReason::Rebased { into } => write!(f, "rebased onto {into}"),
+            PruneReason::Squashed { into } => write!(f, "squash-merged into {into}"),
+        }
+    }
+}
+
+/// A branch [`plan`] would delete.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrunableBranch {
+    pub branch: String,
+    #[serde(flatten)]
+    pub reason: PruneReason,
+}
+
+/// What `lk prune` would delete, see [`plan`].
+#[derive(Debug, Default, Serialize)]
+pub struct PrunePlan {
+    pub branches: Vec<PrunableBranch>,
+    pub skipped: Vec<SkippedBranch>,
+}
+
+/// The result of [`prune`] and [`delete`].
+#[derive(Debug, Default, Serialize)]
+pub struct PruneReport {
+    /// git's output, in order.
+    pub lines: Vec<PruneLine>,
+    /// The branches `lk prune` set out to delete, and why.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub planned: Vec<PrunableBranch>,
+    /// Local branches that were deleted (or only recorded, in a dry run).
+    pub deleted: Vec<String>,
+    /// Local branches that could be pruned but were kept.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedBranch>,
+    /// Worktrees removed to delete the branch they had checked out.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_worktrees: Vec<String>,
+    /// Local branches that could not be deleted.
+    #[serde(serialize_with = "serialize_failed")]
+    pub failed: Vec<(String, Error)>,
+}
+
+/// Serializes failed deletions as `[{ "branch", "error" }]`.
+fn serialize_failed<S: Serializer>(
+    failed: &[(String, Error)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Failed<'a> {
+        branch: &'a str,
+        error: &'a Error,
+    }
+
+    let mut seq = serializer.serialize_seq(Some(failed.len()))?;
+    for (branch, error) in failed {
+        seq.serialize_element(&Failed { branch, error })?;
+    }
+    seq.end()
+}
+
+/// Fetches or pulls with `--prune`, then deletes the local branches whose
+/// remote branch was pruned. The current branch and protected branches are
+/// never deleted but reported as skipped, and unless `force` is set neither
+/// is a branch with commits the pruned remote branch didn't have. Branches
+/// checked out in another worktree are kept unless `remove_worktrees` is set
+/// and the worktree is clean. In a dry run git only
+/// reports what it would prune. `gone` and `merged_into` are ignored.
+///
+/// Pruned refs come from `fetch --porcelain` on git 2.41+, otherwise from
+/// comparing the remote-tracking refs before and after.
+pub fn prune(repo: &Repo, source: PruneSource, options: &PruneOptions) -> Result<PruneReport> {
+    let git = repo.git();
+    let current_branch = git_current_branch(git)?;
+    let upstreams = git_branch_upstreams(git)?;
+    let worktrees = worktree::list(repo)?;
+    let remotes = remote_names(git);
+    // Pruning deletes the remote-tracking refs and their reflogs, so note
+    // where they pointed first.
+    let tips_before = remote_tips(git)?;
+
+    let fetch_remote = repo.remotes().fetch_remote();
+    let push_remote = repo.remotes().default_push_remote(git);
+    let mut args = match source {
+        PruneSource::Pull => vec!["pull", "--prune"],
+        // Branches are pruned from the fork, trunk moves on upstream.
+        PruneSource::Fetch if push_remote != fetch_remote => vec![
+            "fetch",
+            "--prune",
+            "--multiple",
+            fetch_remote,
+            push_remote.as_str(),
+        ],
+        PruneSource::Fetch => vec!["fetch", "--prune", fetch_remote],
+    };
+
+    // Let git report what it would prune without touching remote-tracking refs.
+    if git.is_dry_run() {
+        args.insert(1, "--dry-run");
+    }
+    let porcelain = source == PruneSource::Fetch
+        && git_version(git).is_some_and(|version| version >= FETCH_PORCELAIN);
+    if porcelain {
+        args.insert(1, "--porcelain");
+    }
+
+    let lines = if porcelain {
+        git.stdout("fetch with pruning", &args)?
+            .iter()
+            .filter_map(|line| porcelain_line(line, &remotes))
+            .collect()
+    } else {
+        let text = git.lines("pull with pruning", &args)?;
+        if git.is_dry_run() {
+            // A dry run leaves the refs alone, so git's text is all there is.
+            text.into_iter()
+                .map(|text| PruneLine {
+                    pruned: is_pruned_branch(&text, &remotes),
+                    text,
+                })
+                .collect()
+        } else {
+            pruned_between(&tips_before, &remote_tips(git)?, &remotes, text)
+        }
+    };
+
+    let mut report = PruneReport::default();
+    let mut pruned_branches: Vec<String> = Vec::new();
+    let mut pruned_refs: HashMap<String, String> = HashMap::new();
+
+    for PruneLine { text, pruned } in lines {
+        if let Some(local) = pruned
+            .as_ref()
+            .and_then(|pruned| local_branch_for(pruned, &upstreams))
+        {
+            if !pruned_branches.contains(&local) {
+                if let Some(pruned) = &pruned {
+                    pruned_refs.insert(local.clone(), pruned.remote_ref());
+                }
+                pruned_branches.push(local);
+            }
+        }
+        report.lines.push(PruneLine { text, pruned });
+    }
+
+    let mut to_delete = Vec::new();
+    for branch in pruned_branches {
+        let mut reason = if branch == current_branch {
+            Some(SkipReason::CurrentBranch)
+        } else {
+            branch::is_protected(repo.config(), &branch).map(|glob| SkipReason::Protected { glob })
+        };
+        if reason.is_none() && !options.force {
+            let tip = pruned_refs
+                .get(&branch)
+                .and_then(|remote_ref| tips_before.get(remote_ref));
+            let commits = unpushed_commits(git, &branch, tip.map(String::as_str))?;
+            reason = (!commits.is_empty()).then_some(SkipReason::Unpushed { commits });
+        }
+        if reason.is_none() {
+            reason = checked_out(git, &worktrees, &branch)?;
+        }
+        match reason {
+            Some(reason) => report.skipped.push(SkippedBranch { branch, reason }),
+            None => to_delete.push(branch),
+        }
+    }
+
+    if options.remove_worktrees {
+        remove_clean_worktrees(repo, &worktrees, &mut report);
+    }
+    delete_branches(repo, to_delete, &mut report);
+    Ok(report)
+}
+
+/// The worktree `branch` is checked out in, if any, and whether it's dirty.
+fn checked_out(
+    git: &dyn GitRunner,
+    worktrees: &[WorktreeEntry],
+    branch: &str,
+) -> Result<Option<SkipReason>> {
+    let Some(entry) = worktrees
+        .iter()
+        .find(|entry| entry.branch.as_deref() == Some(branch))
+    else {
+        return Ok(None);
+    };
+    Ok(Some(SkipReason::CheckedOut {
+        worktree: entry.path.clone(),
+        dirty: worktree::is_dirty(git, &entry.path)?,
+    }))
+}
+
+/// Removes the clean worktrees holding skipped branches, deleting the
+/// branches with them (see [`worktree::remove_entry`]). The main worktree
+/// and dirty ones stay.
+fn remove_clean_worktrees(repo: &Repo, worktrees: &[WorktreeEntry], report: &mut PruneReport) {
+    let main = worktrees.first().map(|entry| entry.path.as_str());
+    let skipped = std::mem::take(&mut report.skipped);
+    let mut left = Vec::new();
+    for skipped in skipped {
+        let entry = match &skipped.reason {
+            SkipReason::CheckedOut {
+                worktree,
+                dirty: false,
+            } if Some(worktree.as_str()) != main => {
+                worktrees.iter().find(|entry| entry.path == *worktree)
+            }
+            _ => None,
+        };
+        let Some(entry) = entry else {
+            left.push(skipped);
+            continue;
+        };
+        match worktree::remove_entry(repo, entry, false) {
+            Ok(removed) => {
+                report.removed_worktrees.push(entry.path.clone());
+                if removed.branch_deleted {
+                    report.deleted.push(skipped.branch);
+                } else {
+                    delete_branches(repo, vec![skipped.branch], report);
+                }
+            }
+            Err(err) => report.failed.push((skipped.branch, err)),
+        }
+    }
+    report.skipped = left;
+}
+
+/// Where every remote-tracking branch points, by `<remote>/<branch>`.
+pub fn remote_tips(git: &dyn GitRunner) -> Result<HashMap<String, String>> {
+    Ok(git
+        .stdout(
+            "list remote branches",
+            &[
+                "for-each-ref",
+                "--format=%(refname) %(objectname)",
+                "refs/remotes",
+            ],
+        )?
+        .into_iter()
+        .filter_map(|line| {
+            let (refname, sha) = line.split_once(' ')?;
+            let remote_ref = refname.strip_prefix("refs/remotes/")?;
+            Some((remote_ref.to_string(), sha.trim().to_string()))
+        })
+        .collect())
+}
+
+/// Commits on `branch` that `remote_tip` doesn't have, as `<short sha>
+/// <subject>`. Without a known tip, commits no remote-tracking branch has.
+pub fn unpushed_commits(
+    git: &dyn GitRunner,
+    branch: &str,
+    remote_tip: Option<&str>,
+) -> Result<Vec<String>> {
+    let mut args = vec!["log", "--format=%h %s", branch, "--not"];
+    match remote_tip {
+        Some(tip) => args.push(tip),
+        None => args.push("--remotes"),
+    }
+    git.stdout("list unpushed commits", &args)
+}
+
+/// Deletes `branches` with `git branch -D`, recording each result.
+fn delete_branches(repo: &Repo, branches: Vec<String>, report: &mut PruneReport) {
+    for branch in branches {
+        let branch_delete = repo.git().status(
+            format!("💣 delete branch {branch}").as_str(),
+            &["branch", "-D", branch.as_str()],
+        );
+        match branch_delete {
+            Ok(()) => report.deleted.push(branch),
+            Err(err) => report.failed.push((branch, err)),
+        }
+    }
+}
+
+/// Local branches whose upstream is `[gone]`, i.e. the remote-tracking ref
+/// was pruned by any earlier fetch, not just the current one.
+pub fn gone_branches(git: &dyn GitRunner) -> Result<Vec<String>> {
+    Ok(git
+        .stdout(
+            "list gone branches",
+            &[
+                "for-each-ref",
+                "--format=%(refname:short) %(upstream:track)",
+                "refs/heads",
+            ],
+        )?
+        .into_iter()
+        .filter_map(|line| {
+            let (branch, track) = line.split_once(' ')?;
+            (track.trim() == "[gone]").then(|| branch.to_string())
+        })
+        .collect())
+}
+
+/// Which of `branches` have their changes in `into`, and how: as an
+/// ancestor, as patch-equivalent commits (`git cherry`), or squashed into a
+/// single commit. The squash check looks for the patch-id of a branch's diff
+/// against its merge base among `into`'s commits, whose patch-ids are
+/// computed once for all branches.
+pub fn merged_branches(
+    git: &dyn GitRunner,
+    branches: &[String],
+    into: &str,
+) -> Result<Vec<PrunableBranch>> {
+    let mut reasons: HashMap<&str, PruneReason> = HashMap::new();
+    // Branches left for the squash check: name, merge base and patch-id.
+    let mut squash_candidates: Vec<(&str, String, String)> = Vec::new();
+    for branch in branches {
+        let branch = branch.as_str();
+        let into_name = into.to_string();
+        if git
+            .stdout(
+                "check ancestry",
+                &["merge-base", "--is-ancestor", branch, into],
+            )
+            .is_ok()
+        {
+            reasons.insert(branch, PruneReason::Merged { into: into_name });
+            continue;
+        }
+
+        let cherry = git.stdout("compare patches", &["cherry", into, branch])?;
+        if !cherry.is_empty() && cherry.iter().all(|line| line.starts_with('-')) {
+            reasons.insert(branch, PruneReason::Rebased { into: into_name });
+            continue;
+        }
+
+        let Some(base) = first_line(git.stdout("find merge base", &["merge-base", into, branch])?)
+        else {
+            continue;
+        };
+        let patch_id = git.pipe(
+            "compute patch id",
+            "",
+            &["diff", &base, branch],
+            &["patch-id", "--stable"],
+        )?;
+        if let Some(patch_id) =
+            first_line(patch_id).and_then(|line| line.split_whitespace().next().map(str::to_string))
+        {
+            squash_candidates.push((branch, base, patch_id));
+        }
+    }
+
+    if !squash_candidates.is_empty() {
+        let into_patch_ids = patch_ids_since(
+            git,
+            into,
+            squash_candidates.iter().map(|(_, base, _)| base.as_str()),
+        )?;
+        for (branch, _, patch_id) in squash_candidates {
+            if into_patch_ids.contains(&patch_id) {
+                let reason = PruneReason::Squashed {
+                    into: into.to_string(),
+                };
+                reasons.insert(branch, reason);
+            }
+        }
+    }
+
+    Ok(branches
+        .iter()
+        .filter_map(|branch| {
+            let reason = reasons.remove(branch.as_str())?;
+            Some(PrunableBranch {
+                branch: branch.clone(),
+                reason,
+            })
+        })
+        .collect())
+}
+
+/// The patch-ids of the non-merge commits on `into` since the oldest of
+/// `bases`, which covers `base..into` for each of them.
+fn patch_ids_since<'a>(
+    git: &dyn GitRunner,
+    into: &str,
+    bases: impl Iterator<Item = &'a str>,
+) -> Result<HashSet<String>> {
+    let mut bases: Vec<&str> = bases.collect();
+    bases.sort_unstable();
+    bases.dedup();
+    let oldest = match bases.as_slice() {
+        [base] => Some(base.to_string()),
+        _ => {
+            let mut args = vec!["merge-base", "--octopus"];
+            args.extend(&bases);
+            // Unrelated histories have no common base: look at all of `into`.
+            git.stdout("find oldest merge base", &args)
+                .ok()
+                .and_then(first_line)
+        }
+    };
+    let range = match &oldest {
+        Some(base) => format!("{base}..{into}"),
+        None => into.to_string(),
+    };
+    let into_commits = git.stdout("list commits", &["rev-list", "--no-merges", &range])?;
+    let shas: Vec<&str> = into_commits.iter().map(|sha| sha.trim()).collect();
+    Ok(compute_patch_ids(git, &shas)?.into_values().collect())
+}
+
+fn first_line(lines: Vec<String>) -> Option<String> {
+    lines
+        .into_iter()
+        .next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+}
+
+/// What `lk prune` looks for, see [`plan`].
+#[derive(Debug, Clone, Default)]
+pub struct PruneOptions {
+    /// Branches whose upstream is gone.
+    pub gone: bool,
+    /// Branches whose changes are in this branch.
+    pub merged_into: Option<String>,
+    /// Delete branches even when they have unpushed commits.
+    pub force: bool,
+    /// Remove clean worktrees holding branches to delete.
+    pub remove_worktrees: bool,
+}
+
+/// Finds the local branches `lk prune` would delete: with `gone`, those
+/// whose upstream is gone; with `merged_into`, those whose changes are in
+/// that branch (see [`merged_branches`]). `merged_into` and the trunk are never
+/// candidates. The current branch, protected branches and branches checked
+/// out in another worktree are skipped, and so are gone branches with
+/// commits no remote has, unless `force` is set.
+pub fn plan(repo: &Repo, options: &PruneOptions) -> Result<PrunePlan> {
+    let PruneOptions {
+        gone,
+        merged_into,
+        force,
+        ..
+    } = options;
+    let git = repo.git();
+    let current_branch = git_current_branch(git)?;
+    let worktrees = worktree::list(repo)?;
+    let trunk = repo.config().trunk();
+
+    let mut candidates: Vec<PrunableBranch> = Vec::new();
+    if *gone {
+        candidates.extend(
+            gone_branches(git)?
+                .into_iter()
+                .map(|branch| PrunableBranch {
+                    branch,
+                    reason: PruneReason::Gone,
+                }),
+        );
+    }
+    if let Some(into) = merged_into {
+        let mut branches: Vec<String> = git_branch_upstreams(git)?.into_keys().collect();
+        branches.sort();
+        branches.retain(|branch| {
+            *branch != *into
+                && *branch != trunk
+                && !candidates
+                    .iter()
+                    .any(|candidate| candidate.branch == *branch)
+        });
+        candidates.extend(merged_branches(git, &branches, into)?);
+    }
+
+    let mut plan = PrunePlan::default();
+    for candidate in candidates {
+        let branch = candidate.branch.as_str();
+        // Unpushed commits come before worktrees, so removing a worktree
+        // never takes unpushed work with it.
+        let unpushed = if candidate.reason == PruneReason::Gone && !force {
+            unpushed_commits(git, branch, None)?
+        } else {
+            Vec::new()
+        };
+        let reason = if branch == current_branch {
+            Some(SkipReason::CurrentBranch)
+        } else if let Some(glob) = branch::is_protected(repo.config(), branch) {
+            Some(SkipReason::Protected { glob })
+        } else if !unpushed.is_empty() {
+            Some(SkipReason::Unpushed { commits: unpushed })
+        } else {
+            checked_out(git, &worktrees, branch)?
+        };
+        match reason {
+            Some(reason) => plan.skipped.push(SkippedBranch {
+                branch: candidate.branch,
+                reason,
+            }),
+            None => plan.branches.push(candidate),
+        }
+    }
+    Ok(plan)
+}
+
+/// Deletes the branches in `plan`, first removing the clean worktrees that
+/// hold skipped ones when `remove_worktrees` is set.
+pub fn delete(repo: &Repo, plan: PrunePlan, remove_worktrees: bool) -> Result<PruneReport> {
+    let mut report = PruneReport {
+        skipped: plan.skipped,
+        ..Default::default()
+    };
+    if remove_worktrees {
+        remove_clean_worktrees(repo, &worktree::list(repo)?, &mut report);
+    }
+    let branches = plan
+        .branches
+        .iter()
+        .map(|candidate| candidate.branch.clone())
+        .collect();
+    delete_branches(repo, branches, &mut report);
+    report.planned = plan.branches;
+    Ok(report)
+}
+
 #[cfg(test)]
 mod prune_tests {
     use super::*;
+    use crate::config::Config;
+    use crate::git::{RecordingRunner, ScriptedRunner};
+    use crate::remote::Remotes;
+    use crate::repo::{scripted_repo, scripted_repo_with};
     use test_case::test_case;
 
+    fn remotes() -> Vec<String> {
+        vec![String::from("origin"), String::from("upstream")]
+    }
+
+    fn pruned(remote: &str, branch: &str) -> PrunedRef {
+        PrunedRef {
+            remote: remote.to_string(),
+            branch: branch.to_string(),
+        }
+    }
+
     #[test]
     fn from_pruned_line() {
-        let subject = is_pruned_branch(String::from(
+        let subject = is_pruned_branch(
             " - [deleted]         (none)     -> origin/command-push",
-        ));
-        assert_eq!(subject, Some(String::from("command-push")));
+            &remotes(),
+        );
+        assert_eq!(subject, Some(pruned("origin", "command-push")));
+    }
+
+    #[test]
+    fn from_pruned_line_on_other_remote() {
+        let subject = is_pruned_branch(
+            " - [deleted]         (none)     -> upstream/users/kyle/fix",
+            &remotes(),
+        );
+        assert_eq!(subject, Some(pruned("upstream", "users/kyle/fix")));
+    }
+
+    #[test]
+    fn from_pruned_line_with_remote_name_in_branch() {
+        let subject = is_pruned_branch(
+            " - [deleted]         (none)     -> upstream/origin/thing",
+            &remotes(),
+        );
+        assert_eq!(subject, Some(pruned("upstream", "origin/thing")));
+    }
+
+    #[test]
+    fn from_pruned_line_with_unknown_remote() {
+        let subject = is_pruned_branch(" - [deleted]         (none)     -> fork/topic", &[]);
+        assert_eq!(subject, Some(pruned("fork", "topic")));
+    }
+
+    #[test]
+    fn local_branch_prefers_tracking_branch() {
+        let upstreams = HashMap::from([
+            (String::from("topic"), Some(String::from("origin/topic"))),
+            (String::from("mine"), Some(String::from("upstream/topic"))),
+        ]);
+        assert_eq!(
+            local_branch_for(&pruned("upstream", "topic"), &upstreams),
+            Some(String::from("mine"))
+        );
+    }
+
+    #[test]
+    fn local_branch_falls_back_to_untracked_same_name() {
+        let upstreams = HashMap::from([
+            (String::from("topic"), None),
+            (String::from("other"), Some(String::from("origin/other"))),
+        ]);
+        assert_eq!(
+            local_branch_for(&pruned("origin", "topic"), &upstreams),
+            Some(String::from("topic"))
+        );
+        assert_eq!(
+            local_branch_for(&pruned("origin", "missing"), &upstreams),
+            None
+        );
     }
 
     #[test_case("remote: Enumerating objects: 81, done.")]
@@ -48,15 +846,14 @@ mod prune_tests {
     #[test_case("   01c2f3a..e4b40f0  main       -> origin/main")]
     #[test_case(" * [new tag]         loki-cli-0.2.0 -> loki-cli-0.2.0")]
     fn from_not_pruned(input: &str) {
-        let line = String::from(input);
-        let subject = is_pruned_branch(line);
+        let subject = is_pruned_branch(input, &remotes());
         assert_eq!(subject, None);
     }
 
     #[test]
     fn highlights_remote_branch_in_line() {
         let line = " - [deleted]         (none)     -> origin/command-push";
-        let highlighted = highlight_pruned_branch_line(line, "command-push");
+        let highlighted = highlight_pruned_branch_line(line, &pruned("origin", "command-push"));
         assert!(
             highlighted.contains("\u{1b}[31morigin/command-push\u{1b}[0m"),
             "{highlighted} did not highlight the remote branch"
@@ -68,4 +865,450 @@ mod prune_tests {
         let highlighted = highlight_branch_name("feature/example");
         assert_eq!(highlighted, "\u{1b}[31mfeature/example\u{1b}[0m");
     }
+
+    const UPSTREAMS: &str = "for-each-ref --format=%(refname:short) %(upstream:short) refs/heads";
+    const REMOTE_REFS: &str = "for-each-ref --format=%(refname) %(objectname) refs/remotes";
+    const PRUNED_FEATURE: &str = " - [deleted]         (none)     -> origin/feature";
+
+    fn prune_runner() -> ScriptedRunner {
+        ScriptedRunner::new()
+            .with("rev-parse --abbrev-ref HEAD", "main")
+            .with(
+                UPSTREAMS,
+                "main origin/main\nfeature origin/feature\nother origin/other",
+            )
+            .with("remote", "origin")
+    }
+
+    /// Scripts `runs` fetches that prune origin/feature: the remote refs
+    /// before and after each one, and git's report.
+    fn fetch_pruning_feature(mut git: ScriptedRunner, runs: usize) -> ScriptedRunner {
+        for _ in 0..runs {
+            git = git
+                .then(
+                    REMOTE_REFS,
+                    "refs/remotes/origin/main m1\nrefs/remotes/origin/feature f1",
+                )
+                .then(REMOTE_REFS, "refs/remotes/origin/main m1");
+        }
+        git.with("fetch --prune origin", PRUNED_FEATURE)
+    }
+
+    #[test]
+    fn fetch_deletes_branches_tracking_pruned_refs() {
+        let git = fetch_pruning_feature(prune_runner(), 1);
+        let report = prune(
+            &scripted_repo(&git),
+            PruneSource::Fetch,
+            &PruneOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(report.deleted, vec![String::from("feature")]);
+        assert_eq!(report.lines[0].pruned, Some(pruned("origin", "feature")));
+        assert!(git.calls().contains(&String::from("branch -D feature")));
+        assert!(!git.calls().contains(&String::from("branch -D other")));
+    }
+
+    #[test]
+    fn fetch_finds_pruned_refs_git_does_not_report() {
+        // Localized git output that `is_pruned_branch` can't read.
+        let git = fetch_pruning_feature(prune_runner(), 1).with(
+            "fetch --prune origin",
+            " - [gelöscht]        (nichts)   -> origin/feature",
+        );
+        let report = prune(
+            &scripted_repo(&git),
+            PruneSource::Fetch,
+            &PruneOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(report.deleted, vec![String::from("feature")]);
+        assert_eq!(report.lines[0].pruned, None);
+        assert_eq!(report.lines[1].text, PRUNED_FEATURE);
+        assert_eq!(report.lines[1].pruned, Some(pruned("origin", "feature")));
+    }
+
+    #[test]
+    fn fetch_ignores_reported_refs_that_still_exist() {
+        let git = prune_runner()
+            .with(REMOTE_REFS, "refs/remotes/origin/feature f1")
+            .with("fetch --prune origin", PRUNED_FEATURE);
+        let report = prune(
+            &scripted_repo(&git),
+            PruneSource::Fetch,
+            &PruneOptions::default(),
+        )
+        .unwrap();
+
+        assert!(report.deleted.is_empty());
+        assert_eq!(report.lines[0].pruned, None);
+    }
+
+    #[test]
+    fn fetch_reads_porcelain_output_on_newer_git() {
+        let git = prune_runner().with("--version", "git version 2.43.0").with(
+            "fetch --porcelain --prune origin",
+            "  01c2f3a 0000000000000000000000000000000000000001 refs/remotes/origin/main\n\
+                 - 02c2f3a 0000000000000000000000000000000000000000 refs/remotes/origin/feature\n\
+                 = 03c2f3a 03c2f3a refs/remotes/origin/other",
+        );
+        let report = prune(
+            &scripted_repo(&git),
+            PruneSource::Fetch,
+            &PruneOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(report.deleted, vec![String::from("feature")]);
+        assert_eq!(report.lines.len(), 2);
+        assert_eq!(report.lines[1].pruned, Some(pruned("origin", "feature")));
+        assert!(!git.calls().contains(&String::from("fetch --prune origin")));
+    }
+
+    #[test_case(
+        "- 1234567 0000000 refs/remotes/upstream/origin/thing",
+        Some(pruned("upstream", "origin/thing")) ;
+        "deleted branch"
+    )]
+    #[test_case("- 1234567 0000000 refs/tags/v1.0", None ; "deleted tag")]
+    #[test_case("  1234567 89abcde refs/remotes/origin/main", None ; "fast forward")]
+    #[test_case("* 0000000 89abcde refs/remotes/origin/new", None ; "new branch")]
+    fn from_porcelain_line(line: &str, expected: Option<PrunedRef>) {
+        let subject = porcelain_line(line, &remotes()).unwrap();
+        assert_eq!(subject.pruned, expected);
+    }
+
+    #[test]
+    fn keeps_branches_with_commits_the_remote_never_had() {
+        let git = fetch_pruning_feature(prune_runner(), 2).with(
+            "log --format=%h %s feature --not f1",
+            "abc1234 wip: not pushed",
+        );
+        let report = prune(
+            &scripted_repo(&git),
+            PruneSource::Fetch,
+            &PruneOptions::default(),
+        )
+        .unwrap();
+
+        assert!(report.deleted.is_empty());
+        assert_eq!(
+            report.skipped[0].reason,
+            SkipReason::Unpushed {
+                commits: vec![String::from("abc1234 wip: not pushed")]
+            }
+        );
+        assert!(!git.calls().contains(&String::from("branch -D feature")));
+
+        let options = PruneOptions {
+            force: true,
+            ..Default::default()
+        };
+        let report = prune(&scripted_repo(&git), PruneSource::Fetch, &options).unwrap();
+        assert_eq!(report.deleted, vec![String::from("feature")]);
+    }
+
+    #[test]
+    fn never_deletes_protected_branches() {
+        let git = fetch_pruning_feature(prune_runner(), 1);
+        let mut config = Config::default();
+        config.apply_flag(crate::config::BRANCH_PROTECTED, Some("feat*"));
+        let repo = scripted_repo_with(&git, config);
+
+        let options = PruneOptions {
+            force: true,
+            ..Default::default()
+        };
+        let report = prune(&repo, PruneSource::Fetch, &options).unwrap();
+        assert!(report.deleted.is_empty());
+        assert_eq!(
+            report.skipped[0].reason,
+            SkipReason::Protected {
+                glob: String::from("feat*")
+            }
+        );
+        assert!(!git.calls().iter().any(|call| call.starts_with("branch -D")));
+    }
+
+    #[test]
+    fn never_deletes_the_current_branch() {
+        let git = prune_runner()
+            .then(
+                REMOTE_REFS,
+                "refs/remotes/origin/main m1\nrefs/remotes/origin/feature f1",
+            )
+            .then(REMOTE_REFS, "refs/remotes/origin/feature f1")
+            .with(
+                "pull --prune",
+                " - [deleted]         (none)     -> origin/main",
+            );
+        let report = prune(
+            &scripted_repo(&git),
+            PruneSource::Pull,
+            &PruneOptions::default(),
+        )
+        .unwrap();
+        assert!(report.deleted.is_empty());
+        assert_eq!(
+            report.skipped,
+            vec![SkippedBranch {
+                branch: String::from("main"),
+                reason: SkipReason::CurrentBranch,
+            }]
+        );
+        assert!(!git.calls().iter().any(|call| call.starts_with("branch -D")));
+    }
+
+    const WORKTREES: &str = "worktree /repo\nbranch refs/heads/main\n\nworktree /repo_feature\nbranch refs/heads/feature";
+
+    #[test]
+    fn keeps_branches_checked_out_in_dirty_worktrees() {
+        let git = fetch_pruning_feature(prune_runner(), 1)
+            .with("worktree list --porcelain", WORKTREES)
+            .with("-C /repo_feature status --porcelain", " M src/lib.rs");
+        let options = PruneOptions {
+            remove_worktrees: true,
+            ..Default::default()
+        };
+        let report = prune(&scripted_repo(&git), PruneSource::Fetch, &options).unwrap();
+
+        assert!(report.deleted.is_empty());
+        assert_eq!(
+            report.skipped[0].reason,
+            SkipReason::CheckedOut {
+                worktree: String::from("/repo_feature"),
+                dirty: true,
+            }
+        );
+        assert!(!git
+            .calls()
+            .iter()
+            .any(|call| call.starts_with("worktree remove")));
+    }
+
+    #[test]
+    fn removes_clean_worktrees_of_pruned_branches() {
+        let git =
+            fetch_pruning_feature(prune_runner(), 2).with("worktree list --porcelain", WORKTREES);
+        let repo = scripted_repo(&git);
+
+        let report = prune(&repo, PruneSource::Fetch, &PruneOptions::default()).unwrap();
+        assert!(report.deleted.is_empty());
+        assert!(!git.calls().contains(&String::from("branch -D feature")));
+
+        let options = PruneOptions {
+            remove_worktrees: true,
+            ..Default::default()
+        };
+        let report = prune(&repo, PruneSource::Fetch, &options).unwrap();
+        assert_eq!(report.deleted, vec![String::from("feature")]);
+        assert_eq!(
+            report.removed_worktrees,
+            vec![String::from("/repo_feature")]
+        );
+        assert!(report.skipped.is_empty());
+        let calls = git.calls();
+        assert!(calls.contains(&String::from("worktree remove /repo_feature")));
+        assert!(calls.contains(&String::from("branch -D feature")));
+    }
+
+    const TRACK: &str = "for-each-ref --format=%(refname:short) %(upstream:track) refs/heads";
+
+    #[test]
+    fn gone_deletes_branches_whose_upstream_is_gone() {
+        let git = ScriptedRunner::new()
+            .with("rev-parse --abbrev-ref HEAD", "current")
+            .with(
+                TRACK,
+                "main [behind 2]\ncurrent [gone]\nfeature [gone]\nelsewhere [gone]\nlocal ",
+            )
+            .with(
+                "worktree list --porcelain",
+                "worktree /repo\nbranch refs/heads/current\n\nworktree /repo_elsewhere\nbranch refs/heads/elsewhere",
+            );
+        let repo = scripted_repo(&git);
+        let report = delete(
+            &repo,
+            plan(
+                &repo,
+                &PruneOptions {
+                    gone: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(report.deleted, vec![String::from("feature")]);
+        assert_eq!(
+            report.skipped,
+            vec![
+                SkippedBranch {
+                    branch: String::from("current"),
+                    reason: SkipReason::CurrentBranch,
+                },
+                SkippedBranch {
+                    branch: String::from("elsewhere"),
+                    reason: SkipReason::CheckedOut {
+                        worktree: String::from("/repo_elsewhere"),
+                        dirty: false,
+                    },
+                },
+            ]
+        );
+        let deletes: Vec<String> = git
+            .calls()
+            .into_iter()
+            .filter(|call| call.starts_with("branch -D"))
+            .collect();
+        assert_eq!(deletes, vec!["branch -D feature"]);
+    }
+
+    fn branches(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn merged_includes_squash_merges() {
+        let git = ScriptedRunner::new()
+            .fail("merge-base --is-ancestor fix main", 1, "")
+            .with("cherry main fix", "+ c1\n+ c2")
+            .with("merge-base main fix", "base")
+            .with(
+                "diff base fix | patch-id --stable",
+                "p-fix 0000000000000000000000000000000000000000",
+            )
+            .with("rev-list --no-merges base..main", "m1\nm2")
+            .with(
+                "diff-tree --stdin -p | patch-id --stable",
+                "p-other m1\np-fix m2",
+            );
+        assert_eq!(
+            merged_branches(&git, &branches(&["fix"]), "main").unwrap(),
+            vec![PrunableBranch {
+                branch: String::from("fix"),
+                reason: PruneReason::Squashed {
+                    into: String::from("main")
+                },
+            }]
+        );
+        assert!(
+            !git.calls().iter().any(|call| call.contains("commit-tree")),
+            "the squash check must not write objects"
+        );
+    }
+
+    #[test]
+    fn merged_detects_rebase_merges_and_unmerged_branches() {
+        let git = ScriptedRunner::new()
+            .fail("merge-base --is-ancestor fix main", 1, "")
+            .with("cherry main fix", "- c1\n- c2")
+            .fail("merge-base --is-ancestor wip main", 1, "")
+            .with("cherry main wip", "- c1\n+ c2")
+            .with("merge-base main wip", "base")
+            .with(
+                "diff base wip | patch-id --stable",
+                "p-wip 0000000000000000000000000000000000000000",
+            )
+            .with("rev-list --no-merges base..main", "m1")
+            .with("diff-tree --stdin -p | patch-id --stable", "p-other m1");
+        assert_eq!(
+            merged_branches(&git, &branches(&["fix", "wip"]), "main").unwrap(),
+            vec![PrunableBranch {
+                branch: String::from("fix"),
+                reason: PruneReason::Rebased {
+                    into: String::from("main")
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn merged_lists_the_commits_on_into_once() {
+        let git = ScriptedRunner::new()
+            .fail("merge-base --is-ancestor a main", 1, "")
+            .with("cherry main a", "+ c1")
+            .with("merge-base main a", "base-a")
+            .with("diff base-a a | patch-id --stable", "p-a 0000")
+            .fail("merge-base --is-ancestor b main", 1, "")
+            .with("cherry main b", "+ c2")
+            .with("merge-base main b", "base-b")
+            .with("diff base-b b | patch-id --stable", "p-b 0000")
+            .with("merge-base --octopus base-a base-b", "base-a")
+            .with("rev-list --no-merges base-a..main", "m1\nm2\nm3")
+            .with(
+                "diff-tree --stdin -p | patch-id --stable",
+                "p-b m1\np-other m2\np-a m3",
+            );
+        let merged = merged_branches(&git, &branches(&["a", "b"]), "main").unwrap();
+        assert_eq!(
+            merged
+                .iter()
+                .map(|candidate| candidate.branch.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        let listings = git
+            .calls()
+            .into_iter()
+            .filter(|call| call.starts_with("rev-list") || call.starts_with("diff-tree"))
+            .count();
+        assert_eq!(listings, 2);
+    }
+
+    #[test]
+    fn merged_plan_skips_trunk_and_protected_branches() {
+        let git = ScriptedRunner::new()
+            .with("rev-parse --abbrev-ref HEAD", "main")
+            .with(
+                UPSTREAMS,
+                "main origin/main\nrelease/1 origin/release/1\ndone origin/done",
+            );
+        let mut config = Config::default();
+        config.apply_flag(crate::config::BRANCH_PROTECTED, Some("release/*"));
+        let repo = scripted_repo_with(&git, config);
+
+        let plan = plan(
+            &repo,
+            &PruneOptions {
+                merged_into: Some(String::from("main")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            plan.branches,
+            vec![PrunableBranch {
+                branch: String::from("done"),
+                reason: PruneReason::Merged {
+                    into: String::from("main")
+                },
+            }]
+        );
+        assert_eq!(
+            plan.skipped[0].reason,
+            SkipReason::Protected {
+                glob: String::from("release/*")
+            }
+        );
+        assert!(!git
+            .calls()
+            .iter()
+            .any(|call| call.contains("--is-ancestor main")));
+    }
+
+    #[test]
+    fn fetch_dry_run_only_records_deletes() {
+        let git = prune_runner().with("fetch --dry-run --prune origin", PRUNED_FEATURE);
+        let config = Config::default();
+        let remotes = Remotes::resolve(&config, None);
+        let recording = Repo::new(Box::new(RecordingRunner::new(git.clone())), config, remotes);
+        let report = prune(&recording, PruneSource::Fetch, &PruneOptions::default()).unwrap();
+        assert_eq!(report.deleted, vec![String::from("feature")]);
+        assert!(!git.calls().contains(&String::from("branch -D feature")));
+    }
 }
@@ -0,0 +1,167 @@
+//! Why a push was rejected, read from git's stderr.
+//!
+//! `lk push` turns the `! [rejected]` / `! [remote rejected]` lines into a
+//! [`Rejection`] with a hint, and with `--rebase-on-reject` recovers from a
+//! non-fast-forward by fetching the branch, rebasing onto it and pushing
+//! again (see [`rebase_onto_remote`]).
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::repo::Repo;
+
+/// The reason the remote refused a push.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rejection {
+    /// The remote branch has commits the local one doesn't.
+    NonFastForward,
+    /// `--force-with-lease` found the remote branch somewhere other than the
+    /// last fetched remote-tracking ref.
+    StaleLease,
+    /// The remote protects the branch (GitHub, GitLab, Bitbucket, ...).
+    ProtectedBranch,
+    /// A server-side hook declined the push.
+    HookDeclined,
+}
+
+impl Rejection {
+    /// Finds the rejection reason in a failed push's stderr, if there is one.
+    pub fn parse(stderr: &str) -> Option<Rejection> {
+        let stderr = stderr.to_lowercase();
+        // Protected branches are usually enforced by a hook, so check them
+        // before the generic hook message.
+        if stderr.contains("protected branch")
+            || stderr.contains("gh006")
+            || stderr.contains("not allowed to push code to protected branches")
+        {
+            return Some(Rejection::ProtectedBranch);
+        }
+        if stderr.contains("(stale info)") {
+            return Some(Rejection::StaleLease);
+        }
+        if stderr.contains("(non-fast-forward)") || stderr.contains("(fetch first)") {
+            return Some(Rejection::NonFastForward);
+        }
+        if stderr.contains("[remote rejected]") || stderr.contains("hook declined") {
+            return Some(Rejection::HookDeclined);
+        }
+        None
+    }
+
+    /// What to do about it, for `branch` on `remote`.
+    pub fn hint(self, remote: &str, branch: &str) -> String {
+        match self {
+            Rejection::NonFastForward => format!(
+                "Run `lk push --rebase-on-reject` to fetch, rebase onto {remote}/{branch} and push again."
+            ),
+            Rejection::StaleLease => format!(
+                "Someone else pushed to {remote}/{branch} since your last fetch. Fetch and review their commits before forcing again."
+            ),
+            Rejection::ProtectedBranch => String::from(
+                "Push to a new branch (`lk new --from HEAD <name>`) and open a pull request instead.",
+            ),
+            Rejection::HookDeclined => {
+                String::from("See the remote's message above for what it requires.")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rejection::NonFastForward => "the remote branch has commits you don't have",
+            Rejection::StaleLease => "the force-with-lease check failed (stale info)",
+            Rejection::ProtectedBranch => "the branch is protected on the remote",
+            Rejection::HookDeclined => "a remote hook declined it",
+        })
+    }
+}
+
+/// Turns a failed push into a precondition error naming the rejection
+/// reason, or returns `err` as is when it isn't a rejection.
+pub fn explain(err: Error, remote: &str, branch: &str) -> Error {
+    match err.stderr().and_then(Rejection::parse) {
+        Some(rejection) => Error::precondition(format!(
+            "Push of {branch} to {remote} was rejected: {rejection}. {}",
+            rejection.hint(remote, branch)
+        )),
+        None => err,
+    }
+}
+
+/// Fetches `branch` from `remote` and rebases the current branch onto it. A
+/// conflict stops the rebase in progress, for the user to resolve.
+pub fn rebase_onto_remote(repo: &Repo, remote: &str, branch: &str) -> Result<()> {
+    let git = repo.git();
+    git.status_quiet("fetch", &["fetch", remote, branch])?;
+    let upstream = format!("{remote}/{branch}");
+    git.status("rebase onto remote branch", &["rebase", &upstream])
+        .map_err(|err| {
+            Error::precondition(format!(
+                "Rebasing {branch} onto {upstream} stopped ({err}). Resolve it, run `git rebase --continue`, then `lk push`."
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EXIT_PRECONDITION;
+    use crate::git::{GitRunner, ScriptedRunner};
+    use crate::repo::scripted_repo;
+    use test_case::test_case;
+
+    #[test_case(
+        " ! [rejected]        fix -> fix (non-fast-forward)",
+        Some(Rejection::NonFastForward) ;
+        "non fast forward"
+    )]
+    #[test_case(
+        " ! [rejected]        fix -> fix (fetch first)",
+        Some(Rejection::NonFastForward) ;
+        "fetch first"
+    )]
+    #[test_case(
+        " ! [rejected]        fix -> fix (stale info)",
+        Some(Rejection::StaleLease) ;
+        "stale lease"
+    )]
+    #[test_case(
+        "remote: error: GH006: Protected branch update failed for refs/heads/main.\n ! [remote rejected] main -> main (protected branch hook declined)",
+        Some(Rejection::ProtectedBranch) ;
+        "github protected branch"
+    )]
+    #[test_case(
+        " ! [remote rejected] fix -> fix (pre-receive hook declined)",
+        Some(Rejection::HookDeclined) ;
+        "hook declined"
+    )]
+    #[test_case("fatal: Could not read from remote repository.", None ; "not a rejection")]
+    fn parses_rejection_reasons(stderr: &str, expected: Option<Rejection>) {
+        assert_eq!(Rejection::parse(stderr), expected);
+    }
+
+    #[test]
+    fn explain_names_the_reason() {
+        let git = ScriptedRunner::new().fail(
+            "push origin fix",
+            1,
+            " ! [rejected] fix -> fix (non-fast-forward)",
+        );
+        let err = git.status("push", &["push", "origin", "fix"]).unwrap_err();
+        let err = explain(err, "origin", "fix");
+        assert_eq!(err.exit_code(), EXIT_PRECONDITION);
+        assert!(err.to_string().contains("--rebase-on-reject"));
+    }
+
+    #[test]
+    fn rebase_fetches_the_branch_first() {
+        let git = ScriptedRunner::new();
+        rebase_onto_remote(&scripted_repo(&git), "origin", "fix").unwrap();
+        assert_eq!(git.calls(), vec!["fetch origin fix", "rebase origin/fix"]);
+    }
+}
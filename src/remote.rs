@@ -0,0 +1,119 @@
+use crate::config::{Config, REMOTE};
+use crate::git::GitRunner;
+
+/// The remote loki falls back to when nothing else is configured.
+pub const DEFAULT_REMOTE: &str = "origin";
+
+/// Resolves which remotes loki fetches from and pushes to.
+///
+/// The fetch remote comes from `--remote`, the `remote` config key or
+/// `origin`. Pushes additionally honor git's own `branch.<name>.pushRemote`
+/// and `remote.pushDefault` unless `--remote` was given explicitly.
+#[derive(Debug, Clone)]
+pub struct Remotes {
+    remote: String,
+    explicit: bool,
+}
+
+impl Remotes {
+    pub fn resolve(config: &Config, flag: Option<&str>) -> Self {
+        let remote = config
+            .resolve_str(REMOTE, flag)
+            .unwrap_or_else(|| String::from(DEFAULT_REMOTE));
+        Remotes {
+            remote,
+            explicit: flag.is_some(),
+        }
+    }
+
+    /// The remote to fetch from and to resolve remote branches against.
+    pub fn fetch_remote(&self) -> &str {
+        &self.remote
+    }
+
+    /// The remote branches are pushed to when nothing branch-specific is set.
+    pub fn default_push_remote(&self, git: &dyn GitRunner) -> String {
+        if self.explicit {
+            return self.remote.clone();
+        }
+        pick_push_remote(
+            None,
+            git_config_value(git, "remote.pushDefault"),
+            &self.remote,
+        )
+    }
+
+    /// True for the fork model: fetch from an upstream remote, push to a
+    /// personal fork (see `lk fork setup`).
+    pub fn is_triangular(&self, git: &dyn GitRunner) -> bool {
+        self.default_push_remote(git) != self.remote
+    }
+
+    /// The remote `branch` should be pushed to.
+    pub fn push_remote(&self, git: &dyn GitRunner, branch: &str) -> String {
+        if self.explicit {
+            return self.remote.clone();
+        }
+        pick_push_remote(
+            git_config_value(git, &format!("branch.{branch}.pushRemote")),
+            git_config_value(git, "remote.pushDefault"),
+            &self.remote,
+        )
+    }
+}
+
+/// Precedence for push remotes once `--remote` has been ruled out.
+fn pick_push_remote(
+    branch_push_remote: Option<String>,
+    push_default: Option<String>,
+    configured: &str,
+) -> String {
+    branch_push_remote
+        .or(push_default)
+        .unwrap_or_else(|| configured.to_string())
+}
+
+/// Names of all remotes configured in the current repository.
+pub fn remote_names(git: &dyn GitRunner) -> Vec<String> {
+    git.stdout("list remotes", &["remote"])
+        .unwrap_or_default()
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+fn git_config_value(git: &dyn GitRunner, key: &str) -> Option<String> {
+    git.stdout("read git config", &["config", "--get", key])
+        .ok()?
+        .into_iter()
+        .next()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_push_remote_wins() {
+        let remote = pick_push_remote(
+            Some(String::from("fork")),
+            Some(String::from("mine")),
+            "origin",
+        );
+        assert_eq!(remote, "fork");
+    }
+
+    #[test]
+    fn push_default_beats_configured_remote() {
+        let remote = pick_push_remote(None, Some(String::from("fork")), "origin");
+        assert_eq!(remote, "fork");
+    }
+
+    #[test]
+    fn falls_back_to_configured_remote() {
+        assert_eq!(pick_push_remote(None, None, "upstream"), "upstream");
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::git::{GitRunner, ProcessRunner};
+use crate::remote::Remotes;
+
+/// A repository loki operates on: how to run git, the effective
+/// configuration and the remotes to fetch from and push to.
+///
+/// Library functions such as [`crate::worktree::add`] and
+/// [`crate::stats::compute`] take a `&Repo`.
+pub struct Repo {
+    git: Box<dyn GitRunner>,
+    config: Config,
+    remotes: Remotes,
+}
+
+impl Repo {
+    /// Opens the repository containing `path`, loading its configuration.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let git = ProcessRunner::in_dir(path);
+        git.stdout("find repo root", &["rev-parse", "--show-toplevel"])
+            .map_err(|_| {
+                Error::precondition(format!("Not a git repository: {}", path.display()))
+            })?;
+        let config = Config::load(&git)?;
+        let remotes = Remotes::resolve(&config, None);
+        Ok(Repo::new(Box::new(git), config, remotes))
+    }
+
+    /// Assembles a repo from parts, e.g. to run against a
+    /// [`crate::git::RecordingRunner`] or [`crate::git::ScriptedRunner`].
+    pub fn new(git: Box<dyn GitRunner>, config: Config, remotes: Remotes) -> Self {
+        Repo {
+            git,
+            config,
+            remotes,
+        }
+    }
+
+    pub fn git(&self) -> &dyn GitRunner {
+        self.git.as_ref()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn remotes(&self) -> &Remotes {
+        &self.remotes
+    }
+
+    /// The top-level directory of the working tree.
+    pub fn root(&self) -> Result<PathBuf> {
+        self.git
+            .stdout("find repo root", &["rev-parse", "--show-toplevel"])?
+            .into_iter()
+            .next()
+            .map(|root| PathBuf::from(root.trim()))
+            .ok_or_else(|| Error::parse("No output from git rev-parse --show-toplevel"))
+    }
+}
+
+/// `<git common dir>/loki`, where loki keeps state shared by every worktree
+/// of the repo.
+pub fn loki_dir(git: &dyn GitRunner) -> Result<PathBuf> {
+    git.stdout(
+        "find git dir",
+        &["rev-parse", "--path-format=absolute", "--git-common-dir"],
+    )?
+    .into_iter()
+    .next()
+    .map(|dir| PathBuf::from(dir.trim()).join("loki"))
+    .ok_or_else(|| Error::parse("No output from git rev-parse --git-common-dir"))
+}
+
+/// A repo with the default config whose git commands `git` answers, for
+/// tests.
+#[cfg(test)]
+pub(crate) fn scripted_repo(git: &crate::git::ScriptedRunner) -> Repo {
+    scripted_repo_with(git, Config::default())
+}
+
+/// Like [`scripted_repo`], with `config`.
+#[cfg(test)]
+pub(crate) fn scripted_repo_with(git: &crate::git::ScriptedRunner, config: Config) -> Repo {
+    let remotes = Remotes::resolve(&config, None);
+    Repo::new(Box::new(git.clone()), config, remotes)
+}
@@ -0,0 +1,184 @@
+//! Shell integration for `lk shell-init`.
+//!
+//! A process can't change its parent shell's directory, so the worktree
+//! commands hand the directory back through a file: the wrapper function
+//! emitted here points [`LOKI_CD_FILE`] at a temp file, runs `lk`, and `cd`s
+//! to whatever path `lk` wrote there.
+
+use std::{path::PathBuf, process::Command};
+
+use clap::ValueEnum;
+use colored::Colorize;
+
+use crate::error::{Error, Result};
+use crate::repo::Repo;
+use crate::vars::LOKI_CD_FILE;
+
+/// Shells `lk shell-init` can emit a wrapper for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    #[value(alias = "powershell")]
+    Pwsh,
+}
+
+const POSIX_WRAPPER: &str = r#"lk() {
+    local lk_cd_file lk_status lk_dir
+    lk_cd_file="$(mktemp "${TMPDIR:-/tmp}/lk-cd.XXXXXX")" || return
+    LOKI_CD_FILE="$lk_cd_file" command lk "$@"
+    lk_status=$?
+    lk_dir="$(cat "$lk_cd_file")"
+    rm -f "$lk_cd_file"
+    if [ -n "$lk_dir" ] && [ "$lk_dir" != "$PWD" ]; then
+        cd -- "$lk_dir" || return
+    fi
+    return $lk_status
+}
+"#;
+
+const FISH_WRAPPER: &str = r#"function lk --wraps lk --description 'loki, changing directory for worktree commands'
+    set -l lk_cd_file (mktemp)
+    or return
+    LOKI_CD_FILE=$lk_cd_file command lk $argv
+    set -l lk_status $status
+    set -l lk_dir (cat $lk_cd_file)
+    rm -f $lk_cd_file
+    if test -n "$lk_dir"; and test "$lk_dir" != "$PWD"
+        cd $lk_dir
+    end
+    return $lk_status
+end
+"#;
+
+const PWSH_WRAPPER: &str = r#"function lk {
+    $lkCdFile = New-TemporaryFile
+    $env:LOKI_CD_FILE = $lkCdFile.FullName
+    try {
+        & (Get-Command lk -CommandType Application | Select-Object -First 1) @args
+        $lkStatus = $LASTEXITCODE
+    } finally {
+        Remove-Item Env:LOKI_CD_FILE -ErrorAction SilentlyContinue
+    }
+    $lkDir = Get-Content -Raw $lkCdFile.FullName
+    Remove-Item $lkCdFile.FullName
+    if ($lkDir -and $lkDir -ne $PWD.Path) {
+        Set-Location $lkDir
+    }
+    $global:LASTEXITCODE = $lkStatus
+}
+"#;
+
+/// The `lk` wrapper function for `shell`. `lk shell-init` follows it with the
+/// shell's completion script.
+pub fn wrapper(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash | Shell::Zsh => POSIX_WRAPPER,
+        Shell::Fish => FISH_WRAPPER,
+        Shell::Pwsh => PWSH_WRAPPER,
+    }
+}
+
+/// The file the shell wrapper asked us to write the new directory to, if
+/// `lk` is running under it.
+pub fn cd_file() -> Option<PathBuf> {
+    std::env::var_os(LOKI_CD_FILE)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Hands `path` to the shell wrapper. Returns `false` when `lk` isn't running
+/// under the wrapper, in which case the caller should print `cd <path>`.
+pub fn request_cd(path: &str) -> Result<bool> {
+    let Some(file) = cd_file() else {
+        return Ok(false);
+    };
+    std::fs::write(&file, path)
+        .map_err(|err| Error::io(format!("Failed to write {}", file.display()), err))?;
+    Ok(true)
+}
+
+/// Runs `script` with `sh -c` (`cmd /C` on Windows) from the repository
+/// root, or prints it in a dry run. `configure` can set environment
+/// variables or redirect output. `name` describes the script in errors.
+pub fn run_script(
+    repo: &Repo,
+    name: &str,
+    script: &str,
+    configure: impl FnOnce(&mut Command),
+) -> Result<()> {
+    if repo.git().is_dry_run() {
+        eprintln!("{} $ {script}", "[dry-run]".yellow());
+        return Ok(());
+    }
+
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(script);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    };
+    if let Ok(root) = repo.root() {
+        command.current_dir(root);
+    }
+    configure(&mut command);
+
+    let status = command.status().map_err(|source| Error::Spawn {
+        name: name.to_string(),
+        command: script.to_string(),
+        source,
+    })?;
+    if !status.success() {
+        return Err(Error::Exit {
+            name: name.to_string(),
+            command: script.to_string(),
+            code: status.code(),
+            stderr: String::new(),
+            streamed: true,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(Shell::Bash ; "bash")]
+    #[test_case(Shell::Zsh ; "zsh")]
+    #[test_case(Shell::Fish ; "fish")]
+    #[test_case(Shell::Pwsh ; "pwsh")]
+    fn wrapper_passes_cd_file_to_lk(shell: Shell) {
+        let script = wrapper(shell);
+        assert!(script.contains(LOKI_CD_FILE));
+        assert!(script.starts_with("function lk") || script.starts_with("lk()"));
+    }
+
+    #[test]
+    fn posix_wrapper_calls_the_binary_not_itself() {
+        assert!(wrapper(Shell::Bash).contains("command lk \"$@\""));
+    }
+
+    #[test_case(Shell::Bash, "return $lk_status" ; "bash")]
+    #[test_case(Shell::Fish, "return $lk_status" ; "fish")]
+    #[test_case(Shell::Pwsh, "$global:LASTEXITCODE = $lkStatus" ; "pwsh")]
+    fn wrapper_keeps_lks_exit_status(shell: Shell, propagate: &str) {
+        let script = wrapper(shell);
+        let last_cd = ["cd ", "Set-Location"]
+            .iter()
+            .filter_map(|cd| script.rfind(cd))
+            .max()
+            .unwrap();
+        assert!(script.rfind(propagate).unwrap() > last_cd);
+    }
+
+    #[test]
+    fn powershell_is_an_alias_for_pwsh() {
+        assert_eq!(Shell::from_str("powershell", true), Ok(Shell::Pwsh));
+    }
+}
@@ -0,0 +1,332 @@
+//! Stacked branches for `lk stack`.
+//!
+//! `lk stack new` records each branch's parent in git config as
+//! `branch.<name>.lokiParent`, together with the parent's tip at the time
+//! (`branch.<name>.lokiParentTip`). When a parent moves, `lk stack restack`
+//! replays each child's own commits onto it with
+//! `git rebase --onto <parent> <recorded tip> <child>`, parents first.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::git::GitRunner;
+use crate::repo::Repo;
+
+const PARENT_KEY: &str = "lokiParent";
+const PARENT_TIP_KEY: &str = "lokiParentTip";
+
+/// A branch with a recorded parent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StackedBranch {
+    pub branch: String,
+    pub parent: String,
+    /// The parent's tip when the branch was created or last restacked.
+    pub parent_tip: Option<String>,
+}
+
+/// Reads `branch.<name>.<key>` for every branch that has it. Git lowercases
+/// the key in `--get-regexp` output but keeps the branch name as is.
+fn branch_config(git: &dyn GitRunner, key: &str) -> BTreeMap<String, String> {
+    let suffix = format!(".{}", key.to_lowercase());
+    let pattern = format!(r"^branch\..*\.{}$", key.to_lowercase());
+    git.stdout("read stack config", &["config", "--get-regexp", &pattern])
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            let branch = name.strip_prefix("branch.")?.strip_suffix(&suffix)?;
+            Some((branch.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Every branch with a recorded parent, by name.
+pub fn branches(git: &dyn GitRunner) -> BTreeMap<String, StackedBranch> {
+    let mut tips = branch_config(git, PARENT_TIP_KEY);
+    branch_config(git, PARENT_KEY)
+        .into_iter()
+        .map(|(branch, parent)| {
+            let parent_tip = tips.remove(&branch);
+            let stacked = StackedBranch {
+                branch: branch.clone(),
+                parent,
+                parent_tip,
+            };
+            (branch, stacked)
+        })
+        .collect()
+}
+
+/// Records `parent` (currently at `tip`) as the parent of `branch`.
+pub fn set_parent(git: &dyn GitRunner, branch: &str, parent: &str, tip: &str) -> Result<()> {
+    git.status_quiet(
+        "record parent branch",
+        &["config", &format!("branch.{branch}.{PARENT_KEY}"), parent],
+    )?;
+    set_parent_tip(git, branch, tip)
+}
+
+fn set_parent_tip(git: &dyn GitRunner, branch: &str, tip: &str) -> Result<()> {
+    git.status_quiet(
+        "record parent tip",
+        &["config", &format!("branch.{branch}.{PARENT_TIP_KEY}"), tip],
+    )
+}
+
+/// The commit `rev` points at, if it exists.
+pub fn tip(git: &dyn GitRunner, rev: &str) -> Option<String> {
+    git.stdout(
+        "resolve commit",
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{rev}^{{commit}}"),
+        ],
+    )
+    .ok()?
+    .into_iter()
+    .next()
+    .map(|sha| sha.trim().to_string())
+}
+
+/// A branch and its children, for `lk stack list`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    pub branch: String,
+    pub children: Vec<Node>,
+}
+
+/// The recorded branches as trees, one per root. A root is a parent that
+/// has no recorded parent itself, e.g. `main`.
+pub fn trees(stacked: &BTreeMap<String, StackedBranch>) -> Vec<Node> {
+    let mut children: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for stacked in stacked.values() {
+        children
+            .entry(stacked.parent.as_str())
+            .or_default()
+            .push(stacked.branch.as_str());
+    }
+
+    fn build(
+        branch: &str,
+        children: &BTreeMap<&str, Vec<&str>>,
+        seen: &mut BTreeSet<String>,
+    ) -> Node {
+        seen.insert(branch.to_string());
+        let kids = children
+            .get(branch)
+            .into_iter()
+            .flatten()
+            .filter(|child| !seen.contains(**child))
+            .copied()
+            .collect::<Vec<_>>();
+        Node {
+            branch: branch.to_string(),
+            children: kids
+                .into_iter()
+                .map(|child| build(child, children, seen))
+                .collect(),
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let roots: Vec<&str> = children
+        .keys()
+        .filter(|parent| !stacked.contains_key(**parent))
+        .copied()
+        .collect();
+    roots
+        .into_iter()
+        .map(|root| build(root, &children, &mut seen))
+        .collect()
+}
+
+/// Recorded branches with every parent before its children.
+pub fn restack_order(stacked: &BTreeMap<String, StackedBranch>) -> Vec<StackedBranch> {
+    fn walk(node: &Node, stacked: &BTreeMap<String, StackedBranch>, out: &mut Vec<StackedBranch>) {
+        for child in &node.children {
+            if let Some(branch) = stacked.get(&child.branch) {
+                out.push(branch.clone());
+            }
+            walk(child, stacked, out);
+        }
+    }
+    let mut order = Vec::new();
+    for tree in trees(stacked) {
+        walk(&tree, stacked, &mut order);
+    }
+    order
+}
+
+/// What restacking one branch did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restacked {
+    /// Its commits were rebased onto the parent's new tip.
+    Rebased,
+    /// The parent hadn't moved, or the branch already contained it.
+    UpToDate,
+    /// The parent branch no longer exists.
+    MissingParent,
+}
+
+/// Rebases `stacked.branch` onto its parent's current tip, leaving the
+/// branch checked out, and records the new parent tip. A conflict stops
+/// the rebase in progress, for the user to resolve.
+pub fn restack(repo: &Repo, stacked: &StackedBranch) -> Result<Restacked> {
+    let git = repo.git();
+    let Some(parent_tip) = tip(git, &stacked.parent) else {
+        return Ok(Restacked::MissingParent);
+    };
+    if stacked.parent_tip.as_deref() == Some(parent_tip.as_str()) {
+        return Ok(Restacked::UpToDate);
+    }
+
+    let contains_parent = git
+        .stdout(
+            "check ancestry",
+            &[
+                "merge-base",
+                "--is-ancestor",
+                &stacked.parent,
+                &stacked.branch,
+            ],
+        )
+        .is_ok();
+    if contains_parent {
+        set_parent_tip(git, &stacked.branch, &parent_tip)?;
+        return Ok(Restacked::UpToDate);
+    }
+
+    // Without a recorded tip, the fork point is the best guess at where the
+    // branch's own commits start.
+    let old_base = match &stacked.parent_tip {
+        Some(old) => old.clone(),
+        None => git
+            .stdout(
+                "find fork point",
+                &["merge-base", &stacked.parent, &stacked.branch],
+            )?
+            .into_iter()
+            .next()
+            .map(|sha| sha.trim().to_string())
+            .ok_or_else(|| {
+                Error::precondition(format!(
+                    "{} and {} have no common history.",
+                    stacked.branch, stacked.parent
+                ))
+            })?,
+    };
+
+    git.status(
+        "rebase onto parent",
+        &[
+            "rebase",
+            "--onto",
+            &stacked.parent,
+            &old_base,
+            &stacked.branch,
+        ],
+    )
+    .map_err(|err| {
+        Error::precondition(format!(
+            "Restacking {} onto {} stopped ({err}). Resolve it, run `git rebase --continue`, then `lk stack restack` again.",
+            stacked.branch, stacked.parent
+        ))
+    })?;
+    set_parent_tip(git, &stacked.branch, &parent_tip)?;
+    Ok(Restacked::Rebased)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::ScriptedRunner;
+    use crate::repo::scripted_repo;
+
+    fn stacked(branch: &str, parent: &str, tip: Option<&str>) -> StackedBranch {
+        StackedBranch {
+            branch: branch.to_string(),
+            parent: parent.to_string(),
+            parent_tip: tip.map(String::from),
+        }
+    }
+
+    fn stack(branches: &[StackedBranch]) -> BTreeMap<String, StackedBranch> {
+        branches
+            .iter()
+            .map(|branch| (branch.branch.clone(), branch.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn branches_read_parents_and_tips_from_git_config() {
+        let git = ScriptedRunner::new()
+            .with(
+                r"config --get-regexp ^branch\..*\.lokiparent$",
+                "branch.users/me/part.2.lokiparent users/me/part.1",
+            )
+            .with(
+                r"config --get-regexp ^branch\..*\.lokiparenttip$",
+                "branch.users/me/part.2.lokiparenttip abc123",
+            );
+        assert_eq!(
+            branches(&git).into_values().collect::<Vec<_>>(),
+            vec![stacked(
+                "users/me/part.2",
+                "users/me/part.1",
+                Some("abc123")
+            )]
+        );
+    }
+
+    #[test]
+    fn order_puts_parents_before_children() {
+        let stack = stack(&[
+            stacked("c", "b", None),
+            stacked("b", "a", None),
+            stacked("a", "main", None),
+            stacked("x", "main", None),
+        ]);
+        let order: Vec<String> = restack_order(&stack)
+            .into_iter()
+            .map(|branch| branch.branch)
+            .collect();
+        assert_eq!(order, vec!["a", "b", "c", "x"]);
+
+        let trees = trees(&stack);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].branch, "main");
+        assert_eq!(trees[0].children.len(), 2);
+    }
+
+    #[test]
+    fn restack_rebases_from_the_recorded_tip() {
+        let git = ScriptedRunner::new()
+            .with("rev-parse --verify --quiet a^{commit}", "new-a")
+            .fail("merge-base --is-ancestor a b", 1, "");
+        let result = restack(&scripted_repo(&git), &stacked("b", "a", Some("old-a")));
+        assert_eq!(result.unwrap(), Restacked::Rebased);
+        let calls = git.calls();
+        assert!(calls.contains(&String::from("rebase --onto a old-a b")));
+        assert_eq!(calls.last().unwrap(), "config branch.b.lokiParentTip new-a");
+    }
+
+    #[test]
+    fn restack_skips_branches_whose_parent_has_not_moved() {
+        let git = ScriptedRunner::new().with("rev-parse --verify --quiet a^{commit}", "same");
+        let result = restack(&scripted_repo(&git), &stacked("b", "a", Some("same")));
+        assert_eq!(result.unwrap(), Restacked::UpToDate);
+        assert!(!git.calls().iter().any(|call| call.starts_with("rebase")));
+    }
+
+    #[test]
+    fn restack_reports_missing_parent() {
+        let git = ScriptedRunner::new();
+        let result = restack(&scripted_repo(&git), &stacked("b", "gone", Some("old")));
+        assert_eq!(result.unwrap(), Restacked::MissingParent);
+    }
+}
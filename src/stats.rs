@@ -0,0 +1,998 @@
+//! Commit statistics by author, as shown by `lk repo stats`.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration as ChronoDuration, Months, NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::git::GitRunner;
+use crate::repo::Repo;
+
+/// Which commits to count, see [`compute`].
+#[derive(Debug, Default, Clone)]
+pub struct StatsOptions {
+    /// Only commits from the last N days.
+    pub days: Option<u32>,
+    /// Only commits from the last N weeks.
+    pub weeks: Option<u32>,
+    /// Only commits from the last N months.
+    pub months: Option<u32>,
+    /// Commits starting from this date (overrides `days`/`weeks`/`months`).
+    pub from: Option<NaiveDate>,
+    /// Commits through this date (defaults to today).
+    pub to: Option<NaiveDate>,
+    /// Only count commits on the first-parent chain of HEAD.
+    pub first_parent: bool,
+    /// Only commits whose author name fuzzily matches one of these.
+    pub names: Vec<String>,
+    /// Only commits whose author email fuzzily matches one of these.
+    pub emails: Vec<String>,
+    /// Count every SHA instead of collapsing commits that share a patch-id.
+    pub no_dedup: bool,
+}
+
+/// The window of history that was analyzed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimeRange {
+    pub start_ts: Option<i64>,
+    pub end_ts: i64,
+    pub start_label: String,
+    pub end_label: String,
+    /// True when no `--to` date was given and the range runs to the newest commit.
+    pub end_is_latest: bool,
+}
+
+/// Commit counts for one author, keyed by their canonical email.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuthorStats {
+    pub name: Option<String>,
+    pub email: String,
+    pub commits: usize,
+    /// Timestamps of the author's newest and oldest counted commits.
+    pub latest_ts: i64,
+    pub oldest_ts: i64,
+}
+
+impl AuthorStats {
+    /// `Name <email>`, or just the email when no name was seen.
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{name} <{}>", self.email),
+            None => self.email.clone(),
+        }
+    }
+
+    /// Average commits per week over the author's own active window.
+    pub fn commits_per_week(&self) -> f64 {
+        (self.commits as f64) / active_weeks_inclusive(self.latest_ts, self.oldest_ts)
+    }
+}
+
+/// The result of [`compute`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsReport {
+    pub range: TimeRange,
+    /// Date of the newest counted commit, if any.
+    pub latest_commit_date: Option<NaiveDate>,
+    pub total_commits: usize,
+    /// Commits dropped because they shared a patch-id with another commit.
+    pub duplicates_collapsed: usize,
+    /// Every author, most commits first.
+    pub authors: Vec<AuthorStats>,
+}
+
+impl StatsReport {
+    /// The end of the range, resolved to the newest commit's date when the
+    /// range was open-ended.
+    pub fn end_label(&self) -> String {
+        match self.latest_commit_date {
+            Some(date) if self.range.end_is_latest => format!("{date} (latest commit)"),
+            _ => self.range.end_label.clone(),
+        }
+    }
+}
+
+/// Counts commits by author in `repo`, collapsing duplicate patches unless
+/// `no_dedup` is set.
+pub fn compute(repo: &Repo, options: &StatsOptions) -> Result<StatsReport> {
+    let git = repo.git();
+    let range = resolve_time_range(options)?;
+
+    // Phase 1: collect candidate commits in one `git log` pass.
+    let raw_commits = collect_raw_commits(git, options, &range)?;
+
+    // Phase 2: optionally deduplicate by patch-id.
+    let (effective_commits, duplicates_collapsed) = if options.no_dedup {
+        (raw_commits, 0usize)
+    } else {
+        let shas: Vec<&str> = raw_commits.iter().map(|c| c.sha.as_str()).collect();
+        let patch_ids = compute_patch_ids(git, &shas)?;
+        dedup_commits(raw_commits, &patch_ids)
+    };
+
+    // Phase 3: tally (filters, aliasing, active windows).
+    let mut totals: HashMap<String, usize> = HashMap::new();
+    let mut email_to_name: HashMap<String, String> = HashMap::new();
+    let mut email_aliases: HashMap<String, String> = HashMap::new();
+    let mut name_to_email: HashMap<String, String> = HashMap::new();
+    let mut latest_commit_date_in_range: Option<NaiveDate> = None;
+    let mut latest_commit_ts_by_author: HashMap<String, i64> = HashMap::new();
+    let mut oldest_commit_ts_by_author: HashMap<String, i64> = HashMap::new();
+
+    let name_filters_lower: Vec<String> = options.names.iter().map(|s| s.to_lowercase()).collect();
+    let email_filters_lower: Vec<String> =
+        options.emails.iter().map(|s| s.to_lowercase()).collect();
+
+    // `collect_raw_commits` preserves git log's reverse-chronological order, so
+    // the per-author "latest/oldest" tracking remains correct.
+    for commit in &effective_commits {
+        let email = if commit.email.is_empty() {
+            "Unknown"
+        } else {
+            commit.email.as_str()
+        };
+        let name = commit.name.as_str();
+        let canonical_email =
+            canonicalize_author(email, name, &mut email_aliases, &mut name_to_email);
+
+        if !matches_author_filters_lowered(
+            name,
+            canonical_email.as_str(),
+            &name_filters_lower,
+            &email_filters_lower,
+        ) {
+            continue;
+        }
+
+        if !name.is_empty() {
+            email_to_name
+                .entry(canonical_email.clone())
+                .or_insert_with(|| name.to_string());
+        }
+
+        let date = DateTime::from_timestamp(commit.timestamp, 0)
+            .ok_or_else(|| {
+                Error::parse(format!(
+                    "Commit timestamp out of range: {}",
+                    commit.timestamp
+                ))
+            })?
+            .date_naive();
+        if latest_commit_date_in_range.is_none() {
+            latest_commit_date_in_range = Some(date);
+        } else if let Some(current_latest) = latest_commit_date_in_range {
+            if date > current_latest {
+                latest_commit_date_in_range = Some(date);
+            }
+        }
+
+        // Per-author windows: track min and max independently because dedup
+        // may have reordered commits relative to git log's stream.
+        latest_commit_ts_by_author
+            .entry(canonical_email.clone())
+            .and_modify(|ts| {
+                if commit.timestamp > *ts {
+                    *ts = commit.timestamp;
+                }
+            })
+            .or_insert(commit.timestamp);
+        oldest_commit_ts_by_author
+            .entry(canonical_email.clone())
+            .and_modify(|ts| {
+                if commit.timestamp < *ts {
+                    *ts = commit.timestamp;
+                }
+            })
+            .or_insert(commit.timestamp);
+
+        *totals.entry(canonical_email.clone()).or_insert(0) += 1;
+    }
+
+    let mut authors: Vec<AuthorStats> = totals
+        .into_iter()
+        .map(|(email, commits)| AuthorStats {
+            name: email_to_name.get(&email).cloned(),
+            latest_ts: latest_commit_ts_by_author[&email],
+            oldest_ts: oldest_commit_ts_by_author[&email],
+            email,
+            commits,
+        })
+        .collect();
+    authors.sort_by(|a, b| {
+        b.commits
+            .cmp(&a.commits)
+            .then_with(|| a.email.cmp(&b.email))
+    });
+
+    Ok(StatsReport {
+        range,
+        latest_commit_date: latest_commit_date_in_range,
+        total_commits: authors.iter().map(|author| author.commits).sum(),
+        duplicates_collapsed,
+        authors,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RawCommit {
+    sha: String,
+    timestamp: i64,
+    name: String,
+    email: String,
+}
+
+fn collect_raw_commits(
+    git: &dyn GitRunner,
+    options: &StatsOptions,
+    range: &TimeRange,
+) -> Result<Vec<RawCommit>> {
+    let mut git_args: Vec<String> = vec!["log".to_string()];
+    if options.first_parent {
+        git_args.push("--first-parent".to_string());
+    }
+    git_args.push("--pretty=format:%H%x09%ct%x09%an%x09%ae".to_string());
+    if let Some(start_ts) = range.start_ts {
+        git_args.push(format!("--since=@{start_ts}"));
+    }
+    if !range.end_is_latest {
+        git_args.push(format!("--until=@{}", range.end_ts));
+    }
+    git_args.push("HEAD".to_string());
+
+    let git_args: Vec<&str> = git_args.iter().map(String::as_str).collect();
+    let lines = git.stdout("collect author stats", &git_args)?;
+
+    let mut commits = Vec::new();
+    for raw_line in lines {
+        let trimmed = raw_line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        let mut parts = trimmed.splitn(4, '\t');
+        let (sha_part, timestamp_part, name_part, email_part) = match (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) {
+            (Some(sha), Some(ts), Some(name), Some(email)) => (sha, ts, name, email),
+            _ => {
+                return Err(Error::parse(format!(
+                        "Unexpected git log output (expected `<sha>\\t<timestamp>\\t<name>\\t<email>`): `{trimmed}`"
+                    )));
+            }
+        };
+        if sha_part.is_empty() || timestamp_part.is_empty() {
+            return Err(Error::parse(format!(
+                "Unexpected git log output (expected `<sha>\\t<timestamp>\\t<name>\\t<email>`): `{trimmed}`"
+            )));
+        }
+
+        let timestamp = timestamp_part.parse::<i64>().map_err(|err| {
+            Error::parse(format!(
+                "Failed to parse git log timestamp `{timestamp_part}`: {err}"
+            ))
+        })?;
+
+        commits.push(RawCommit {
+            sha: sha_part.to_string(),
+            timestamp,
+            name: name_part.trim().to_string(),
+            email: email_part.trim().to_string(),
+        });
+    }
+
+    Ok(commits)
+}
+
+/// Resolve each commit SHA to a `git patch-id --stable` value, when one
+/// exists. Returns a map from SHA → patch-id. Commits with no patch
+/// (empty diffs, merge commits) simply won't appear in the map.
+pub(crate) fn compute_patch_ids(
+    git: &dyn GitRunner,
+    shas: &[&str],
+) -> Result<HashMap<String, String>> {
+    if shas.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    // git diff-tree --stdin -p < SHAs  |  git patch-id --stable
+    let input: String = shas.iter().map(|sha| format!("{sha}\n")).collect();
+    let lines = git.pipe(
+        "compute patch ids",
+        &input,
+        &["diff-tree", "--stdin", "-p"],
+        &["patch-id", "--stable"],
+    )?;
+
+    let mut map: HashMap<String, String> = HashMap::new();
+    for line in lines {
+        // Each line is "<patch-id> <commit-sha>".
+        let mut parts = line.split_whitespace();
+        let (Some(pid), Some(commit_sha)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        map.insert(commit_sha.to_string(), pid.to_string());
+    }
+
+    Ok(map)
+}
+
+/// Collapse commits that share a `git patch-id`. Commits without an
+/// entry in `patch_ids` are treated as unique (their SHA becomes their
+/// own dedup key). For each patch-id group, the winner is the commit
+/// with the smallest `(timestamp, sha)` tuple — i.e. earliest author
+/// date, with SHA breaking ties deterministically.
+///
+/// Returns `(winners, duplicates_collapsed)`.
+fn dedup_commits(
+    commits: Vec<RawCommit>,
+    patch_ids: &HashMap<String, String>,
+) -> (Vec<RawCommit>, usize) {
+    let original_count = commits.len();
+    let mut by_key: HashMap<String, RawCommit> = HashMap::new();
+    for commit in commits {
+        let key = match patch_ids.get(&commit.sha) {
+            Some(pid) => format!("p:{pid}"),
+            None => format!("s:{}", commit.sha),
+        };
+        match by_key.entry(key) {
+            std::collections::hash_map::Entry::Vacant(e) => {
+                e.insert(commit);
+            }
+            std::collections::hash_map::Entry::Occupied(mut e) => {
+                let existing = e.get();
+                let new_key = (commit.timestamp, commit.sha.as_str());
+                let existing_key = (existing.timestamp, existing.sha.as_str());
+                if new_key < existing_key {
+                    e.insert(commit);
+                }
+            }
+        }
+    }
+    let winners: Vec<RawCommit> = by_key.into_values().collect();
+    let dups = original_count - winners.len();
+    (winners, dups)
+}
+
+fn canonicalize_author(
+    email: &str,
+    name: &str,
+    email_aliases: &mut HashMap<String, String>,
+    name_to_email: &mut HashMap<String, String>,
+) -> String {
+    if let Some(existing_email) = email_aliases.get(email) {
+        return existing_email.clone();
+    }
+
+    if !name.is_empty() {
+        if let Some(existing_email) = name_to_email.get(name) {
+            email_aliases.insert(email.to_string(), existing_email.clone());
+            return existing_email.clone();
+        } else {
+            name_to_email.insert(name.to_string(), email.to_string());
+        }
+    }
+
+    let canonical = email.to_string();
+    email_aliases
+        .entry(canonical.clone())
+        .or_insert_with(|| canonical.clone());
+    canonical
+}
+
+fn matches_author_filters_lowered(
+    name: &str,
+    email: &str,
+    name_filters_lower: &[String],
+    email_filters_lower: &[String],
+) -> bool {
+    if !name_filters_lower.is_empty() {
+        if name.is_empty() {
+            return false;
+        }
+        let name_lower = name.to_lowercase();
+        if !name_filters_lower
+            .iter()
+            .any(|filter| name_lower.contains(filter))
+        {
+            return false;
+        }
+    }
+
+    if !email_filters_lower.is_empty() {
+        if email.is_empty() {
+            return false;
+        }
+        let email_lower = email.to_lowercase();
+        if !email_filters_lower
+            .iter()
+            .any(|filter| email_lower.contains(filter))
+        {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn active_weeks_inclusive(latest_ts: i64, oldest_ts: i64) -> f64 {
+    let span_seconds = latest_ts.saturating_sub(oldest_ts).max(0);
+    // Inclusive day window keeps single-commit authors reasonable (1 day => 1/7 week),
+    // and still properly boosts authors who only started partway through the range.
+    let active_days = (span_seconds / 86_400) + 1;
+    (active_days as f64) / 7.0
+}
+
+fn active_days_inclusive(latest_ts: i64, oldest_ts: i64) -> i64 {
+    let span_seconds = latest_ts.saturating_sub(oldest_ts).max(0);
+    (span_seconds / 86_400) + 1
+}
+
+pub fn format_active_span(latest_ts: i64, oldest_ts: i64) -> String {
+    // Use an average Gregorian year/month to avoid jumpy “calendar” math.
+    let days = active_days_inclusive(latest_ts, oldest_ts) as f64;
+    let years = days / 365.25;
+    if years >= 1.0 {
+        let rounded = (years * 10.0).round() / 10.0;
+        let unit = if (rounded - 1.0).abs() < 1e-9 {
+            "year"
+        } else {
+            "years"
+        };
+        format!("{rounded:.1} {unit}")
+    } else {
+        let months = days / (365.25 / 12.0);
+        let rounded = (months * 10.0).round() / 10.0;
+        let unit = if (rounded - 1.0).abs() < 1e-9 {
+            "month"
+        } else {
+            "months"
+        };
+        format!("{rounded:.1} {unit}")
+    }
+}
+
+fn resolve_time_range(options: &StatsOptions) -> Result<TimeRange> {
+    let now = Utc::now();
+    let (reference_end_dt, end_label, end_is_latest, end_ts) = if let Some(to_date) = options.to {
+        let end_naive = to_date
+            .and_hms_opt(23, 59, 59)
+            .ok_or_else(|| Error::precondition("invalid --to date"))?;
+        let dt = DateTime::<Utc>::from_naive_utc_and_offset(end_naive, Utc);
+        (dt, to_date.to_string(), false, dt.timestamp())
+    } else {
+        (now, String::from("latest commit"), true, i64::MAX)
+    };
+
+    let mut start_label = String::from("initial commit");
+    let mut start_dt: Option<DateTime<Utc>> = None;
+
+    if let Some(from_date) = options.from {
+        let from_naive = from_date
+            .and_hms_opt(0, 0, 0)
+            .ok_or_else(|| Error::precondition("invalid --from date"))?;
+        start_dt = Some(DateTime::<Utc>::from_naive_utc_and_offset(from_naive, Utc));
+        start_label = from_date.to_string();
+    } else if let Some(days) = options.days {
+        if days == 0 {
+            return Err(Error::precondition("--days must be greater than zero."));
+        }
+        let dt = reference_end_dt - ChronoDuration::days(days as i64);
+        start_label = format!(
+            "{} (last {} day{})",
+            dt.format("%Y-%m-%d"),
+            days,
+            if days == 1 { "" } else { "s" }
+        );
+        start_dt = Some(dt);
+    } else if let Some(weeks) = options.weeks {
+        if weeks == 0 {
+            return Err(Error::precondition("--weeks must be greater than zero."));
+        }
+        let dt = reference_end_dt - ChronoDuration::weeks(weeks as i64);
+        start_label = format!(
+            "{} (last {} week{})",
+            dt.format("%Y-%m-%d"),
+            weeks,
+            if weeks == 1 { "" } else { "s" }
+        );
+        start_dt = Some(dt);
+    } else if let Some(months_count) = options.months {
+        if months_count == 0 {
+            return Err(Error::precondition("--months must be greater than zero."));
+        }
+        let end_date = reference_end_dt.date_naive();
+        let start_date = end_date
+            .checked_sub_months(Months::new(months_count))
+            .ok_or_else(|| Error::precondition("month subtraction overflow"))?;
+        let start_naive = start_date
+            .and_hms_opt(0, 0, 0)
+            .ok_or_else(|| Error::precondition("invalid computed month range"))?;
+        let dt = DateTime::<Utc>::from_naive_utc_and_offset(start_naive, Utc);
+        start_label = format!(
+            "{} (last {} month{})",
+            start_date,
+            months_count,
+            if months_count == 1 { "" } else { "s" }
+        );
+        start_dt = Some(dt);
+    }
+
+    if let Some(start_dt_value) = start_dt {
+        if start_dt_value > reference_end_dt {
+            return Err(Error::precondition(
+                "The computed start date occurs after the end date. Check your filters.",
+            ));
+        }
+    }
+
+    Ok(TimeRange {
+        start_ts: start_dt.map(|dt| dt.timestamp()),
+        end_ts,
+        start_label,
+        end_label,
+        end_is_latest,
+    })
+}
+
+#[allow(dead_code)]
+fn matches_author_filters(name: &str, email: &str, options: &StatsOptions) -> bool {
+    if !options.names.is_empty()
+        && (name.is_empty()
+            || !options
+                .names
+                .iter()
+                .any(|filter| name.to_lowercase().contains(&filter.to_lowercase())))
+    {
+        return false;
+    }
+
+    if !options.emails.is_empty()
+        && (email.is_empty()
+            || !options
+                .emails
+                .iter()
+                .any(|filter| email.to_lowercase().contains(&filter.to_lowercase())))
+    {
+        return false;
+    }
+
+    true
+}
+
+/// Parses a `YYYY-MM-DD` date argument.
+pub fn parse_naive_date(value: &str) -> std::result::Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|err| format!("Invalid date `{value}` (expected YYYY-MM-DD): {err}"))
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    use super::*;
+    use crate::git::ScriptedRunner;
+    use std::collections::HashMap;
+
+    #[test]
+    fn canonicalize_author_reuses_first_email_for_name() {
+        let mut email_aliases = HashMap::new();
+        let mut name_to_email = HashMap::new();
+
+        let first = canonicalize_author(
+            "alias@microsoft.com",
+            "msuser",
+            &mut email_aliases,
+            &mut name_to_email,
+        );
+        assert_eq!(first, "alias@microsoft.com");
+
+        let second = canonicalize_author(
+            "msuser@microsoft.com",
+            "msuser",
+            &mut email_aliases,
+            &mut name_to_email,
+        );
+        assert_eq!(second, "alias@microsoft.com");
+    }
+
+    #[test]
+    fn canonicalize_author_handles_name_change_after_alias() {
+        let mut email_aliases = HashMap::new();
+        let mut name_to_email = HashMap::new();
+
+        canonicalize_author(
+            "alias@microsoft.com",
+            "msuser",
+            &mut email_aliases,
+            &mut name_to_email,
+        );
+        canonicalize_author(
+            "msuser@microsoft.com",
+            "msuser",
+            &mut email_aliases,
+            &mut name_to_email,
+        );
+        let reused = canonicalize_author(
+            "msuser@microsoft.com",
+            "display name",
+            &mut email_aliases,
+            &mut name_to_email,
+        );
+        assert_eq!(reused, "alias@microsoft.com");
+    }
+
+    #[test]
+    fn matches_author_filters_by_name_exact() {
+        let mut options = StatsOptions::default();
+        options.names = vec![String::from("Example User")];
+
+        assert!(matches_author_filters(
+            "Example User",
+            "user@example.com",
+            &options
+        ));
+        assert!(!matches_author_filters(
+            "Someone Else",
+            "user@example.com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn matches_author_filters_by_name_fuzzy() {
+        let mut options = StatsOptions::default();
+        options.names = vec![String::from("example")];
+
+        // Fuzzy match: "example" is a substring of "Example User"
+        assert!(matches_author_filters(
+            "Example User",
+            "user@example.com",
+            &options
+        ));
+        // Case insensitive fuzzy match
+        assert!(matches_author_filters(
+            "EXAMPLE USER",
+            "user@example.com",
+            &options
+        ));
+        // No match
+        assert!(!matches_author_filters(
+            "Someone Else",
+            "user@example.com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn matches_author_filters_by_name_case_insensitive() {
+        let mut options = StatsOptions::default();
+        options.names = vec![String::from("EXAMPLE USER")];
+
+        assert!(matches_author_filters(
+            "example user",
+            "user@example.com",
+            &options
+        ));
+        assert!(matches_author_filters(
+            "Example User",
+            "user@example.com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn matches_author_filters_by_email_exact() {
+        let mut options = StatsOptions::default();
+        options.emails = vec![String::from("user@example.com")];
+
+        assert!(matches_author_filters(
+            "Example User",
+            "user@example.com",
+            &options
+        ));
+        assert!(!matches_author_filters(
+            "Example User",
+            "other@example.com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn matches_author_filters_by_email_fuzzy() {
+        let mut options = StatsOptions::default();
+        options.emails = vec![String::from("example.com")];
+
+        // Fuzzy match: "example.com" is a substring of "user@example.com"
+        assert!(matches_author_filters(
+            "Example User",
+            "user@example.com",
+            &options
+        ));
+        // Also matches other emails from the same domain
+        assert!(matches_author_filters(
+            "Example User",
+            "other@example.com",
+            &options
+        ));
+        // No match for different domain
+        assert!(!matches_author_filters(
+            "Example User",
+            "user@other.com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn matches_author_filters_by_email_case_insensitive() {
+        let mut options = StatsOptions::default();
+        options.emails = vec![String::from("USER@EXAMPLE.COM")];
+
+        assert!(matches_author_filters(
+            "Example User",
+            "user@example.com",
+            &options
+        ));
+        assert!(matches_author_filters(
+            "Example User",
+            "User@Example.Com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn matches_author_filters_requires_all_filters() {
+        let mut options = StatsOptions::default();
+        options.names = vec![String::from("Example User")];
+        options.emails = vec![String::from("user@example.com")];
+
+        assert!(matches_author_filters(
+            "Example User",
+            "user@example.com",
+            &options
+        ));
+        assert!(!matches_author_filters(
+            "Example User",
+            "other@other.com",
+            &options
+        ));
+        assert!(!matches_author_filters(
+            "Another User",
+            "user@example.com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn matches_author_filters_fuzzy_with_multiple_filters() {
+        let mut options = StatsOptions::default();
+        options.names = vec![String::from("john"), String::from("jane")];
+
+        // Matches first filter
+        assert!(matches_author_filters(
+            "John Smith",
+            "john@example.com",
+            &options
+        ));
+        // Matches second filter
+        assert!(matches_author_filters(
+            "Jane Doe",
+            "jane@example.com",
+            &options
+        ));
+        // No match
+        assert!(!matches_author_filters(
+            "Bob Wilson",
+            "bob@example.com",
+            &options
+        ));
+    }
+
+    #[test]
+    fn active_weeks_inclusive_counts_single_day_as_one_seventh_week() {
+        // Same-day activity => 1 active day => 1/7 week.
+        let weeks = active_weeks_inclusive(1_700_000_000, 1_700_000_000);
+        assert!((weeks - (1.0 / 7.0)).abs() < 1e-9, "weeks={weeks}");
+    }
+
+    #[test]
+    fn active_weeks_inclusive_increases_with_span_days() {
+        // 8 days inclusive => 8/7 weeks.
+        let oldest = 1_700_000_000;
+        let latest = oldest + (7 * 86_400);
+        let weeks = active_weeks_inclusive(latest, oldest);
+        assert!((weeks - (8.0 / 7.0)).abs() < 1e-9, "weeks={weeks}");
+    }
+
+    #[test]
+    fn format_active_span_uses_months_below_one_year() {
+        // 6 months-ish
+        let oldest = 1_700_000_000;
+        let latest = oldest + (183 * 86_400);
+        let span = format_active_span(latest, oldest);
+        assert!(
+            span.contains("month"),
+            "expected months in span, got `{span}`"
+        );
+    }
+
+    #[test]
+    fn format_active_span_uses_years_at_or_above_one_year() {
+        // ~400 days
+        let oldest = 1_700_000_000;
+        let latest = oldest + (399 * 86_400);
+        let span = format_active_span(latest, oldest);
+        assert!(
+            span.contains("year"),
+            "expected years in span, got `{span}`"
+        );
+    }
+
+    fn raw(sha: &str, ts: i64, name: &str, email: &str) -> RawCommit {
+        RawCommit {
+            sha: sha.to_string(),
+            timestamp: ts,
+            name: name.to_string(),
+            email: email.to_string(),
+        }
+    }
+
+    #[test]
+    fn dedup_commits_no_patch_ids_means_all_unique() {
+        let commits = vec![
+            raw("aaa", 1000, "Alice", "a@example.com"),
+            raw("bbb", 1100, "Bob", "b@example.com"),
+            raw("ccc", 1200, "Cara", "c@example.com"),
+        ];
+        let patch_ids: HashMap<String, String> = HashMap::new();
+        let (winners, dups) = dedup_commits(commits.clone(), &patch_ids);
+        assert_eq!(dups, 0);
+        assert_eq!(winners.len(), 3);
+    }
+
+    #[test]
+    fn dedup_commits_collapses_shared_patch_id_keeping_earliest() {
+        // Two commits share patch-id `pid1`. The earlier author date wins.
+        let commits = vec![
+            raw("late", 2000, "Alice", "a@example.com"),
+            raw("early", 1000, "Bob", "b@example.com"),
+            raw("solo", 1500, "Cara", "c@example.com"),
+        ];
+        let mut patch_ids = HashMap::new();
+        patch_ids.insert("late".to_string(), "pid1".to_string());
+        patch_ids.insert("early".to_string(), "pid1".to_string());
+        patch_ids.insert("solo".to_string(), "pid2".to_string());
+
+        let (mut winners, dups) = dedup_commits(commits, &patch_ids);
+        winners.sort_by(|a, b| a.sha.cmp(&b.sha));
+
+        assert_eq!(dups, 1);
+        assert_eq!(winners.len(), 2);
+
+        let by_sha: HashMap<&str, &RawCommit> =
+            winners.iter().map(|c| (c.sha.as_str(), c)).collect();
+        // Earliest-by-timestamp (1000) wins, attributed to Bob.
+        assert_eq!(by_sha["early"].name, "Bob");
+        // `solo` is unaffected.
+        assert_eq!(by_sha["solo"].name, "Cara");
+        // `late` was dropped.
+        assert!(!by_sha.contains_key("late"));
+    }
+
+    #[test]
+    fn dedup_commits_tiebreaks_equal_timestamps_by_sha() {
+        // Two commits share patch-id AND timestamp. Lower SHA wins for
+        // determinism.
+        let commits = vec![
+            raw("b_sha", 1000, "Alice", "a@example.com"),
+            raw("a_sha", 1000, "Bob", "b@example.com"),
+        ];
+        let mut patch_ids = HashMap::new();
+        patch_ids.insert("b_sha".to_string(), "pid1".to_string());
+        patch_ids.insert("a_sha".to_string(), "pid1".to_string());
+
+        let (winners, dups) = dedup_commits(commits, &patch_ids);
+        assert_eq!(dups, 1);
+        assert_eq!(winners.len(), 1);
+        assert_eq!(winners[0].sha, "a_sha");
+        assert_eq!(winners[0].name, "Bob");
+    }
+
+    #[test]
+    fn dedup_commits_commits_without_patch_id_are_unique_by_sha() {
+        // Two commits with no patch-id mapping (e.g. empty diffs or
+        // merge commits) must NOT collapse together even though they
+        // share author/timestamp.
+        let commits = vec![
+            raw("merge1", 1000, "Alice", "a@example.com"),
+            raw("merge2", 1000, "Alice", "a@example.com"),
+        ];
+        let patch_ids: HashMap<String, String> = HashMap::new();
+        let (winners, dups) = dedup_commits(commits, &patch_ids);
+        assert_eq!(dups, 0);
+        assert_eq!(winners.len(), 2);
+    }
+
+    #[test]
+    fn dedup_commits_mixes_patch_id_and_unmapped_commits() {
+        // A patch-id group of 3, plus an unrelated solo commit, plus an
+        // unmapped (no patch-id) commit. Result: 1 winner from the
+        // group + the solo + the unmapped = 3 winners, 2 duplicates.
+        let commits = vec![
+            raw("x1", 3000, "Alice", "a@example.com"),
+            raw("x2", 1000, "Bob", "b@example.com"),
+            raw("x3", 2000, "Cara", "c@example.com"),
+            raw("solo", 1500, "Dan", "d@example.com"),
+            raw("nopatch", 2500, "Eve", "e@example.com"),
+        ];
+        let mut patch_ids = HashMap::new();
+        patch_ids.insert("x1".to_string(), "pid1".to_string());
+        patch_ids.insert("x2".to_string(), "pid1".to_string());
+        patch_ids.insert("x3".to_string(), "pid1".to_string());
+        patch_ids.insert("solo".to_string(), "pid2".to_string());
+        // `nopatch` intentionally not in the map.
+
+        let (mut winners, dups) = dedup_commits(commits, &patch_ids);
+        winners.sort_by(|a, b| a.sha.cmp(&b.sha));
+
+        assert_eq!(dups, 2);
+        assert_eq!(winners.len(), 3);
+
+        let shas: Vec<&str> = winners.iter().map(|c| c.sha.as_str()).collect();
+        assert!(
+            shas.contains(&"x2"),
+            "earliest (1000) of pid1 group should win, got {shas:?}"
+        );
+        assert!(shas.contains(&"solo"));
+        assert!(shas.contains(&"nopatch"));
+    }
+
+    fn fixed_range() -> TimeRange {
+        TimeRange {
+            start_ts: Some(100),
+            end_ts: 200,
+            start_label: String::from("start"),
+            end_label: String::from("end"),
+            end_is_latest: false,
+        }
+    }
+
+    const STATS_LOG: &str =
+        "log --pretty=format:%H%x09%ct%x09%an%x09%ae --since=@100 --until=@200 HEAD";
+
+    #[test]
+    fn collect_raw_commits_parses_git_log() {
+        let git = ScriptedRunner::new().with(
+            STATS_LOG,
+            "aaa\t150\tAlice\ta@example.com\n\nbbb\t120\tBob\tb@example.com",
+        );
+        let commits = collect_raw_commits(&git, &StatsOptions::default(), &fixed_range()).unwrap();
+        assert_eq!(
+            commits,
+            vec![
+                raw("aaa", 150, "Alice", "a@example.com"),
+                raw("bbb", 120, "Bob", "b@example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_raw_commits_rejects_malformed_lines() {
+        let git = ScriptedRunner::new().with(STATS_LOG, "not a log line");
+        let err = collect_raw_commits(&git, &StatsOptions::default(), &fixed_range()).unwrap_err();
+        assert_eq!(err.exit_code(), crate::error::EXIT_PARSE);
+    }
+
+    #[test]
+    fn compute_patch_ids_maps_shas_to_patch_ids() {
+        let git = ScriptedRunner::new().with(
+            "diff-tree --stdin -p | patch-id --stable",
+            "pid1 aaa\npid1 bbb",
+        );
+        let patch_ids = compute_patch_ids(&git, &["aaa", "bbb"]).unwrap();
+        assert_eq!(patch_ids.get("aaa").map(String::as_str), Some("pid1"));
+        assert_eq!(patch_ids.get("bbb").map(String::as_str), Some("pid1"));
+    }
+}
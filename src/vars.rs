@@ -1,11 +1,59 @@
 /// Environment variable for the branch name prefix used by `new` and `worktree add`.
 pub const LOKI_NEW_PREFIX: &str = "LOKI_NEW_PREFIX";
 
+/// Environment variable for the base ref used by `new`.
+pub const LOKI_NEW_BASE: &str = "LOKI_NEW_BASE";
+
 /// Environment variable for the base ref used by `worktree add`.
 pub const LOKI_WORKTREE_BASE: &str = "LOKI_WORKTREE_BASE";
 
 /// Environment variable for the rebase target branch.
 pub const LOKI_REBASE_TARGET: &str = "LOKI_REBASE_TARGET";
 
+/// Environment variable for the trunk branch name.
+pub const LOKI_TRUNK: &str = "LOKI_TRUNK";
+
+/// Environment variable for the remote loki fetches from and pushes to.
+pub const LOKI_REMOTE: &str = "LOKI_REMOTE";
+
+/// Environment variable that turns on offline mode (`true`).
+pub const LOKI_OFFLINE: &str = "LOKI_OFFLINE";
+
+/// Environment variable overriding the global config file location.
+pub const LOKI_CONFIG: &str = "LOKI_CONFIG";
+
+/// Environment variable set by the `lk shell-init` wrapper to the file `lk`
+/// writes a directory to change into.
+pub const LOKI_CD_FILE: &str = "LOKI_CD_FILE";
+
+/// Environment variable the completion scripts from `lk completions` set to
+/// ask `lk` for candidates instead of running a command.
+pub const LOKI_COMPLETE: &str = "LOKI_COMPLETE";
+
 /// Git config override that disables all hooks.
 pub const NO_HOOKS: &str = "core.hooksPath=/dev/null";
+
+/// Hook environment: the hook being run, e.g. `post-worktree-add`.
+pub const LOKI_HOOK: &str = "LOKI_HOOK";
+
+/// Hook environment: the lk command, e.g. `worktree-add`.
+pub const LOKI_COMMAND: &str = "LOKI_COMMAND";
+
+/// Hook environment: the remote the command fetched from or pushed to. Not
+/// [`LOKI_REMOTE`], so `lk` commands a hook runs keep the configured remote.
+pub const LOKI_HOOK_REMOTE: &str = "LOKI_HOOK_REMOTE";
+
+/// Hook environment: the branch the command worked on.
+pub const LOKI_BRANCH: &str = "LOKI_BRANCH";
+
+/// Hook environment: the upstream a branch was pushed to, e.g. `origin/fix`.
+pub const LOKI_UPSTREAM: &str = "LOKI_UPSTREAM";
+
+/// Hook environment: the worktree that was created or removed.
+pub const LOKI_WORKTREE_PATH: &str = "LOKI_WORKTREE_PATH";
+
+/// Hook environment: the ref a worktree was created from or a rebase targeted.
+pub const LOKI_BASE_REF: &str = "LOKI_BASE_REF";
+
+/// Hook environment: local branches the command deleted, one per line.
+pub const LOKI_DELETED_BRANCHES: &str = "LOKI_DELETED_BRANCHES";
@@ -1,10 +1,15 @@
-use std::io::IsTerminal;
+//! Sibling worktrees named `<repo>_<name>`, as managed by `lk worktree`.
+
 use std::path::{Path, PathBuf};
 
-use colored::Colorize;
+use serde::Serialize;
 
-use crate::git::{git_command_iter, git_command_lines, git_command_status_quiet};
-use crate::vars::LOKI_NEW_PREFIX;
+use crate::branch::{self, Template};
+use crate::config::{Setting, BRANCH_PREFIX, WORKTREE_BASE};
+use crate::error::{Error, Result};
+use crate::git::GitRunner;
+use crate::pending::{self, Outcome, PendingPush};
+use crate::repo::Repo;
 
 // ---------------------------------------------------------------------------
 // Helpers
@@ -20,67 +25,84 @@ pub fn infer_worktree_name(dir_name: &str) -> &str {
 }
 
 /// Returns the main (first) worktree path via `git worktree list --porcelain`.
-fn resolve_main_worktree() -> Result<String, String> {
-    git_command_iter("list worktrees", vec!["worktree", "list", "--porcelain"])?
+fn resolve_main_worktree(git: &dyn GitRunner) -> Result<String> {
+    git.lines("list worktrees", &["worktree", "list", "--porcelain"])?
+        .into_iter()
         .find_map(|line| line.strip_prefix("worktree ").map(|s| s.to_string()))
-        .ok_or_else(|| String::from("Could not determine main worktree from git worktree list"))
+        .ok_or_else(|| Error::parse("Could not determine main worktree from git worktree list"))
 }
 
 /// Builds a sibling worktree path: `<parent>/<repo_name>_<name>`.
-fn worktree_path(repo_root: &str, name: &str) -> Result<PathBuf, String> {
+fn worktree_path(repo_root: &str, name: &str) -> Result<PathBuf> {
     let root = Path::new(repo_root);
     let repo_name = root
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
-        .ok_or_else(|| format!("Could not determine repo name from path: {repo_root}"))?;
-    let parent = root
-        .parent()
-        .ok_or_else(|| format!("Could not determine parent directory of: {repo_root}"))?;
+        .ok_or_else(|| {
+            Error::precondition(format!(
+                "Could not determine repo name from path: {repo_root}"
+            ))
+        })?;
+    let parent = root.parent().ok_or_else(|| {
+        Error::precondition(format!(
+            "Could not determine parent directory of: {repo_root}"
+        ))
+    })?;
     Ok(parent.join(format!("{repo_name}_{name}")))
 }
 
-/// Finds a worktree whose directory ends with `_<name>` or equals `<name>`.
-fn resolve_worktree_by_name(name: &str) -> Result<String, String> {
-    let suffix = format!("_{name}");
+/// Finds a worktree by name: a directory ending with `_<name>` or equal to
+/// `<name>` (as typed or slugified), else a worktree whose branch is `name`
+/// or what the `branch.template` makes of it.
+fn resolve_worktree_by_name(repo: &Repo, name: &str) -> Result<String> {
+    let entries = list_worktree_entries(repo.git())?;
+    let dir_of = |entry: &WorktreeEntry| {
+        Path::new(&entry.path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
 
-    for line in git_command_iter("list worktrees", vec!["worktree", "list", "--porcelain"])? {
-        if let Some(path) = line.strip_prefix("worktree ") {
-            let dir = Path::new(path)
-                .file_name()
-                .map(|n| n.to_string_lossy().to_string())
-                .unwrap_or_default();
-            if dir.ends_with(&suffix) || dir == name {
-                return Ok(path.to_string());
-            }
+    let slug = branch::slugify(name, usize::MAX);
+    for candidate in [name, slug.as_str()] {
+        let suffix = format!("_{candidate}");
+        if let Some(entry) = entries.iter().find(|entry| {
+            let dir = dir_of(entry);
+            dir.ends_with(&suffix) || dir == candidate
+        }) {
+            return Ok(entry.path.clone());
         }
     }
 
-    Err(format!("No worktree found matching '{name}'"))
+    let template = Template::new(repo.config())?;
+    let prefix = repo.config().resolve_str(BRANCH_PREFIX, None);
+    let matcher = template.matcher(&template.fields(repo, name)?, prefix.as_deref())?;
+    let by_branch = entries.iter().find(|entry| {
+        entry
+            .branch
+            .as_deref()
+            .is_some_and(|branch| branch == name || matcher.is_match(branch))
+    });
+    if let Some(entry) = by_branch {
+        return Ok(entry.path.clone());
+    }
+
+    Err(Error::precondition(format!(
+        "No worktree found matching '{name}'"
+    )))
 }
 
 /// Normalizes path separators to forward slashes for cross-platform comparison.
-fn normalize_path(path: &str) -> String {
+pub fn normalize_path(path: &str) -> String {
     path.replace('\\', "/")
 }
 
-/// Outputs `cd <path>` to stdout. When stdout is a terminal (not piped),
-/// prints a platform-appropriate tip for piping.
-fn emit_cd(path: &str) {
-    println!("cd {path}");
-    if std::io::stdout().is_terminal() {
-        let hint = if cfg!(windows) { "| iex" } else { "through eval" };
-        eprintln!(
-            "\n{}",
-            format!("Tip: pipe this command {hint} to switch automatically.").dimmed()
-        );
-    }
-}
-
-/// Checks if a ref matches an existing remote branch on origin.
+/// Checks if a ref matches an existing remote branch on `remote`.
 /// Returns the full remote ref (e.g. `origin/branch-name`) if found.
-fn find_remote_branch(name: &str) -> Option<String> {
-    // Check common forms: bare name, origin/name, or full ref
-    let candidates = if name.starts_with("origin/") {
+fn find_remote_branch(git: &dyn GitRunner, name: &str, remote: &str) -> Option<String> {
+    // Check common forms: bare name, <remote>/name, or full ref
+    let remote_prefix = format!("{remote}/");
+    let candidates = if name.starts_with(&remote_prefix) {
         vec![name.to_string()]
     } else if name.starts_with("refs/") {
         vec![name.strip_prefix("refs/heads/").unwrap_or(name).to_string()]
@@ -89,27 +111,32 @@ fn find_remote_branch(name: &str) -> Option<String> {
     };
 
     for candidate in &candidates {
-        let lines = git_command_lines(
-            "ls-remote",
-            vec!["ls-remote", "--heads", "origin", candidate.as_str()],
-        )
-        .unwrap_or_default();
+        let lines = git
+            .lines(
+                "ls-remote",
+                &["ls-remote", "--heads", remote, candidate.as_str()],
+            )
+            .unwrap_or_default();
         if !lines.is_empty() {
-            return Some(format!("origin/{candidate}"));
+            return Some(format!("{remote_prefix}{candidate}"));
         }
     }
     None
 }
 
-/// A parsed worktree entry from porcelain output.
-struct WorktreeEntry {
-    path: String,
-    name: String,
-    branch: Option<String>,
+/// A worktree as reported by `git worktree list --porcelain`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WorktreeEntry {
+    pub path: String,
+    /// The name inferred from the directory (see [`infer_worktree_name`]).
+    pub name: String,
+    /// The checked out branch, unless HEAD is detached.
+    pub branch: Option<String>,
 }
 
 impl WorktreeEntry {
-    fn display_label(&self) -> String {
+    /// `name [branch]`, or just the name when HEAD is detached.
+    pub fn display_label(&self) -> String {
         match &self.branch {
             Some(b) => format!("{} [{b}]", self.name),
             None => self.name.clone(),
@@ -118,7 +145,7 @@ impl WorktreeEntry {
 }
 
 /// Parses `git worktree list --porcelain` into structured entries.
-fn list_worktree_entries() -> Result<Vec<WorktreeEntry>, String> {
+fn list_worktree_entries(git: &dyn GitRunner) -> Result<Vec<WorktreeEntry>> {
     let mut entries = Vec::new();
     let mut current_path: Option<String> = None;
     let mut current_branch: Option<String> = None;
@@ -136,7 +163,7 @@ fn list_worktree_entries() -> Result<Vec<WorktreeEntry>, String> {
         });
     };
 
-    for line in git_command_iter("worktree list", vec!["worktree", "list", "--porcelain"])? {
+    for line in git.lines("worktree list", &["worktree", "list", "--porcelain"])? {
         if let Some(path) = line.strip_prefix("worktree ") {
             current_path = Some(path.to_string());
         } else if let Some(branch) = line.strip_prefix("branch refs/heads/") {
@@ -159,128 +186,229 @@ fn list_worktree_entries() -> Result<Vec<WorktreeEntry>, String> {
 // Commands
 // ---------------------------------------------------------------------------
 
+/// Options for [`add`].
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    /// Worktree name, slugified and also used for the new branch (after
+    /// the prefix).
+    pub name: String,
+    /// Ref to create the worktree from. Defaults to the repo's
+    /// `worktree.base` setting.
+    pub base: Option<String>,
+    /// Prefix for the new branch name. Defaults to the repo's
+    /// `branch.prefix` setting.
+    pub prefix: Option<String>,
+}
+
+/// A worktree created by [`add`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WorktreeInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub branch: String,
+    /// The ref the worktree was created from.
+    pub base: String,
+    /// The base was an existing remote branch, so it was checked out
+    /// instead of creating a new branch.
+    pub existing_branch: bool,
+    /// The branch prefix that was applied, with where it was configured.
+    pub prefix: Option<Setting>,
+    /// The remote was offline, so the push waits for `lk push --pending`.
+    pub queued: bool,
+}
+
+/// A worktree removed by [`remove`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RemovedWorktree {
+    pub path: PathBuf,
+    /// The main worktree, which is where the caller should go next.
+    pub main: PathBuf,
+    /// The branch that was checked out in the worktree, if any.
+    pub branch: Option<String>,
+    /// Whether that branch was deleted.
+    pub branch_deleted: bool,
+}
+
 /// Creates a worktree at `<parent>/<repo>_<name>`, then creates and pushes a
-/// branch with optional prefix. If the base ref is an existing remote branch,
-/// checks it out directly instead. Outputs `cd <path>` to stdout for piping.
-pub fn worktree_add(name: &[String], base: &str, prefix: Option<&str>) -> Result<(), String> {
-    if name.is_empty() {
-        return Err(String::from("name cannot be empty."));
+/// branch named by the `branch.template`, with optional prefix. If the base
+/// ref is an existing remote branch, checks it out directly instead. When
+/// offline the push is queued instead (see [`crate::pending`]).
+pub fn add(repo: &Repo, options: &AddOptions) -> Result<WorktreeInfo> {
+    if options.name.is_empty() {
+        return Err(Error::precondition("name cannot be empty."));
     }
 
-    let mut name = name.join("-");
-    let main_root = resolve_main_worktree()?;
+    let git = repo.git();
+    let remotes = repo.remotes();
+    let config = repo.config();
+    let base = config
+        .resolve_str(WORKTREE_BASE, options.base.as_deref())
+        .unwrap_or_else(|| format!("{}/{}", remotes.fetch_remote(), config.trunk()));
+
+    let name = branch::slug(config, &options.name)?;
+    let main_root = resolve_main_worktree(git)?;
     let wt_path = worktree_path(&main_root, &name)?;
     let wt_path_str = wt_path.to_string_lossy();
 
     if wt_path.exists() {
-        return Err(format!("Worktree path already exists: {wt_path_str}"));
+        return Err(Error::precondition(format!(
+            "Worktree path already exists: {wt_path_str}"
+        )));
     }
 
     // Check if the base is an existing remote branch to check out directly
-    if let Some(remote_ref) = find_remote_branch(base) {
-        eprintln!(
-            "Found existing branch {} — checking out into worktree",
-            base.cyan()
-        );
-        git_command_status_quiet("fetch", vec!["fetch", "origin"])?;
-        git_command_status_quiet(
+    let remote = remotes.fetch_remote();
+    let mut online = !config.offline();
+    let existing = online
+        .then(|| find_remote_branch(git, &base, remote))
+        .flatten();
+    if let Some(remote_ref) = existing {
+        git.status_quiet("fetch", &["fetch", remote])?;
+        git.status_quiet(
             "worktree add",
-            vec![
+            &[
                 "worktree",
                 "add",
                 "--track",
                 "-b",
-                base,
+                base.as_str(),
                 wt_path_str.as_ref(),
                 remote_ref.as_str(),
             ],
         )?;
 
-        eprintln!("\n{}", "Worktree ready!".green().bold());
-        emit_cd(&wt_path_str);
-        return Ok(());
+        return Ok(WorktreeInfo {
+            name,
+            path: wt_path.clone(),
+            branch: base,
+            base: remote_ref,
+            existing_branch: true,
+            prefix: None,
+            queued: false,
+        });
     }
 
-    // New branch flow — fetch first so the base ref is up-to-date
-    eprintln!("Fetching latest from origin…");
-    git_command_status_quiet("fetch", vec!["fetch", "origin"])?;
+    let prefix = config
+        .resolve(BRANCH_PREFIX, options.prefix.as_deref())
+        .filter(|prefix| prefix.as_str().is_some());
+    let branch = Template::new(config)?.branch(
+        repo,
+        prefix.as_ref().and_then(Setting::as_str),
+        &options.name,
+    )?;
+    branch::validate(repo, &branch)?;
 
-    eprintln!("Creating worktree at {}", wt_path_str.green());
-    git_command_status_quiet(
+    // New branch flow — fetch first so the base ref is up-to-date
+    pending::fetch(repo, remote, &mut online)?;
+    git.status_quiet(
         "worktree add",
-        vec!["worktree", "add", wt_path_str.as_ref(), base],
+        &["worktree", "add", wt_path_str.as_ref(), base.as_str()],
     )?;
 
-    std::env::set_current_dir(&wt_path)
-        .map_err(|e| format!("Failed to enter worktree directory: {e}"))?;
-
-    if let Some(prefix) = prefix {
-        eprintln!("Using branch prefix `{prefix}` (set via --prefix or {LOKI_NEW_PREFIX}).");
-        name = format!("{prefix}{name}");
-    }
-
-    git_command_status_quiet("create branch", vec!["switch", "--create", name.as_str()])?;
-    git_command_status_quiet(
-        "push to origin",
-        vec!["push", "--set-upstream", "origin", name.as_str()],
+    // Run inside the new worktree via -C so dry runs don't need it to exist.
+    git.status_quiet(
+        "create branch",
+        &[
+            "-C",
+            wt_path_str.as_ref(),
+            "switch",
+            "--create",
+            branch.as_str(),
+        ],
+    )?;
+    let push_remote = remotes.push_remote(git, &branch);
+    let outcome = pending::push(
+        repo,
+        PendingPush::new(&branch, push_remote, false),
+        online,
+        true,
     )?;
 
-    eprintln!("\n{}", "Worktree ready!".green().bold());
-    emit_cd(&wt_path_str);
-
-    Ok(())
+    Ok(WorktreeInfo {
+        name,
+        path: wt_path.clone(),
+        branch,
+        base,
+        existing_branch: false,
+        prefix,
+        queued: outcome == Outcome::Queued,
+    })
 }
 
-/// Removes a worktree and deletes its local branch. If `name` is empty the
-/// worktree name is inferred from the current directory. Outputs `cd <main>`
-/// to stdout for piping.
-pub fn worktree_remove(name: &[String], force: bool) -> Result<(), String> {
-    let main_root = resolve_main_worktree()?;
-
-    let name = if name.is_empty() {
-        let cwd = std::env::current_dir()
-            .map_err(|e| format!("Failed to get current directory: {e}"))?;
-        let dir_name = cwd
-            .file_name()
-            .map(|n| n.to_string_lossy().to_string())
-            .unwrap_or_default();
-        let inferred = infer_worktree_name(&dir_name).to_string();
-        eprintln!("Inferred worktree name: {}", inferred.cyan());
-        inferred
-    } else {
-        name.join("-")
-    };
-
-    let wt_path = worktree_path(&main_root, &name)?;
+/// Removes the named worktree and deletes its local branch. The process
+/// can't be inside the worktree on every platform, so callers that may be
+/// should move to the main worktree ([`find`] with no name) first.
+pub fn remove(repo: &Repo, name: &str, force: bool) -> Result<RemovedWorktree> {
+    let git = repo.git();
+    let main_root = resolve_main_worktree(git)?;
+    let wt_path = worktree_path(&main_root, name)?;
 
     // Fall back to plain name if the <repo>_<name> path doesn't exist
     let wt_path = if wt_path.exists() {
         wt_path
     } else {
-        let parent = wt_path
-            .parent()
-            .ok_or_else(|| format!("Could not determine parent of: {}", wt_path.to_string_lossy()))?;
-        let fallback = parent.join(&name);
-        if !fallback.exists() {
-            return Err(format!(
-                "Worktree directory not found at {} or {}",
-                wt_path.to_string_lossy(),
-                fallback.to_string_lossy()
-            ));
+        let parent = wt_path.parent().ok_or_else(|| {
+            Error::precondition(format!(
+                "Could not determine parent of: {}",
+                wt_path.to_string_lossy()
+            ))
+        })?;
+        let fallback = parent.join(name);
+        if fallback.exists() {
+            fallback
+        } else {
+            // The name may be a branch, or a name the branch template maps to
+            let found = resolve_worktree_by_name(repo, name).map_err(|_| {
+                Error::precondition(format!(
+                    "Worktree directory not found at {} or {}",
+                    wt_path.to_string_lossy(),
+                    fallback.to_string_lossy()
+                ))
+            })?;
+            PathBuf::from(found)
         }
-        fallback
     };
+    remove_path(repo, &main_root, &wt_path, name, force)
+}
+
+/// Removes a worktree from [`list`] and deletes its local branch, like
+/// [`remove`].
+pub fn remove_entry(repo: &Repo, entry: &WorktreeEntry, force: bool) -> Result<RemovedWorktree> {
+    let main_root = resolve_main_worktree(repo.git())?;
+    remove_path(repo, &main_root, Path::new(&entry.path), &entry.name, force)
+}
+
+/// True when the worktree at `path` has uncommitted changes or untracked
+/// files, which `git worktree remove` refuses to throw away.
+pub fn is_dirty(git: &dyn GitRunner, path: &str) -> Result<bool> {
+    let status = git.stdout(
+        "check worktree status",
+        &["-C", path, "status", "--porcelain"],
+    )?;
+    Ok(status.iter().any(|line| !line.trim().is_empty()))
+}
+
+/// Removes the worktree at `wt_path`; `name` is what the user would pass to
+/// `lk w r` for it.
+fn remove_path(
+    repo: &Repo,
+    main_root: &str,
+    wt_path: &Path,
+    name: &str,
+    force: bool,
+) -> Result<RemovedWorktree> {
+    let git = repo.git();
     let wt_path_str = wt_path.to_string_lossy();
 
     // Don't allow removing the main worktree
-    if normalize_path(&wt_path_str) == normalize_path(&main_root) {
-        return Err(String::from(
+    if normalize_path(&wt_path_str) == normalize_path(main_root) {
+        return Err(Error::precondition(
             "You're in the main repo - only secondary worktrees can be removed.",
         ));
     }
 
     // Look up the actual branch checked out in this worktree before removing
-    let actual_branch = list_worktree_entries()
+    let actual_branch = list(repo)
         .ok()
         .and_then(|entries| {
             let normalized_target = normalize_path(&wt_path_str);
@@ -290,18 +418,6 @@ pub fn worktree_remove(name: &[String], force: bool) -> Result<(), String> {
                 .and_then(|e| e.branch)
         });
 
-    // Move out of the worktree so the OS can delete it
-    if let Ok(cwd) = std::env::current_dir() {
-        if cwd.starts_with(&wt_path) {
-            eprintln!(
-                "Leaving worktree directory, moving to {}",
-                main_root.green()
-            );
-            std::env::set_current_dir(&main_root)
-                .map_err(|e| format!("Failed to change to main worktree: {e}"))?;
-        }
-    }
-
     // Attempt worktree removal — retry with --force on dirty worktree errors
     let mut remove_args = vec!["worktree", "remove"];
     if force {
@@ -309,80 +425,48 @@ pub fn worktree_remove(name: &[String], force: bool) -> Result<(), String> {
     }
     remove_args.push(wt_path_str.as_ref());
 
-    if let Err(err) = git_command_status_quiet("worktree remove", remove_args) {
-        if !force && (err.contains("modified or untracked") || err.contains("contains modified")) {
-            return Err(format!(
+    if let Err(err) = git.status_quiet("worktree remove", &remove_args) {
+        let dirty = err.stderr().is_some_and(|stderr| {
+            stderr.contains("modified or untracked") || stderr.contains("contains modified")
+        });
+        if !force && dirty {
+            return Err(Error::precondition(format!(
                 "Worktree has uncommitted changes. Run with --force to remove anyway:\n  lk w r --force {}",
                 name
-            ));
+            )));
         }
         return Err(err);
     }
-    eprintln!("Removed worktree {}", wt_path_str.red());
 
     // Prune stale worktree refs so branch deletion succeeds
-    let _ = git_command_status_quiet("worktree prune", vec!["worktree", "prune"]);
+    let _ = git.status_quiet("worktree prune", &["worktree", "prune"]);
 
     // Best-effort branch cleanup using the actual checked-out branch
-    if let Some(branch) = actual_branch {
-        match git_command_status_quiet("delete branch", vec!["branch", "-D", branch.as_str()]) {
-            Ok(()) => eprintln!("Deleted branch {}", branch.red()),
-            Err(_) => eprintln!(
-                "Branch {} not found locally (may already be deleted)",
-                branch.yellow()
-            ),
-        }
-    }
-
-    emit_cd(&main_root);
-    Ok(())
+    let branch_deleted = actual_branch.as_ref().is_some_and(|branch| {
+        git.status_quiet("delete branch", &["branch", "-D", branch.as_str()])
+            .is_ok()
+    });
+
+    Ok(RemovedWorktree {
+        path: wt_path.to_path_buf(),
+        main: PathBuf::from(main_root),
+        branch: actual_branch,
+        branch_deleted,
+    })
 }
 
-/// Outputs `cd <path>` for the named worktree, or the main worktree if no
-/// name is given. Designed for `eval` / `Invoke-Expression` piping.
-pub fn worktree_switch(name: &[String]) -> Result<(), String> {
-    let target = if name.is_empty() {
-        resolve_main_worktree()?
-    } else {
-        resolve_worktree_by_name(&name.join("-"))?
+/// Finds the named worktree, or the main worktree if no name is given.
+pub fn find(repo: &Repo, name: Option<&str>) -> Result<PathBuf> {
+    let path = match name {
+        None => resolve_main_worktree(repo.git())?,
+        Some(name) => resolve_worktree_by_name(repo, name)?,
     };
-
-    emit_cd(&target);
-    Ok(())
+    Ok(PathBuf::from(path))
 }
-/// Lists all worktrees, highlighting the current one and showing switch hints.
-pub fn worktree_list() -> Result<(), String> {
-    let cwd = std::env::current_dir()
-        .ok()
-        .map(|p| normalize_path(&p.to_string_lossy()));
-
-    let entries = list_worktree_entries()?;
-
-    for entry in &entries {
-        let is_current = cwd.as_ref().is_some_and(|c| {
-            let normalized = normalize_path(&entry.path);
-            *c == normalized || c.starts_with(&format!("{normalized}/"))
-        });
 
-        let label = entry.display_label();
-        if is_current {
-            println!("{}", format!("* {label}").green().bold());
-        } else {
-            let hint = switch_hint(&entry.name).dimmed();
-            println!("  {label}  {hint}");
-        }
-    }
-
-    Ok(())
-}
-
-/// Returns the platform-appropriate command to switch to a worktree.
-fn switch_hint(name: &str) -> String {
-    if cfg!(windows) {
-        format!("lk w s {name} | iex")
-    } else {
-        format!("eval \"$(lk w s {name})\"")
-    }
+/// Lists all worktrees, main worktree first.
+pub fn list(repo: &Repo) -> Result<Vec<WorktreeEntry>> {
+    list_worktree_entries(repo.git())
 }
 
 // ---------------------------------------------------------------------------
@@ -392,6 +476,9 @@ fn switch_hint(name: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{Config, Value};
+    use crate::git::ScriptedRunner;
+    use crate::repo::{scripted_repo, scripted_repo_with};
 
     #[test]
     fn infer_name_with_underscore() {
@@ -440,4 +527,151 @@ mod tests {
     fn normalize_path_preserves_forward_slashes() {
         assert_eq!(normalize_path("/home/user/repos"), "/home/user/repos");
     }
+
+    #[test]
+    fn add_creates_and_pushes_prefixed_branch() {
+        let git = ScriptedRunner::new().with(
+            "worktree list --porcelain",
+            "worktree /nonexistent/loki/repo\nbranch refs/heads/main",
+        );
+        let options = AddOptions {
+            name: String::from("feature"),
+            prefix: Some(String::from("users/me/")),
+            ..Default::default()
+        };
+        let info = add(&scripted_repo(&git), &options).unwrap();
+
+        let wt = Path::new("/nonexistent/loki").join("repo_feature");
+        assert_eq!(info.path, wt);
+        assert_eq!(info.branch, "users/me/feature");
+        assert!(!info.existing_branch);
+        assert_eq!(
+            info.prefix.map(|prefix| prefix.value),
+            Some(Value::Str(String::from("users/me/")))
+        );
+
+        let wt = wt.to_string_lossy();
+        let calls = git.calls();
+        assert!(calls.contains(&String::from("fetch origin")));
+        assert!(calls.contains(&format!("worktree add {wt} origin/main")));
+        assert!(calls.contains(&format!("-C {wt} switch --create users/me/feature")));
+        assert_eq!(
+            calls.last().unwrap(),
+            "push --set-upstream origin users/me/feature"
+        );
+    }
+
+    #[test]
+    fn add_checks_out_existing_remote_branch() {
+        let git = ScriptedRunner::new()
+            .with(
+                "worktree list --porcelain",
+                "worktree /nonexistent/loki/repo\nbranch refs/heads/main",
+            )
+            .with(
+                "ls-remote --heads origin their-fix",
+                "abc123\trefs/heads/their-fix",
+            );
+        let options = AddOptions {
+            name: String::from("review"),
+            base: Some(String::from("their-fix")),
+            ..Default::default()
+        };
+        let info = add(&scripted_repo(&git), &options).unwrap();
+
+        assert!(info.existing_branch);
+        assert_eq!(info.branch, "their-fix");
+        let wt = info.path.to_string_lossy();
+        assert_eq!(
+            git.calls().last().unwrap(),
+            &format!("worktree add --track -b their-fix {wt} origin/their-fix")
+        );
+    }
+
+    #[test]
+    fn add_slugifies_the_name() {
+        let git = ScriptedRunner::new().with(
+            "worktree list --porcelain",
+            "worktree /nonexistent/loki/repo\nbranch refs/heads/main",
+        );
+        let options = AddOptions {
+            name: String::from("Fix: auth bug #123?"),
+            ..Default::default()
+        };
+        let info = add(&scripted_repo(&git), &options).unwrap();
+
+        assert_eq!(info.name, "fix-auth-bug-123");
+        assert_eq!(info.branch, "fix-auth-bug-123");
+        assert_eq!(
+            info.path,
+            Path::new("/nonexistent/loki").join("repo_fix-auth-bug-123")
+        );
+    }
+
+    #[test]
+    fn add_checks_branch_policy_before_creating_anything() {
+        let git = ScriptedRunner::new().with(
+            "worktree list --porcelain",
+            "worktree /nonexistent/loki/repo\nbranch refs/heads/main",
+        );
+        let mut config = Config::default();
+        config.apply_flag(crate::config::BRANCH_PATTERN, Some("^users/"));
+        let repo = scripted_repo_with(&git, config);
+        let options = AddOptions {
+            name: String::from("feature"),
+            ..Default::default()
+        };
+
+        let err = add(&repo, &options).unwrap_err();
+        assert!(matches!(err, Error::Precondition(_)));
+        let calls = git.calls();
+        assert!(!calls.iter().any(|call| call.starts_with("worktree add")));
+    }
+
+    #[test]
+    fn find_maps_names_through_the_branch_template() {
+        let git = ScriptedRunner::new().with(
+            "worktree list --porcelain",
+            "worktree /repos/app\nbranch refs/heads/main\n\nworktree /repos/app_abc-1-fix-auth\nbranch refs/heads/users/me/ABC-1-fix-auth\n",
+        );
+        let mut config = Config::default();
+        config.apply_flag(crate::config::BRANCH_TEMPLATE, Some("{ticket}-{slug}"));
+        config.apply_flag(BRANCH_PREFIX, Some("users/me/"));
+        let repo = scripted_repo_with(&git, config);
+
+        for name in ["Fix auth", "ABC-1-fix-auth", "users/me/ABC-1-fix-auth"] {
+            assert_eq!(
+                find(&repo, Some(name)).unwrap(),
+                PathBuf::from("/repos/app_abc-1-fix-auth")
+            );
+        }
+        assert!(find(&repo, Some("fix login")).is_err());
+    }
+
+    #[test]
+    fn remove_reports_dirty_worktree() {
+        let root = std::env::temp_dir().join(format!("loki-wt-remove-{}", std::process::id()));
+        let main = root.join("repo");
+        let wt = root.join("repo_feature");
+        std::fs::create_dir_all(&main).unwrap();
+        std::fs::create_dir_all(&wt).unwrap();
+        let (main, wt) = (main.to_string_lossy(), wt.to_string_lossy());
+
+        let porcelain = format!(
+            "worktree {main}\nbranch refs/heads/main\n\nworktree {wt}\nbranch refs/heads/feature"
+        );
+        let git = ScriptedRunner::new()
+            .with("worktree list --porcelain", &porcelain)
+            .fail(
+                &format!("worktree remove {wt}"),
+                128,
+                "fatal: '../repo_feature' contains modified or untracked files, use --force to delete it",
+            );
+        let result = remove(&scripted_repo(&git), "feature", false);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::Precondition(_)));
+        assert!(!git.calls().contains(&String::from("branch -D feature")));
+    }
 }